@@ -0,0 +1,14 @@
+// Compares creating FFT backends from a warm FftPlanCache against planning each one.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dtln_rs::dtln_fft::{default_fft, FftPlanCache};
+
+fn bench_backend_creation(c: &mut Criterion) {
+    let cache = FftPlanCache::new();
+    black_box(cache.fft());
+
+    c.bench_function("fft_uncached", |b| b.iter(|| black_box(default_fft())));
+    c.bench_function("fft_cached", |b| b.iter(|| black_box(cache.fft())));
+}
+
+criterion_group!(benches, bench_backend_creation);
+criterion_main!(benches);
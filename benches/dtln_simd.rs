@@ -0,0 +1,54 @@
+// Compares the SIMD and scalar paths of the per-block DSP over DTLN sized blocks.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dtln_rs::constants::{DTLN_BLOCK_LEN, DTLN_FFT_OUT_SIZE};
+use dtln_rs::dtln_simd::{
+    magnitude, magnitude_scalar, multiply, multiply_scalar, overlap_add, overlap_add_scalar, scale,
+    scale_scalar,
+};
+use num::Complex;
+
+fn bench_overlap_add(c: &mut Criterion) {
+    let block = (0..DTLN_BLOCK_LEN)
+        .map(|_| rand::random::<f32>())
+        .collect::<Vec<f32>>();
+    let mut out = vec![0.0; DTLN_BLOCK_LEN];
+
+    c.bench_function("overlap_add_simd", |b| {
+        b.iter(|| overlap_add(black_box(&mut out), black_box(&block)))
+    });
+    c.bench_function("overlap_add_scalar", |b| {
+        b.iter(|| overlap_add_scalar(black_box(&mut out), black_box(&block)))
+    });
+    c.bench_function("scale_simd", |b| {
+        b.iter(|| scale(black_box(&mut out), black_box(0.25)))
+    });
+    c.bench_function("scale_scalar", |b| {
+        b.iter(|| scale_scalar(black_box(&mut out), black_box(0.25)))
+    });
+}
+
+fn bench_spectrum(c: &mut Criterion) {
+    let spectrum = (0..DTLN_FFT_OUT_SIZE)
+        .map(|_| Complex::new(rand::random::<f32>(), rand::random::<f32>()))
+        .collect::<Vec<Complex<f32>>>();
+    let mask = (0..DTLN_FFT_OUT_SIZE)
+        .map(|_| rand::random::<f32>())
+        .collect::<Vec<f32>>();
+    let mut out = vec![0.0; DTLN_FFT_OUT_SIZE];
+
+    c.bench_function("magnitude_simd", |b| {
+        b.iter(|| magnitude(black_box(&spectrum), black_box(&mut out)))
+    });
+    c.bench_function("magnitude_scalar", |b| {
+        b.iter(|| magnitude_scalar(black_box(&spectrum), black_box(&mut out)))
+    });
+    c.bench_function("multiply_simd", |b| {
+        b.iter(|| multiply(black_box(&mut out), black_box(&mask)))
+    });
+    c.bench_function("multiply_scalar", |b| {
+        b.iter(|| multiply_scalar(black_box(&mut out), black_box(&mask)))
+    });
+}
+
+criterion_group!(benches, bench_overlap_add, bench_spectrum);
+criterion_main!(benches);
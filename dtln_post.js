@@ -1,6 +1,47 @@
 const DTLN_SAMPLE_BLOCK_SIZE = 512;
 const DTLN_SIZEOF_FLOAT32 = 4;
 
+function dtlnLastError() {
+  const errorPtr = Module._dtln_last_error_wasm();
+  const errorEnd = Module.HEAPU8.indexOf(0, errorPtr);
+  return new Error(new TextDecoder().decode(Module.HEAPU8.slice(errorPtr, errorEnd)));
+}
+
+// Denoiser for callers doing their own buffering: processBlock takes and returns exactly
+// blockShift() samples, throwing on anything else. Blocks must be passed in order, and
+// destroy() must be called to release the engine.
+class WasmDenoiser {
+  constructor() {
+    this.handle = Module._dtln_create_wasm();
+    this.shift = Module._dtln_block_shift_wasm(this.handle);
+    // Sample buffers, aligned so that the pointers divide into HEAPF32 indices.
+    this.inputPtr = Module._dtln_alloc_samples_wasm(this.shift);
+    this.outputPtr = Module._dtln_alloc_samples_wasm(this.shift);
+  }
+
+  blockShift() {
+    return this.shift;
+  }
+
+  processBlock(input) {
+    if (input.length !== this.shift) {
+      throw new Error(`Expected a block of exactly ${this.shift} samples, got ${input.length}`);
+    }
+    Module.HEAPF32.set(input, this.inputPtr / DTLN_SIZEOF_FLOAT32);
+    if (!Module._dtln_process_block_wasm(this.handle, this.inputPtr, this.outputPtr, input.length)) {
+      throw dtlnLastError();
+    }
+    const outputStart = this.outputPtr / DTLN_SIZEOF_FLOAT32;
+    return Module.HEAPF32.slice(outputStart, outputStart + this.shift);
+  }
+
+  destroy() {
+    Module._dtln_free_samples_wasm(this.inputPtr, this.shift);
+    Module._dtln_free_samples_wasm(this.outputPtr, this.shift);
+    Module._dtln_destroy_wasm(this.handle);
+  }
+}
+
 // Export interface that matches the node plugin.
 let DtlnPlugin = {
   dtln_create: () => {
@@ -15,6 +56,29 @@ let DtlnPlugin = {
     output.set(Module.HEAPF32.subarray(audioBufferPtr, audioBufferPtr + DTLN_SAMPLE_BLOCK_SIZE));
     return false;
   },
+  // Denoises a complete WAV file (Uint8Array), returning the denoised WAV file. Throws if
+  // the input can't be decoded.
+  denoise_wav_bytes: (input) => {
+    const inputPtr = Module._dtln_alloc_bytes_wasm(input.length);
+    const outputLenPtr = Module._dtln_alloc_bytes_wasm(4);
+    try {
+      Module.HEAPU8.set(input, inputPtr);
+      const outputPtr = Module._dtln_denoise_wav_bytes_wasm(inputPtr, input.length, outputLenPtr);
+      if (outputPtr === 0) {
+        throw dtlnLastError();
+      }
+      // The length's bytes come from dtln_alloc_bytes_wasm, which doesn't align them
+      // for HEAPU32.
+      const outputLen = new DataView(Module.HEAPU8.buffer).getUint32(outputLenPtr, true);
+      const output = Module.HEAPU8.slice(outputPtr, outputPtr + outputLen);
+      Module._dtln_free_bytes_wasm(outputPtr, outputLen);
+      return output;
+    } finally {
+      Module._dtln_free_bytes_wasm(inputPtr, input.length);
+      Module._dtln_free_bytes_wasm(outputLenPtr, 4);
+    }
+  },
+  WasmDenoiser,
 };
 
 if (typeof module !== "undefined") {
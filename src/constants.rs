@@ -1,7 +1,13 @@
+// Sample rate the DTLN models were trained on.
+pub const DTLN_SAMPLE_RATE: u32 = 16000;
+
 // 32 ms @ 16khz per DTLN docs: https://github.com/breizhn/DTLN
 pub const DTLN_BLOCK_LEN: usize = 512;
 
 // 8 ms @ 16khz per DTLN docs.
 pub const DTLN_BLOCK_SHIFT: usize = 128;
 
+// Delay between a sample entering the engine and its denoised counterpart leaving it.
+pub const DTLN_LATENCY: usize = DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT;
+
 pub const DTLN_FFT_OUT_SIZE: usize = DTLN_BLOCK_LEN / 2 + 1;
@@ -0,0 +1,421 @@
+// Self-contained conformance checks, verifying a build of this crate computes what the
+// reference platform does.
+//
+// Each check runs a stage that commonly breaks when porting to a new target (the
+// embedded models, the FFT backend, sample encoding byte order, the TFLite runtime and
+// its delegates) on embedded or seeded input, and compares the result against the
+// expected values below.
+use std::fmt;
+use std::time::Duration;
+
+use num::Complex;
+
+use crate::constants::*;
+use crate::dtln_fft::default_fft;
+use crate::dtln_processor::denoise_buffer;
+use crate::dtln_sample::PcmFormat;
+use crate::dtln_utilities::{generate_test_signal, TestSignal};
+
+// Seed of the white noise the model checks denoise.
+const CONFORMANCE_SEED: u64 = 0x6474_6c6e;
+// Length of the model checks' input.
+const CONFORMANCE_DURATION: Duration = Duration::from_secs(2);
+// Bin and amplitude of the cosine the FFT checks transform.
+const FFT_TEST_BIN: usize = 16;
+const FFT_TEST_AMPLITUDE: f32 = 0.5;
+// Samples encoded by the byte order checks.
+const PCM_TEST_SAMPLES: [f32; 2] = [0.5, -0.25];
+// Length of the segments whose output energy the model checks compare, 250 ms.
+const ENERGY_SEGMENT: usize = DTLN_SAMPLE_RATE as usize / 4;
+const ENERGY_SEGMENTS: usize = 8;
+
+// Known-good values the checks compare against.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Reference {
+    // model_version() of the bundled models.
+    pub model_version: &'static str,
+    // Magnitude of FFT_TEST_BIN for the test cosine, and the most any other bin may have.
+    pub fft_peak: f32,
+    pub fft_leakage: f32,
+    // Largest error of a forward and inverse transform of the model input.
+    pub fft_round_trip_error: f32,
+    // PCM_TEST_SAMPLES encoded as F32Le and I16Le.
+    pub f32_le: [u8; 8],
+    pub i16_le: [u8; 4],
+    // Largest output sample for silent input.
+    pub silence_peak: f32,
+    // Least and most reduction of the white noise's energy, in dB. Beyond the most the
+    // output is muted rather than denoised.
+    pub noise_reduction_db: f32,
+    pub max_noise_reduction_db: f32,
+    // Energy of the denoised white noise per ENERGY_SEGMENT in dBFS, from a float32 CPU
+    // run of the bundled models, and how far a build may stray from it.
+    pub output_energy_db: [f32; ENERGY_SEGMENTS],
+    pub output_energy_tolerance_db: f32,
+}
+
+pub(crate) const REFERENCE: Reference = Reference {
+    model_version: "dtln-quant-21d943205007f395",
+    fft_peak: FFT_TEST_AMPLITUDE * DTLN_BLOCK_LEN as f32 / 2.0,
+    fft_leakage: 1e-3,
+    fft_round_trip_error: 1e-5,
+    f32_le: [0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x80, 0xbe],
+    i16_le: [0x00, 0x40, 0x00, 0xe0],
+    silence_peak: 1e-6,
+    noise_reduction_db: 6.0,
+    max_noise_reduction_db: 60.0,
+    output_energy_db: [
+        -32.75, -43.20, -42.80, -45.20, -38.92, -45.84, -39.14, -41.94,
+    ],
+    output_energy_tolerance_db: 1.0,
+};
+
+// Outcome of one conformance check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceCheck {
+    pub name: &'static str,
+    // What the reference requires and what this build produced, for reporting.
+    pub expected: String,
+    pub actual: String,
+    pub passed: bool,
+}
+
+/**
+ * Result of run_conformance: every check that ran, in order. The build conforms if all
+ * of them passed; Display lists them with the failures' expected and actual values.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConformanceResult {
+    pub checks: Vec<ConformanceCheck>,
+}
+
+impl ConformanceResult {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &ConformanceCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    fn check(&mut self, name: &'static str, expected: String, actual: String, passed: bool) {
+        self.checks.push(ConformanceCheck {
+            name,
+            expected,
+            actual,
+            passed,
+        });
+    }
+}
+
+impl fmt::Display for ConformanceResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.failures().count();
+        match failed {
+            0 => writeln!(f, "conformance passed, {} checks", self.checks.len())?,
+            _ => writeln!(
+                f,
+                "conformance failed, {} of {} checks",
+                failed,
+                self.checks.len()
+            )?,
+        }
+        for check in self.checks.iter() {
+            match check.passed {
+                true => writeln!(f, "  ok   {}: {}", check.name, check.actual)?,
+                false => writeln!(
+                    f,
+                    "  FAIL {}: expected {}, got {}",
+                    check.name, check.expected, check.actual
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Runs the conformance checks against the bundled models, for packagers to verify a
+ * build, e.g. after cross-compiling. Stronger than a smoke test: beyond running at all,
+ * the model checksum, FFT, sample byte order and denoiser output are compared with
+ * known-good values, catching miscompiled FFT or SIMD paths, byte order mistakes, and
+ * TFLite delegates that return wrong results. Takes a few seconds of processing.
+ *
+ * Never fails outright; an engine that can't be created is reported as a failed check.
+ */
+pub fn run_conformance() -> ConformanceResult {
+    run_conformance_with(&REFERENCE)
+}
+
+pub(crate) fn run_conformance_with(reference: &Reference) -> ConformanceResult {
+    let mut result = ConformanceResult::default();
+
+    let model_version = crate::model_version();
+    result.check(
+        "model_checksum",
+        reference.model_version.to_string(),
+        model_version.to_string(),
+        model_version == reference.model_version,
+    );
+
+    check_fft(reference, &mut result);
+    check_pcm(reference, &mut result);
+    check_model(reference, &mut result);
+    result
+}
+
+fn check_fft(reference: &Reference, result: &mut ConformanceResult) {
+    let mut fft = default_fft();
+    let mut spectrum = vec![Complex::new(0.0, 0.0); DTLN_FFT_OUT_SIZE];
+
+    let mut cosine = (0..DTLN_BLOCK_LEN)
+        .map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * (FFT_TEST_BIN * i) as f64;
+            FFT_TEST_AMPLITUDE * (phase / DTLN_BLOCK_LEN as f64).cos() as f32
+        })
+        .collect::<Vec<f32>>();
+    fft.forward(&mut cosine, &mut spectrum);
+    let peak = spectrum[FFT_TEST_BIN].norm();
+    let leakage = spectrum
+        .iter()
+        .enumerate()
+        .filter(|(bin, _)| *bin != FFT_TEST_BIN)
+        .fold(0f32, |max, (_, value)| max.max(value.norm()));
+    let tolerance = reference.fft_peak * 1e-5;
+    result.check(
+        "fft_spectrum",
+        format!(
+            "bin {} at {} +-{}, other bins under {}",
+            FFT_TEST_BIN, reference.fft_peak, tolerance, reference.fft_leakage
+        ),
+        format!(
+            "bin {} at {}, other bins up to {}",
+            FFT_TEST_BIN, peak, leakage
+        ),
+        (peak - reference.fft_peak).abs() <= tolerance && leakage <= reference.fft_leakage,
+    );
+
+    let input = noise_input();
+    let mut error = 0f32;
+    let mut output = vec![0.0; DTLN_BLOCK_LEN];
+    for block in input.chunks_exact(DTLN_BLOCK_LEN) {
+        let mut scratch = block.to_vec();
+        fft.forward(&mut scratch, &mut spectrum);
+        fft.inverse(&mut spectrum, &mut output);
+        for (restored, sample) in output.iter().zip(block) {
+            error = error.max((restored / DTLN_BLOCK_LEN as f32 - sample).abs());
+        }
+    }
+    result.check(
+        "fft_round_trip",
+        format!("error under {}", reference.fft_round_trip_error),
+        format!("error {}", error),
+        error <= reference.fft_round_trip_error,
+    );
+}
+
+fn check_pcm(reference: &Reference, result: &mut ConformanceResult) {
+    for (name, format, expected) in [
+        ("pcm_f32_le", PcmFormat::F32Le, &reference.f32_le[..]),
+        ("pcm_i16_le", PcmFormat::I16Le, &reference.i16_le[..]),
+    ] {
+        let mut bytes = vec![];
+        for sample in PCM_TEST_SAMPLES {
+            format.encode(sample, &mut bytes);
+        }
+        let decoded = bytes
+            .chunks_exact(format.bytes_per_sample())
+            .map(|sample| format.decode(sample))
+            .collect::<Vec<f32>>();
+        let round_trips = decoded
+            .iter()
+            .zip(PCM_TEST_SAMPLES)
+            .all(|(decoded, sample)| (decoded - sample).abs() <= 1.0 / i16::MAX as f32);
+        result.check(
+            name,
+            format!("{:02x?}", expected),
+            format!("{:02x?}", bytes),
+            bytes == expected && round_trips,
+        );
+    }
+}
+
+fn check_model(reference: &Reference, result: &mut ConformanceResult) {
+    let input = noise_input();
+    let silence = vec![0.0; input.len()];
+    let runs = || -> anyhow::Result<_> {
+        Ok((
+            denoise_buffer(&input)?,
+            denoise_buffer(&input)?,
+            denoise_buffer(&silence)?,
+        ))
+    };
+    match runs() {
+        Ok((first, second, silent)) => {
+            check_output(reference, &input, [&first, &second, &silent], result)
+        }
+        Err(e) => result.check("model_run", "denoised output".into(), e.to_string(), false),
+    }
+}
+
+// Checks the denoised white noise, twice, and the denoised silence.
+fn check_output(
+    reference: &Reference,
+    input: &[f32],
+    [first, second, silent]: [&[f32]; 3],
+    result: &mut ConformanceResult,
+) {
+    let finite = first.iter().all(|sample| sample.is_finite());
+    result.check(
+        "model_output",
+        format!("{} finite samples", input.len()),
+        format!(
+            "{} samples, {}",
+            first.len(),
+            if finite { "finite" } else { "not finite" }
+        ),
+        first.len() == input.len() && finite,
+    );
+
+    let (first_hash, second_hash) = (output_hash(first), output_hash(second));
+    result.check(
+        "model_deterministic",
+        format!("{:016x}", first_hash),
+        format!("{:016x}", second_hash),
+        first_hash == second_hash,
+    );
+
+    let peak = silent
+        .iter()
+        .fold(0f32, |max, sample| max.max(sample.abs()));
+    result.check(
+        "model_silence",
+        format!("peak under {}", reference.silence_peak),
+        format!("peak {}", peak),
+        peak <= reference.silence_peak,
+    );
+
+    // After the warm-up, so the model's first frames aren't counted as reduction. Silent
+    // output has no energy, and fails as infinite reduction.
+    let energy = |samples: &[f32]| {
+        samples[DTLN_BLOCK_LEN..]
+            .iter()
+            .map(|s| (s * s) as f64)
+            .sum::<f64>()
+    };
+    let reduction = 10.0 * (energy(input) / energy(first)).log10();
+    result.check(
+        "model_noise_reduction",
+        format!(
+            "{} to {} dB",
+            reference.noise_reduction_db, reference.max_noise_reduction_db
+        ),
+        format!("{:.1} dB", reduction),
+        finite
+            && reduction >= reference.noise_reduction_db as f64
+            && reduction <= reference.max_noise_reduction_db as f64,
+    );
+
+    let energies = first
+        .chunks_exact(ENERGY_SEGMENT)
+        .map(|segment| {
+            let mean = segment.iter().map(|s| (s * s) as f64).sum::<f64>() / segment.len() as f64;
+            (10.0 * mean.log10()) as f32
+        })
+        .collect::<Vec<f32>>();
+    let matches = energies.len() == ENERGY_SEGMENTS
+        && energies
+            .iter()
+            .zip(reference.output_energy_db.iter())
+            .all(|(actual, expected)| {
+                (actual - expected).abs() <= reference.output_energy_tolerance_db
+            });
+    result.check(
+        "model_output_energy",
+        format!(
+            "{:.1?} dB +-{}",
+            reference.output_energy_db, reference.output_energy_tolerance_db
+        ),
+        format!("{:.1?} dB", energies),
+        finite && matches,
+    );
+}
+
+fn noise_input() -> Vec<f32> {
+    generate_test_signal(
+        TestSignal::WhiteNoise {
+            seed: CONFORMANCE_SEED,
+        },
+        CONFORMANCE_DURATION,
+        DTLN_SAMPLE_RATE,
+    )
+}
+
+// FNV-1a of the samples' bits.
+fn output_hash(samples: &[f32]) -> u64 {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_bits().to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conformance() {
+        let result = run_conformance();
+        assert!(result.passed(), "{}", result);
+        assert!(result.checks.len() >= 7);
+
+        // A corrupted reference value fails its check alone.
+        let corrupted = Reference {
+            i16_le: [0x40, 0x00, 0xe0, 0x00],
+            ..REFERENCE
+        };
+        let result = run_conformance_with(&corrupted);
+        assert!(!result.passed());
+        let failures = result
+            .failures()
+            .map(|check| check.name)
+            .collect::<Vec<_>>();
+        assert_eq!(failures, vec!["pcm_i16_le"]);
+        assert!(result.to_string().contains("FAIL pcm_i16_le"));
+
+        let corrupted = Reference {
+            model_version: "dtln-quant-0000000000000000",
+            ..REFERENCE
+        };
+        assert!(!run_conformance_with(&corrupted).passed());
+
+        // Output energies off by more than the tolerance fail.
+        let mut corrupted = REFERENCE;
+        corrupted.output_energy_db[3] += 2.0 * REFERENCE.output_energy_tolerance_db;
+        let result = run_conformance_with(&corrupted);
+        let failures = result
+            .failures()
+            .map(|check| check.name)
+            .collect::<Vec<_>>();
+        assert_eq!(failures, vec!["model_output_energy"]);
+    }
+
+    #[test]
+    fn test_conformance_muted_output() {
+        // A build that outputs silence reduces the noise without bound, and fails.
+        let input = noise_input();
+        let muted = vec![0.0; input.len()];
+        let mut result = ConformanceResult::default();
+        check_output(&REFERENCE, &input, [&muted, &muted, &muted], &mut result);
+        let failures = result
+            .failures()
+            .map(|check| check.name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failures,
+            vec!["model_noise_reduction", "model_output_energy"]
+        );
+    }
+}
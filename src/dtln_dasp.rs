@@ -0,0 +1,162 @@
+// dasp signal adapter for DtlnImmediateProcessor, behind the `dasp` feature.
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use dasp::sample::Duplex;
+use dasp::{Frame, Sample, Signal};
+
+use crate::constants::DTLN_BLOCK_SHIFT;
+use crate::dtln_processor::{DtlnImmediateProcessor, DtlnProcessEngine};
+
+/**
+ * A dasp Signal yielding the denoised frames of `source`. Frames of any dasp sample
+ * format are converted through f32 with dasp's own scaling; multichannel frames are
+ * denoised one channel per engine, as in denoise_interleaved.
+ *
+ * Frames are pulled from the source a block at a time and come out with the
+ * processor's latency, as from denoise, starting with its warm-up silence. Once the
+ * source is exhausted the processor's latency() frames of tail follow, as from flush,
+ * and the signal is exhausted in turn, yielding equilibrium frames from then on.
+ */
+pub struct DenoiseSignal<S: Signal> {
+    source: S,
+    pub processor: DtlnImmediateProcessor,
+    // Denoised frames not yet yielded.
+    output: VecDeque<S::Frame>,
+    // Frames pulled from the source and denoised frames produced, to size the tail.
+    frames_in: usize,
+    frames_out: usize,
+    // Set once the source ran out and the tail was flushed.
+    finished: bool,
+}
+
+impl<S> DenoiseSignal<S>
+where
+    S: Signal,
+    <S::Frame as Frame>::Sample: Duplex<f32>,
+{
+    pub fn new(source: S) -> Result<DenoiseSignal<S>> {
+        let processor = DtlnImmediateProcessor::with_channels(S::Frame::CHANNELS)?;
+        Ok(DenoiseSignal::with_processor(source, processor))
+    }
+
+    // Denoises with a configured processor, which must have S::Frame::CHANNELS channels.
+    pub fn with_processor(source: S, processor: DtlnImmediateProcessor) -> DenoiseSignal<S> {
+        DenoiseSignal {
+            source,
+            processor,
+            output: VecDeque::with_capacity(DTLN_BLOCK_SHIFT),
+            frames_in: 0,
+            frames_out: 0,
+            finished: false,
+        }
+    }
+
+    // The source signal, e.g. to inspect it once this one is exhausted.
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    /**
+     * Pulls a block from the source, or what is left of it, and denoises it. Once the
+     * source is exhausted, runs silence through until every input frame has come out
+     * followed by latency() frames of tail, as flush does for mono input.
+     */
+    fn fill(&mut self) -> Result<()> {
+        let channels = S::Frame::CHANNELS;
+        let mut input = Vec::with_capacity(DTLN_BLOCK_SHIFT * channels);
+        while input.len() < DTLN_BLOCK_SHIFT * channels && !self.source.is_exhausted() {
+            let frame = self.source.next();
+            input.extend(frame.channels().map(|sample| sample.to_sample::<f32>()));
+            self.frames_in += 1;
+        }
+        let mut samples = self.denoise(&input)?;
+
+        if self.source.is_exhausted() {
+            let tail_end = self.frames_in + self.processor.latency();
+            while self.frames_out + samples.len() / channels < tail_end {
+                samples.extend(self.denoise(&vec![0.0; DTLN_BLOCK_SHIFT * channels])?);
+            }
+            samples.truncate((tail_end - self.frames_out) * channels);
+            self.processor.reset();
+            self.finished = true;
+        }
+        self.frames_out += samples.len() / channels;
+        let mut samples = samples.into_iter().map(Sample::from_sample::<f32>);
+        while let Some(frame) = S::Frame::from_samples(&mut samples) {
+            self.output.push_back(frame);
+        }
+        Ok(())
+    }
+
+    fn denoise(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+        match S::Frame::CHANNELS {
+            1 => Ok(self.processor.denoise(input)?.samples),
+            channels => self.processor.denoise_interleaved(input, channels),
+        }
+    }
+}
+
+impl<S> Signal for DenoiseSignal<S>
+where
+    S: Signal,
+    <S::Frame as Frame>::Sample: Duplex<f32>,
+{
+    type Frame = S::Frame;
+
+    fn next(&mut self) -> S::Frame {
+        while self.output.is_empty() && !self.finished {
+            if let Err(e) = self.fill() {
+                dtln_error!("Unable to denoise signal: {}", e);
+                self.finished = true;
+            }
+        }
+        self.output.pop_front().unwrap_or(S::Frame::EQUILIBRIUM)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.finished && self.output.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dasp::signal;
+
+    #[test]
+    fn test_dasp_matches_slices() -> Result<()> {
+        let samples = (0..DTLN_BLOCK_SHIFT * 20 + 50)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+        let mut processor = DtlnImmediateProcessor::new()?;
+        let mut expected = processor.denoise(&samples)?.samples;
+        expected.extend(processor.flush()?);
+
+        let denoised = DenoiseSignal::new(signal::from_iter(samples.clone()))?;
+        let output = denoised.until_exhausted().collect::<Vec<f32>>();
+        assert_eq!(output, expected);
+
+        // Stereo frames go through one engine per channel.
+        let frames = samples
+            .chunks_exact(2)
+            .map(|pair| [pair[0], pair[1]])
+            .collect::<Vec<[f32; 2]>>();
+        let mut interleaved = frames.iter().flatten().copied().collect::<Vec<f32>>();
+        let mut processor = DtlnImmediateProcessor::with_channels(2)?;
+        let tail_end = (frames.len() + processor.latency()) * 2;
+        interleaved.resize(tail_end + DTLN_BLOCK_SHIFT * 2, 0.0);
+        let mut expected = processor.denoise_interleaved(&interleaved, 2)?;
+        expected.truncate(tail_end);
+
+        let mut denoised = DenoiseSignal::new(signal::from_iter(frames))?;
+        let output = denoised
+            .by_ref()
+            .until_exhausted()
+            .flatten()
+            .collect::<Vec<f32>>();
+        assert_eq!(output, expected);
+        assert_eq!(denoised.next(), [0.0; 2]);
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+// Dumps the engine's intermediate arrays, for comparing against the reference Python
+// DTLN implementation block by block.
+//
+// dtln_debug_export writes one .npy file (format 1.0, little endian f32) per array into
+// the output directory, with one row per processed block:
+//
+//   frame.npy       [blocks, DTLN_BLOCK_LEN]     input frame the block was inferred on
+//   magnitude.npy   [blocks, DTLN_FFT_OUT_SIZE]  |FFT| of the frame, model 1's input
+//   phase.npy       [blocks, DTLN_FFT_OUT_SIZE]  FFT phase of the frame
+//   mask.npy        [blocks, DTLN_FFT_OUT_SIZE]  mask applied, after all adjustments
+//   stage2_out.npy  [blocks, DTLN_BLOCK_LEN]     model 2 output frame
+//   overlap.npy     [blocks, DTLN_BLOCK_LEN]     overlap-add buffer after the block
+//   output.npy      [blocks * DTLN_BLOCK_SHIFT]  emitted samples
+//
+// The first DTLN_BLOCK_SHIFT samples of each overlap.npy row are that block's output.
+use std::fs::File;
+use std::io::{Result, Write};
+use std::path::Path;
+
+use crate::constants::*;
+use crate::dtln_engine::DtlnEngine;
+
+// Arrays collected by the engine while tracing, flattened row by row.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DebugTrace {
+    pub frame: Vec<f32>,
+    pub magnitude: Vec<f32>,
+    pub phase: Vec<f32>,
+    pub mask: Vec<f32>,
+    pub stage2_out: Vec<f32>,
+    pub overlap: Vec<f32>,
+}
+
+// One exported file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugArray {
+    pub name: String,
+    pub shape: Vec<usize>,
+    // FNV-1a hash of the whole file, for spotting changes between runs.
+    pub checksum: u64,
+}
+
+/**
+ * Denoises `samples` with a fresh engine and writes the intermediate arrays of every
+ * block to `dir`, in the layout described at the top of this file. Only whole blocks
+ * are processed. Returns what was written, in layout order.
+ */
+pub fn dtln_debug_export(samples: &[f32], dir: &Path) -> Result<Vec<DebugArray>> {
+    let mut engine =
+        DtlnEngine::new().ok_or_else(|| std::io::Error::other("Unable to create engine"))?;
+    engine.trace = Some(DebugTrace::default());
+
+    let blocks = samples.len() / DTLN_BLOCK_SHIFT;
+    let mut output = vec![0.0; blocks * DTLN_BLOCK_SHIFT];
+    engine.denoise(&samples[..output.len()], &mut output);
+    let trace = engine.trace.take().unwrap_or_default();
+
+    std::fs::create_dir_all(dir)?;
+    [
+        ("frame", &trace.frame, DTLN_BLOCK_LEN),
+        ("magnitude", &trace.magnitude, DTLN_FFT_OUT_SIZE),
+        ("phase", &trace.phase, DTLN_FFT_OUT_SIZE),
+        ("mask", &trace.mask, DTLN_FFT_OUT_SIZE),
+        ("stage2_out", &trace.stage2_out, DTLN_BLOCK_LEN),
+        ("overlap", &trace.overlap, DTLN_BLOCK_LEN),
+    ]
+    .into_iter()
+    .map(|(name, data, row)| write_npy(dir, name, data, &[data.len() / row, row]))
+    .chain(std::iter::once(write_npy(
+        dir,
+        "output",
+        &output,
+        &[output.len()],
+    )))
+    .collect()
+}
+
+fn write_npy(dir: &Path, name: &str, data: &[f32], shape: &[usize]) -> Result<DebugArray> {
+    let shape_text = match shape {
+        [len] => format!("({},)", len),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|dim| dim.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape_text
+    );
+    // The magic, version and length fields plus the header are padded to 64 bytes.
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(10 + header.len() + data.len() * 4);
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for value in data {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    File::create(dir.join(format!("{}.npy", name)))?.write_all(&bytes)?;
+
+    Ok(DebugArray {
+        name: name.to_string(),
+        shape: shape.to_vec(),
+        checksum: fnv1a(&bytes),
+    })
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_export() -> Result<()> {
+        // Exactly representable input, so the framing checksum is the same everywhere.
+        let samples = (0..DTLN_BLOCK_SHIFT as i32 * 10)
+            .map(|n| ((n * 7919) % 20000 - 10000) as f32 / 16384.0)
+            .collect::<Vec<f32>>();
+        let dir = std::env::temp_dir().join(format!("dtln_debug_export_{}", std::process::id()));
+
+        let arrays = dtln_debug_export(&samples, &dir)?;
+        let shapes = arrays
+            .iter()
+            .map(|array| (array.name.as_str(), array.shape.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shapes,
+            [
+                ("frame", vec![10, 512]),
+                ("magnitude", vec![10, 257]),
+                ("phase", vec![10, 257]),
+                ("mask", vec![10, 257]),
+                ("stage2_out", vec![10, 512]),
+                ("overlap", vec![10, 512]),
+                ("output", vec![1280]),
+            ]
+        );
+        assert_eq!(arrays[0].checksum, 0xf92b1ce87fb3eba2);
+
+        let frame = std::fs::read(dir.join("frame.npy"))?;
+        assert_eq!(&frame[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([frame[8], frame[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(frame.len(), 10 + header_len + 10 * 512 * 4);
+
+        // The same input exports the same arrays.
+        assert_eq!(dtln_debug_export(&samples, &dir)?, arrays);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+// Compressed audio input, behind the `flac` and `ogg` features.
+//
+// Supported codecs are FLAC (native or in Ogg, `flac` feature) and Ogg Vorbis (`ogg`
+// feature), decoded with symphonia. Opus isn't supported: symphonia has no Opus decoder,
+// so Ogg Opus files are rejected with DtlnError::UnsupportedFormat. WAV is always
+// available through dtln_utilities and needs neither feature.
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::constants::DTLN_SAMPLE_RATE;
+use crate::dtln_dsp::resample_linear;
+use crate::dtln_error::DtlnError;
+use crate::dtln_utilities::read_wav_to_pcm32;
+
+// Audio decoded to what the engine expects: mono at DTLN_SAMPLE_RATE.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedAudio {
+    pub samples: Vec<f32>,
+    // Rate and channel count of the source, before downmixing and resampling.
+    pub source_sample_rate: u32,
+    pub source_channels: usize,
+}
+
+/**
+ * Reads a WAV, FLAC or Ogg file, downmixing to mono and resampling to DTLN_SAMPLE_RATE
+ * with resample_linear. The format is detected from the contents, the extension is only
+ * a hint, except that .wav files always go through the WAV reader. Opus, and codecs
+ * whose feature isn't enabled, fail with DtlnError::UnsupportedFormat.
+ */
+pub fn read_audio_file(input: &str) -> Result<DecodedAudio, DtlnError> {
+    let path = Path::new(input);
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    if extension.is_some_and(|extension| extension.eq_ignore_ascii_case("wav")) {
+        let mut samples = vec![];
+        let info = read_wav_to_pcm32(input, &mut samples)?;
+        return Ok(DecodedAudio {
+            samples: resample_linear(&samples, info.sample_rate, DTLN_SAMPLE_RATE),
+            source_sample_rate: info.sample_rate,
+            source_channels: info.channels as usize,
+        });
+    }
+
+    let file = File::open(path).map_err(|error| DtlnError::from_io(input, &error))?;
+    decode(input, Box::new(file), extension)
+}
+
+/**
+ * Decodes a FLAC or Ogg file held in memory, same as read_audio_file.
+ */
+pub fn decode_audio_bytes(bytes: &[u8]) -> Result<DecodedAudio, DtlnError> {
+    decode("input", Box::new(Cursor::new(bytes.to_vec())), None)
+}
+
+// Shared by the file and in-memory decoders, `input` names the source in errors.
+fn decode(
+    input: &str,
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+) -> Result<DecodedAudio, DtlnError> {
+    let unsupported = |e: SymphoniaError| DtlnError::UnsupportedFormat(format!("{}: {}", input, e));
+
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(source, Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            // Gapless, so the encoder delay and the padding of the last block are
+            // trimmed and the output is as long as the source.
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .map_err(unsupported)?
+        .format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| DtlnError::UnsupportedFormat(format!("{}: no audio track", input)))?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .filter(|rate| *rate > 0)
+        .ok_or_else(|| DtlnError::UnsupportedFormat(format!("{}: unknown sample rate", input)))?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(unsupported)?;
+
+    let mut mono = vec![];
+    let mut channels = track
+        .codec_params
+        .channels
+        .map_or(0, |channels| channels.count());
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(unsupported(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet is skipped, like most players do.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(unsupported(e)),
+        };
+
+        let spec = *decoded.spec();
+        channels = spec.channels.count();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        mono.extend(
+            buffer
+                .samples()
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+
+    Ok(DecodedAudio {
+        samples: resample_linear(&mono, sample_rate, DTLN_SAMPLE_RATE),
+        source_sample_rate: sample_rate,
+        source_channels: channels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn test_decode_flac() {
+        // 4410 frames of a 440 Hz tone at half scale, 22050 Hz stereo.
+        let flac = include_bytes!("../clips/tone_22050_stereo.flac");
+        let decoded = decode_audio_bytes(flac).unwrap();
+        assert_eq!(decoded.source_sample_rate, 22050);
+        assert_eq!(decoded.source_channels, 2);
+        assert_eq!(decoded.samples.len(), 3200);
+        let peak = decoded
+            .samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 0.5).abs() < 0.01, "peak {}", peak);
+
+        let path = std::env::temp_dir().join("dtln_decode_fixture.flac");
+        std::fs::write(&path, flac).unwrap();
+        assert_eq!(read_audio_file(path.to_str().unwrap()).unwrap(), decoded);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn test_decode_vorbis() {
+        // 4410 samples of a 440 Hz tone at half scale, 22050 Hz mono Vorbis.
+        let vorbis = include_bytes!("../clips/tone_22050_mono.ogg");
+        let decoded = decode_audio_bytes(vorbis).unwrap();
+        assert_eq!(decoded.source_sample_rate, 22050);
+        assert_eq!(decoded.source_channels, 1);
+        // The last page's granule position trims the padding of the final block.
+        assert_eq!(decoded.samples.len(), 3200);
+
+        // Still the same tone once resampled to DTLN_SAMPLE_RATE.
+        let error = decoded
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(n, sample)| {
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                let expected = 0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin();
+                (sample - expected).powi(2)
+            })
+            .sum::<f32>()
+            / decoded.samples.len() as f32;
+        assert!(error.sqrt() < 0.02, "rms error {}", error.sqrt());
+    }
+
+    #[test]
+    fn test_decode_unsupported() {
+        // Opus has no decoder, the Ogg stream is recognized but rejected.
+        let opus = include_bytes!("../clips/opus_20ms.opus");
+        assert!(matches!(
+            decode_audio_bytes(opus),
+            Err(DtlnError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            read_audio_file("missing.flac"),
+            Err(DtlnError::NotFound(_))
+        ));
+    }
+}
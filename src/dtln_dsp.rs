@@ -0,0 +1,892 @@
+// Small DSP building blocks used around the DTLN engine.
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+// Taps in the crossover FIR. Odd, so the filter has an integer group delay. With a
+// Hamming window this gives a transition band of roughly 3.3 * 16000 / 1023 ~= 52 Hz.
+pub const CROSSOVER_TAPS: usize = 1023;
+
+/**
+ * Linear-phase crossover built from a windowed-sinc FIR low-pass. The high band is the
+ * delayed input minus the low band, so low + high reconstructs the delayed input exactly.
+ *
+ * The low band is held in a delay line so it can be added back to the high band once
+ * it has been through a processing stage with a fixed latency.
+ */
+pub struct LinearPhaseCrossover {
+    taps: Vec<f32>,
+    // Last CROSSOVER_TAPS inputs, stored twice so the convolution reads a contiguous slice.
+    history: Vec<f32>,
+    position: usize,
+    low_band: VecDeque<f32>,
+    processing_latency: usize,
+    split_hz: f32,
+}
+
+impl LinearPhaseCrossover {
+    /**
+     * Creates a crossover at `split_hz`. `processing_latency` is the latency of whatever
+     * runs on the high band, the low band is delayed by the same amount before
+     * recombination.
+     */
+    pub fn new(split_hz: f32, sample_rate: u32, processing_latency: usize) -> Self {
+        let cutoff = split_hz / sample_rate as f32;
+        let center = (CROSSOVER_TAPS / 2) as f32;
+
+        let mut taps = (0..CROSSOVER_TAPS)
+            .map(|n| {
+                let x = n as f32 - center;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (2.0 * PI * cutoff * x).sin() / (PI * x)
+                };
+                let window =
+                    0.54 - 0.46 * (2.0 * PI * n as f32 / (CROSSOVER_TAPS - 1) as f32).cos();
+                sinc * window
+            })
+            .collect::<Vec<f32>>();
+
+        // Normalize for unity gain at DC.
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+
+        LinearPhaseCrossover {
+            taps,
+            history: vec![0.0; CROSSOVER_TAPS * 2],
+            position: 0,
+            low_band: VecDeque::from(vec![0.0; processing_latency]),
+            processing_latency,
+            split_hz,
+        }
+    }
+
+    pub fn split_hz(&self) -> f32 {
+        self.split_hz
+    }
+
+    // Estimated bytes held, the taps and the two delay lines.
+    pub fn memory_footprint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + (self.taps.capacity() + self.history.capacity() + self.low_band.capacity())
+                * std::mem::size_of::<f32>()
+    }
+
+    // Group delay of the FIR, the latency the crossover adds.
+    pub fn latency() -> usize {
+        CROSSOVER_TAPS / 2
+    }
+
+    /**
+     * Writes the high band of `input` into `high`, keeping the low band for recombine.
+     */
+    pub fn split(&mut self, input: &[f32], high: &mut [f32]) {
+        for (sample, high) in input.iter().zip(high.iter_mut()) {
+            self.history[self.position] = *sample;
+            self.history[self.position + CROSSOVER_TAPS] = *sample;
+            self.position = (self.position + 1) % CROSSOVER_TAPS;
+
+            // Oldest sample first, matching the tap order.
+            let window = &self.history[self.position..self.position + CROSSOVER_TAPS];
+            let low: f32 = window
+                .iter()
+                .zip(self.taps.iter())
+                .map(|(x, tap)| x * tap)
+                .sum();
+            let delayed = window[CROSSOVER_TAPS / 2];
+
+            *high = delayed - low;
+            self.low_band.push_back(low);
+        }
+    }
+
+    /**
+     * Adds the delayed low band back onto the processed high band.
+     */
+    pub fn recombine(&mut self, processed_high: &mut [f32]) {
+        for sample in processed_high.iter_mut() {
+            *sample += self.low_band.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.position = 0;
+        self.low_band.clear();
+        self.low_band.resize(self.processing_latency, 0.0);
+    }
+}
+
+/**
+ * Resamples `input` from `from_rate` to `to_rate` with linear interpolation. Cheap and
+ * good enough for speech around the model's rate, though it doesn't band-limit, so some
+ * aliasing is left when downsampling.
+ */
+pub fn resample_linear(input: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || input.is_empty() || from_rate == 0 {
+        return input.to_vec();
+    }
+
+    let step = from_rate as f64 / to_rate as f64;
+    let output_len = (input.len() as f64 / step).round() as usize;
+    (0..output_len)
+        .map(|n| {
+            let position = n as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let a = input[index.min(input.len() - 1)];
+            let b = input[(index + 1).min(input.len() - 1)];
+            a + (b - a) * fraction
+        })
+        .collect()
+}
+
+/**
+ * Interpolation used by StreamResampler, trading CPU for aliasing. Costs are taps per
+ * output sample when converting between device rates and DTLN_SAMPLE_RATE; the sinc
+ * kernels widen in proportion when downsampling, so 48 kHz to 16 kHz costs three times
+ * as many. Attenuations are of the aliases folded back by a 44.1 kHz to 16 kHz
+ * conversion, measured with test_resampler_quality_aliasing's sweep.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    // 2 taps and no band-limiting, about 3 dB of alias attenuation. See resample_linear.
+    Linear,
+    // 4 tap Catmull-Rom spline. Flatter passband than Linear, but no less aliasing.
+    Cubic,
+    // Kaiser windowed sinc over 8 zero crossings, 16 taps. About 75 dB, flat to 6 kHz.
+    #[default]
+    SincFast,
+    // Kaiser windowed sinc over 32 zero crossings, 64 taps. About 110 dB.
+    SincHigh,
+}
+
+// Kernel table resolution, in entries per zero crossing.
+const SINC_TABLE_STEPS: usize = 512;
+
+impl ResamplerQuality {
+    // Zero crossings on each side of the sinc kernel, Kaiser beta, and the cutoff as a
+    // fraction of the lower Nyquist rate.
+    fn sinc_shape(self) -> Option<(usize, f64, f64)> {
+        match self {
+            ResamplerQuality::Linear | ResamplerQuality::Cubic => None,
+            ResamplerQuality::SincFast => Some((8, 6.0, 0.85)),
+            ResamplerQuality::SincHigh => Some((32, 9.0, 0.92)),
+        }
+    }
+}
+
+/**
+ * Streaming resampler. The read position and the input history are kept between calls,
+ * so a stream resampled in chunks of any size matches resampling it in one call. The
+ * rates may be changed between chunks without a discontinuity. Equal rates pass samples
+ * straight through at every quality.
+ *
+ * The sinc qualities need that many zero crossings of lookahead, so they hold back a
+ * few input samples more than Linear, which matches resample_linear.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamResampler {
+    from_rate: u32,
+    to_rate: u32,
+    quality: ResamplerQuality,
+    // Kernel over the positive zero crossings for the sinc qualities, empty otherwise.
+    sinc_table: Vec<f32>,
+    // Input samples the next output still reads, including those before it.
+    history: Vec<f32>,
+    // Position of the next output in history.
+    position: f64,
+}
+
+impl StreamResampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> StreamResampler {
+        StreamResampler::with_quality(from_rate, to_rate, ResamplerQuality::Linear)
+    }
+
+    pub fn with_quality(
+        from_rate: u32,
+        to_rate: u32,
+        quality: ResamplerQuality,
+    ) -> StreamResampler {
+        let sinc_table = match quality.sinc_shape() {
+            Some((zero_crossings, beta, _)) => {
+                let steps = zero_crossings * SINC_TABLE_STEPS;
+                (0..=steps + 1)
+                    .map(|i| {
+                        let u = i as f64 / SINC_TABLE_STEPS as f64;
+                        (sinc(u) * kaiser(u / zero_crossings as f64, beta)) as f32
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let mut resampler = StreamResampler {
+            from_rate,
+            to_rate,
+            quality,
+            sinc_table,
+            history: Vec::new(),
+            position: 0.0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    pub fn quality(&self) -> ResamplerQuality {
+        self.quality
+    }
+
+    // Estimated bytes held, the sinc table and the input history.
+    pub fn memory_footprint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + (self.sinc_table.capacity() + self.history.capacity()) * std::mem::size_of::<f32>()
+    }
+
+    /**
+     * Changes the rates from the next chunk on. The interpolation state is kept, so the
+     * output continues from the last sample rather than restarting from silence.
+     */
+    pub fn set_rates(&mut self, from_rate: u32, to_rate: u32) {
+        self.from_rate = from_rate;
+        self.to_rate = to_rate;
+        // A narrower cutoff reaches further back than the history kept so far.
+        let missing = self.reach().saturating_sub(self.position as usize);
+        if missing > 0 {
+            self.history.splice(0..0, std::iter::repeat_n(0.0, missing));
+            self.position += missing as f64;
+        }
+    }
+
+    /**
+     * Changes the rates for input that continues at a new rate, `next` being the first
+     * of it. Unlike set_rates, the history isn't read at the new rate: the input held
+     * back so far is first resampled at the old rates into `output`, with `next`
+     * converted to the old rate as its lookahead (held at its last sample where it runs
+     * short). The new rate then reads on from the same point in time, over that history
+     * converted to it, so the output stays on a uniform grid across the switch. `next`
+     * isn't consumed; pass it to process as usual.
+     */
+    pub fn switch_rates(
+        &mut self,
+        from_rate: u32,
+        to_rate: u32,
+        next: &[f32],
+        output: &mut Vec<f32>,
+    ) {
+        if self.from_rate == 0 || self.to_rate == 0 || from_rate == 0 {
+            self.set_rates(from_rate, to_rate);
+            return;
+        }
+
+        // New input samples per old one. The first new sample follows the last old one.
+        let scale = from_rate as f64 / self.from_rate as f64;
+        let end = self.history.len();
+        let last = self.history.last().copied().unwrap_or(0.0);
+        let lookahead = (0..=self.reach())
+            .map(|offset| lerp(next, offset as f64 * scale, last))
+            .collect::<Vec<f32>>();
+        self.history.extend(lookahead);
+        let step = self.from_rate as f64 / self.to_rate as f64;
+        while self.position < end as f64 {
+            let index = self.position as usize;
+            let fraction = self.position - index as f64;
+            output.push(self.interpolate(index, fraction as f32));
+            self.position += step;
+        }
+
+        let overshoot = (self.position - end as f64) * scale;
+        let old = std::mem::take(&mut self.history);
+        self.from_rate = from_rate;
+        self.to_rate = to_rate;
+        let reach = self.reach();
+        self.history = (1..=reach)
+            .rev()
+            .map(|back| lerp(&old, (end as f64 - back as f64 / scale).max(0.0), last))
+            .collect();
+        self.position = reach as f64 + overshoot;
+    }
+
+    // Appends the resampled `input` to `output`.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if input.is_empty() || self.from_rate == 0 || self.to_rate == 0 {
+            return;
+        }
+
+        self.history.extend_from_slice(input);
+        let step = self.from_rate as f64 / self.to_rate as f64;
+        let reach = self.reach();
+        loop {
+            let index = self.position as usize;
+            let fraction = self.position - index as f64;
+            let last = match self.quality {
+                _ if self.from_rate == self.to_rate => index,
+                ResamplerQuality::Linear | ResamplerQuality::Cubic if fraction == 0.0 => index,
+                _ => index + reach,
+            };
+            if last >= self.history.len() {
+                break;
+            }
+            output.push(self.interpolate(index, fraction as f32));
+            self.position += step;
+        }
+
+        let consumed = (self.position as usize)
+            .saturating_sub(reach)
+            .min(self.history.len());
+        self.history.drain(..consumed);
+        self.position -= consumed as f64;
+    }
+
+    pub fn reset(&mut self) {
+        // Starts on the first input, with silence before it.
+        let reach = self.reach();
+        self.history.clear();
+        self.history.resize(reach, 0.0);
+        self.position = reach as f64;
+    }
+
+    // Input samples read on each side of an output.
+    fn reach(&self) -> usize {
+        match self.quality.sinc_shape() {
+            Some((zero_crossings, _, _)) => {
+                (zero_crossings as f64 / self.cutoff()).ceil() as usize + 1
+            }
+            None if self.quality == ResamplerQuality::Cubic => 2,
+            None => 1,
+        }
+    }
+
+    // Sinc cutoff in cycles per input sample, over the Nyquist rate of 0.5.
+    fn cutoff(&self) -> f64 {
+        let (_, _, passband) = self.quality.sinc_shape().unwrap_or((0, 0.0, 1.0));
+        let ratio = match self.from_rate {
+            0 => 1.0,
+            from_rate => (self.to_rate as f64 / from_rate as f64).min(1.0),
+        };
+        passband * ratio
+    }
+
+    fn interpolate(&self, index: usize, fraction: f32) -> f32 {
+        let at = |offset: isize| self.history[(index as isize + offset) as usize];
+        if self.from_rate == self.to_rate {
+            return at(0);
+        }
+        match self.quality {
+            ResamplerQuality::Linear => {
+                let a = at(0);
+                if fraction == 0.0 {
+                    return a;
+                }
+                a + (at(1) - a) * fraction
+            }
+            ResamplerQuality::Cubic => {
+                let (p0, p1, p2, p3) = (at(-1), at(0), at(1), at(2));
+                let t = fraction;
+                p1 + 0.5
+                    * t
+                    * (p2 - p0
+                        + t * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3
+                            + t * (3.0 * (p1 - p2) + p3 - p0)))
+            }
+            ResamplerQuality::SincFast | ResamplerQuality::SincHigh => {
+                let reach = self.reach() as isize;
+                let cutoff = self.cutoff();
+                let limit = (self.sinc_table.len() - 2) as f64;
+                let mut sum = 0.0;
+                let mut weights = 0.0;
+                for offset in 1 - reach..=reach {
+                    // Distance from the output in zero crossings, as a table position.
+                    let u = ((offset as f64 - fraction as f64) * cutoff).abs()
+                        * SINC_TABLE_STEPS as f64;
+                    if u >= limit {
+                        continue;
+                    }
+                    let entry = u as usize;
+                    let blend = (u - entry as f64) as f32;
+                    let weight = self.sinc_table[entry]
+                        + (self.sinc_table[entry + 1] - self.sinc_table[entry]) * blend;
+                    sum += weight * at(offset);
+                    weights += weight;
+                }
+                // Normalised per output, so the DC gain is exactly 1.
+                sum / weights
+            }
+        }
+    }
+}
+
+// Linear interpolation of `samples` at `at`, `fallback` once it runs out.
+fn lerp(samples: &[f32], at: f64, fallback: f32) -> f32 {
+    let index = at as usize;
+    let fraction = (at - index as f64) as f32;
+    let a = samples.get(index).copied().unwrap_or(fallback);
+    let b = samples.get(index + 1).copied().unwrap_or(a);
+    a + (b - a) * fraction
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let x = std::f64::consts::PI * x;
+    x.sin() / x
+}
+
+// Kaiser window at `x` in -1..=1.
+fn kaiser(x: f64, beta: f64) -> f64 {
+    bessel_i0(beta * (1.0 - x * x).max(0.0).sqrt()) / bessel_i0(beta)
+}
+
+// Zeroth order modified Bessel function of the first kind, from its power series.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..32 {
+        term *= half / k as f64;
+        sum += term * term;
+    }
+    sum
+}
+
+/**
+ * Two threshold gate. It opens once the input reaches `open_threshold` and only closes
+ * again when the input drops below `close_threshold`, so input hovering around a single
+ * threshold doesn't make it flicker.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HysteresisGate {
+    open_threshold: f32,
+    close_threshold: f32,
+    open: bool,
+}
+
+impl HysteresisGate {
+    // `close_threshold` is clamped to at most `open_threshold`.
+    pub fn new(open_threshold: f32, close_threshold: f32) -> Self {
+        HysteresisGate {
+            open_threshold,
+            close_threshold: close_threshold.min(open_threshold),
+            open: true,
+        }
+    }
+
+    pub fn thresholds(&self) -> (f32, f32) {
+        (self.open_threshold, self.close_threshold)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Feeds the next input value, returning whether the gate is open.
+    pub fn update(&mut self, value: f32) -> bool {
+        if self.open {
+            self.open = value >= self.close_threshold;
+        } else {
+            self.open = value >= self.open_threshold;
+        }
+        self.open
+    }
+
+    pub fn reset(&mut self) {
+        self.open = true;
+    }
+}
+
+/**
+ * One-pole pre-emphasis, y[n] = x[n] - coeff * x[n - 1], and its exact inverse for
+ * de-emphasis, z[n] = y[n] + coeff * z[n - 1]. Each direction keeps its own state, so
+ * blocks of any size can be run through either. A coefficient of 0 passes audio
+ * through; the inverse is only stable for coefficients below 1.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PreEmphasis {
+    coeff: f32,
+    // Last input to emphasize.
+    previous_input: f32,
+    // Last output of deemphasize.
+    previous_output: f32,
+}
+
+impl PreEmphasis {
+    pub fn new(coeff: f32) -> Self {
+        PreEmphasis {
+            coeff,
+            ..Default::default()
+        }
+    }
+
+    pub fn coeff(&self) -> f32 {
+        self.coeff
+    }
+
+    pub fn emphasize(&mut self, block: &mut [f32]) {
+        for sample in block.iter_mut() {
+            let input = *sample;
+            *sample -= self.coeff * self.previous_input;
+            self.previous_input = input;
+        }
+    }
+
+    pub fn deemphasize(&mut self, block: &mut [f32]) {
+        for sample in block.iter_mut() {
+            *sample += self.coeff * self.previous_output;
+            self.previous_output = *sample;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.previous_input = 0.0;
+        self.previous_output = 0.0;
+    }
+}
+
+/**
+ * Slow automatic gain control for model conditioning. apply scales each input block
+ * towards a target RMS, following the block mean square with a one-pole envelope that
+ * rises at the attack rate and falls at the release rate, and ramps the gain across the
+ * block so it never steps. restore divides the same ramp back out of the output block
+ * `delay` blocks later, which undoes it exactly for audio passed through unchanged.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputAgc {
+    target: f32,
+    // Per block envelope coefficients for a rising and a falling level.
+    attack: f32,
+    release: f32,
+    max_gain: f32,
+    // Mean square envelope, and the gain at the end of the last block.
+    envelope: f32,
+    gain: f32,
+    // Gain ramps applied to the input blocks whose output hasn't been restored yet.
+    ramps: VecDeque<(f32, f32)>,
+    // Ramp the last restored block was divided by.
+    restored: (f32, f32),
+    delay: usize,
+}
+
+impl InputAgc {
+    /**
+     * `target` is the RMS the input is scaled towards, `attack` and `release` the time
+     * constants of the envelope in blocks, and `max_gain` caps the gain both ways, at
+     * most `max_gain` and at least its reciprocal, so silence isn't blown up without
+     * limit.
+     */
+    pub fn new(target: f32, attack: f32, release: f32, max_gain: f32, delay: usize) -> Self {
+        let mut agc = InputAgc {
+            target,
+            attack: (-1.0 / attack.max(f32::MIN_POSITIVE)).exp(),
+            release: (-1.0 / release.max(f32::MIN_POSITIVE)).exp(),
+            max_gain,
+            envelope: 0.0,
+            gain: 1.0,
+            ramps: VecDeque::with_capacity(delay + 1),
+            restored: (1.0, 1.0),
+            delay,
+        };
+        agc.reset();
+        agc
+    }
+
+    // Gain at the end of the last block applied.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    // Scales the next input block, returning the (start, end) gain ramp applied.
+    pub fn apply(&mut self, block: &mut [f32]) -> (f32, f32) {
+        if block.is_empty() {
+            return (self.gain, self.gain);
+        }
+        let mean_square = block.iter().map(|x| x * x).sum::<f32>() / block.len() as f32;
+        let coeff = if mean_square > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = coeff * self.envelope + (1.0 - coeff) * mean_square;
+        let gain = (self.target / self.envelope.sqrt()).clamp(1.0 / self.max_gain, self.max_gain);
+        let ramp = (
+            self.gain,
+            if gain.is_finite() {
+                gain
+            } else {
+                self.max_gain
+            },
+        );
+        self.gain = ramp.1;
+        scale_ramp(block, ramp, false);
+        self.ramps.push_back(ramp);
+        ramp
+    }
+
+    // Divides the next output block by the ramp applied to the input `delay` blocks ago.
+    pub fn restore(&mut self, block: &mut [f32]) {
+        if self.ramps.len() > self.delay {
+            self.restored = self.ramps.pop_front().unwrap_or((1.0, 1.0));
+        }
+        self.invert(block);
+    }
+
+    // Divides `block` by the last ramp restore used, for other output aligned with it.
+    pub fn invert(&self, block: &mut [f32]) {
+        scale_ramp(block, self.restored, true);
+    }
+
+    // Starts over at unity gain, with the envelope at the target.
+    pub fn reset(&mut self) {
+        self.envelope = self.target * self.target;
+        self.gain = 1.0;
+        self.ramps.clear();
+        self.ramps
+            .extend(std::iter::repeat_n((1.0, 1.0), self.delay));
+        self.restored = (1.0, 1.0);
+    }
+}
+
+// Multiplies, or divides, `block` by a gain ramping linearly from `from` to `to`.
+pub(crate) fn scale_ramp(block: &mut [f32], (from, to): (f32, f32), divide: bool) {
+    let len = block.len() as f32;
+    for (i, sample) in block.iter_mut().enumerate() {
+        let gain = from + (to - from) * (i + 1) as f32 / len;
+        if divide {
+            *sample /= gain;
+        } else {
+            *sample *= gain;
+        }
+    }
+}
+
+// Energy floor, so silence gives a finite SNR.
+const SNR_MIN_ENERGY: f32 = 1e-10;
+
+// Per block growth of the noise estimate while the input is above it, about 1 dB/s.
+const SNR_NOISE_RISE: f32 = 1.002;
+
+// Per block decay of the signal level estimate, so short pauses don't drop it.
+const SNR_LEVEL_DECAY: f32 = 0.99;
+
+/**
+ * Rough per-block SNR estimate. The noise floor follows the minimum block energy,
+ * dropping immediately and creeping back up slowly (minimum statistics). The signal
+ * level follows the peak, decaying slowly through pauses. The SNR is their ratio.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SnrEstimator {
+    noise: Option<f32>,
+    level: f32,
+}
+
+impl SnrEstimator {
+    // Feeds the mean energy of the next block, returning the SNR estimate in dB.
+    pub fn update(&mut self, energy: f32) -> f32 {
+        let energy = energy.max(SNR_MIN_ENERGY);
+        let noise = match self.noise {
+            Some(noise) if energy >= noise => noise * SNR_NOISE_RISE,
+            _ => energy,
+        };
+        self.noise = Some(noise);
+        self.level = energy.max(self.level * SNR_LEVEL_DECAY);
+        10.0 * (self.level / noise).log10()
+    }
+
+    pub fn reset(&mut self) {
+        *self = SnrEstimator::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hysteresis_gate() {
+        // Oscillates around 0.5, with the occasional larger excursion.
+        let values = (0..1000)
+            .map(|n| 0.5 + 0.05 * (n as f32 * 1.3).sin() + 0.3 * (n as f32 * 0.01).sin())
+            .collect::<Vec<f32>>();
+        let toggles = |mut gate: HysteresisGate| {
+            let mut open = gate.is_open();
+            values
+                .iter()
+                .filter(|value| {
+                    let changed = gate.update(**value) != open;
+                    open = gate.is_open();
+                    changed
+                })
+                .count()
+        };
+
+        let single = toggles(HysteresisGate::new(0.5, 0.5));
+        let hysteresis = toggles(HysteresisGate::new(0.6, 0.4));
+        assert!(hysteresis > 0);
+        assert!(hysteresis < single, "{} vs {}", hysteresis, single);
+
+        // Thresholds of 0 keep the gate open for any non-negative input.
+        let mut gate = HysteresisGate::new(0.0, 0.0);
+        assert!(values.iter().all(|value| gate.update(*value)));
+    }
+
+    #[test]
+    fn test_stream_resampler() {
+        let input = (0..4000)
+            .map(|i| (i as f32 * 0.01).sin())
+            .collect::<Vec<f32>>();
+
+        // Equal rates pass samples straight through, with nothing held back.
+        let mut output = Vec::new();
+        let mut resampler = StreamResampler::new(16000, 16000);
+        for chunk in input.chunks(77) {
+            resampler.process(chunk, &mut output);
+        }
+        assert_eq!(output, input);
+
+        // Chunking doesn't change the output.
+        let mut whole = Vec::new();
+        StreamResampler::new(16000, 48000).process(&input, &mut whole);
+        let mut chunked = Vec::new();
+        let mut resampler = StreamResampler::new(16000, 48000);
+        for chunk in input.chunks(160) {
+            resampler.process(chunk, &mut chunked);
+        }
+        assert_eq!(whole.len(), chunked.len());
+        for (a, b) in whole.iter().zip(chunked.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        assert!(whole.len().abs_diff(input.len() * 3) <= 3);
+
+        for quality in [ResamplerQuality::Cubic, ResamplerQuality::SincHigh] {
+            let mut output = Vec::new();
+            let mut resampler = StreamResampler::with_quality(16000, 16000, quality);
+            for chunk in input.chunks(77) {
+                resampler.process(chunk, &mut output);
+            }
+            assert_eq!(output, input);
+
+            let mut whole = Vec::new();
+            StreamResampler::with_quality(44100, 16000, quality).process(&input, &mut whole);
+            let mut chunked = Vec::new();
+            let mut resampler = StreamResampler::with_quality(44100, 16000, quality);
+            for chunk in input.chunks(441) {
+                resampler.process(chunk, &mut chunked);
+            }
+            assert_eq!(whole.len(), chunked.len());
+            for (a, b) in whole.iter().zip(chunked.iter()) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_preemphasis_inverse() {
+        let input = (0..4000)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+        let mut emphasis = PreEmphasis::new(0.97);
+        let mut output = input.clone();
+        // Blocks of different sizes, each direction carrying its state over.
+        for chunk in output.chunks_mut(333) {
+            emphasis.emphasize(chunk);
+        }
+        assert!((output[1] - (input[1] - 0.97 * input[0])).abs() < 1e-6);
+        for chunk in output.chunks_mut(128) {
+            emphasis.deemphasize(chunk);
+        }
+        for (a, b) in input.iter().zip(output.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        let mut passthrough = input.clone();
+        PreEmphasis::new(0.0).emphasize(&mut passthrough);
+        assert_eq!(passthrough, input);
+    }
+
+    #[test]
+    fn test_input_agc() {
+        let rms =
+            |block: &[f32]| (block.iter().map(|x| x * x).sum::<f32>() / block.len() as f32).sqrt();
+        for level in [0.01, 0.5] {
+            let input = (0..128 * 400)
+                .map(|_| (rand::random::<f32>() * 2.0 - 1.0) * level)
+                .collect::<Vec<f32>>();
+            let mut agc = InputAgc::new(0.1, 5.0, 20.0, 100.0, 3);
+            // Stands in for an engine passing audio through with 3 blocks of latency.
+            let mut delay = VecDeque::from(vec![vec![0.0; 128]; 3]);
+            let (mut scaled, mut output) = (vec![], vec![]);
+            for block in input.chunks(128) {
+                let mut block = block.to_vec();
+                agc.apply(&mut block);
+                scaled.extend_from_slice(&block);
+                delay.push_back(block);
+                let mut out = delay.pop_front().unwrap();
+                agc.restore(&mut out);
+                output.extend(out);
+            }
+            // Settled at the target, whatever the level.
+            let settled = rms(&scaled[128 * 300..]);
+            assert!((settled - 0.1).abs() < 0.01, "{} at {}", settled, level);
+
+            // The restored output is the delayed input.
+            for (a, b) in output[128 * 3..].iter().zip(input.iter()) {
+                assert!((a - b).abs() < 1e-5, "{} vs {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resampler_quality_aliasing() {
+        // Linear sweep from 0 Hz to the 44.1 kHz Nyquist rate over two seconds.
+        let (from_rate, to_rate) = (44100, 16000);
+        let len = 2 * from_rate as usize;
+        let end_hz = from_rate as f64 / 2.0;
+        let sweep = (0..len)
+            .map(|i| {
+                let t = i as f64 / from_rate as f64;
+                let phase = std::f64::consts::PI * end_hz * t * t / 2.0;
+                (0.5 * phase.sin()) as f32
+            })
+            .collect::<Vec<f32>>();
+
+        // Output energy while the sweep is above `from_hz` and below `to_hz`. Anything
+        // above the 8 kHz output Nyquist rate can only be there as an alias.
+        let energy = |output: &[f32], from_hz: f64, to_hz: f64| {
+            let at = |hz: f64| ((hz / end_hz * 2.0 * to_rate as f64) as usize).min(output.len());
+            output[at(from_hz)..at(to_hz)]
+                .iter()
+                .map(|sample| sample * sample)
+                .sum::<f32>()
+        };
+        // Mean energy per Hz of the aliases and of 5.5 to 6 kHz, relative to 0.1 to 3 kHz.
+        let levels = |quality| {
+            let mut output = Vec::new();
+            let mut resampler = StreamResampler::with_quality(from_rate, to_rate, quality);
+            for chunk in sweep.chunks(441) {
+                resampler.process(chunk, &mut output);
+            }
+            let passband = energy(&output, 100.0, 3000.0) / 2900.0;
+            let aliases = energy(&output, 9000.0, end_hz) / (end_hz as f32 - 9000.0);
+            let upper = energy(&output, 5500.0, 6000.0) / 500.0;
+            (
+                10.0 * (aliases / passband).log10(),
+                10.0 * (upper / passband).log10(),
+            )
+        };
+
+        let (linear, linear_upper) = levels(ResamplerQuality::Linear);
+        let (_, cubic_upper) = levels(ResamplerQuality::Cubic);
+        let (fast, fast_upper) = levels(ResamplerQuality::SincFast);
+        let (high, high_upper) = levels(ResamplerQuality::SincHigh);
+        assert!(fast < linear - 60.0, "{} vs {}", fast, linear);
+        assert!(high < fast - 20.0, "{} vs {}", high, fast);
+        // Cubic doesn't band-limit either, it buys a flatter passband instead.
+        assert!(
+            cubic_upper > linear_upper,
+            "{} vs {}",
+            cubic_upper,
+            linear_upper
+        );
+        // The sinc passbands reach well past the upper speech formants.
+        assert!(fast_upper.abs() < 1.0 && high_upper.abs() < 1.0);
+    }
+}
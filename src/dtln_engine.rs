@@ -1,14 +1,31 @@
 // dtln_engine.rs
+use std::mem::size_of;
 use std::ptr;
 use std::slice;
 
 use anyhow::Result;
 use num::Complex;
-use realfft::RealFftPlanner;
 
 use crate::constants::*;
+use crate::dtln_dsp::{scale_ramp, HysteresisGate, InputAgc, PreEmphasis, SnrEstimator};
+use crate::dtln_fft::{default_fft, Fft, FftPlanCache};
+use crate::dtln_model_info::EngineInfo;
+use crate::dtln_scratch::{Scratch, ScratchBuffers, ScratchViews};
+use crate::dtln_simd::{magnitude, multiply, overlap_add, scale};
+use crate::dtln_stats::MaskStats;
 use crate::tflite::*;
 
+/**
+ * One denoising stream. Every engine owns its interpreters, tensors, recurrent state,
+ * FFT scratch and working memory, so engines never share mutable data and streams can't
+ * leak into each other however their blocks are interleaved. Only read-only data is
+ * shared: the embedded model bytes and the immutable FFT plans. Supplied ScratchBuffers
+ * are exclusive borrows, so they can't be handed to two engines either.
+ *
+ * An engine itself is one stream: feeding it blocks from two sources mixes their state.
+ *
+ * Engines are Send but not Sync.
+ */
 pub struct DtlnEngine {
     model1: *const TfLiteModel,
     interpreter_1: *mut TfLiteInterpreter,
@@ -23,27 +40,606 @@ pub struct DtlnEngine {
     out_buffer: [f32; DTLN_BLOCK_LEN],
     states_1: [f32; DTLN_BLOCK_LEN],
     states_2: [f32; DTLN_BLOCK_LEN],
+    fft: Box<dyn Fft>,
+    // Framed noise reference, used when the current block was given one.
+    ref_buffer: [f32; DTLN_BLOCK_LEN],
+    reference_active: bool,
+    // Length of the gain ramp at the start of each output block, None when disabled.
+    output_smoothing: Option<usize>,
+    // Broadband gain applied to the previous output block.
+    previous_gain: Option<f32>,
+    // Lowest gain applied to the input, 0 for unlimited suppression.
+    min_gain: f32,
+    // Speech gate deciding whether the gain floor applies to the block.
+    floor_gate: HysteresisGate,
+    floor_active: bool,
+    // Gate decisions of the last frames, newest in bit 0, see output_floor_active.
+    floor_history: u32,
+    // Mean model mask of the last inferred block, see speech_probability.
+    speech: f32,
+    noise_profile: NoiseProfile,
+    // Mask applied to the last block.
+    applied_mask: [f32; DTLN_FFT_OUT_SIZE],
+    info: EngineInfo,
+    scratch: Scratch,
+    auto_bypass: Option<BypassState>,
+    overlap: Overlap,
+    // Threads each interpreter may use, kept for engines set up like this one.
+    threads: usize,
+    // Fraction of the mask's attenuation applied, 1 for the full model mask.
+    suppression_strength: f32,
+    // Proportion of denoised signal in the output, the rest is the dry input.
+    mix: f32,
+    // Pre-emphasis of the input and the inverse on the output; of the reference, and of
+    // the dry input for denoise_split.
+    emphasis: PreEmphasis,
+    reference_emphasis: PreEmphasis,
+    dry_emphasis: PreEmphasis,
+    // Whether the time-domain model runs, see set_stage2_enabled.
+    stage2_enabled: bool,
+    // Input AGC and its configuration, None when disabled.
+    input_agc: Option<(AgcConfig, InputAgc)>,
+    // Engines for the cascade passes after this one, see Cascade.
+    cascade: Vec<DtlnEngine>,
+    // Silent blocks after which the stream state is reset, None when disabled.
+    reset_after_silence: Option<usize>,
+    // Consecutive silent input blocks so far.
+    silent_blocks: usize,
+    // Effective gains of every block since the last take_gains, None when not captured.
+    gain_capture: Option<Vec<f32>>,
+    // Per bin gains applied with the mask before synthesis, None when flat.
+    post_eq: Option<Vec<f32>>,
+    // Per bin exponents the mask is raised to, None when flat.
+    strength_curve: Option<Vec<f32>>,
+    // Totals of the mask statistics since the last take_mask_stats.
+    mask_stats: MaskStats,
+    // Artifact risk of every block since the last take_artifact_risks.
+    artifact_risks: Vec<f32>,
+    invoke_failures: InvokeFailures,
+    // Intermediate arrays of every inferred block, while debug_export runs.
+    #[cfg(feature = "debug-export")]
+    pub(crate) trace: Option<crate::dtln_debug_export::DebugTrace>,
 }
 
+/**
+ * Overlap between consecutive DTLN_BLOCK_LEN analysis blocks, fixing the block shift.
+ * The models were trained at 75% (DTLN_BLOCK_SHIFT); other ratios are for
+ * experimentation and run the recurrent models at a different frame rate than they
+ * were trained for.
+ *
+ * The engine uses rectangular windows, which satisfy COLA only when the block shift
+ * divides DTLN_BLOCK_LEN, so only the ratios in Overlap::SUPPORTED are accepted. The
+ * synthesis gain is renormalized for the overlap so passthrough keeps unity gain.
+ *
+ * Compute scales with the inference rate, DTLN_SAMPLE_RATE / block_shift: 62.5 blocks
+ * per second at 50%, 125 at 75% and 250 at 87.5%. The latency is
+ * DTLN_BLOCK_LEN - block_shift with OverlapMode::Add, 0 with OverlapMode::Save.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overlap {
+    block_shift: usize,
+    mode: OverlapMode,
+}
+
+/**
+ * How the processed frames are turned back into a stream.
+ *
+ * Add (the default) overlap-adds every frame, so each output sample is the sum of the
+ * DTLN_BLOCK_LEN / block_shift frames covering it. Mask changes between frames are
+ * crossfaded by the overlap, but an output sample is only complete once the last frame
+ * covering it has been processed, DTLN_BLOCK_LEN - block_shift samples later.
+ *
+ * Save keeps only the newest block_shift samples of each frame and discards the rest,
+ * so the output block is the input block just received: no latency beyond the block
+ * itself. Nothing smooths the joins, though: any change of mask between frames steps at
+ * the block edge, and the mask's circular convolution wraps the tail of each frame
+ * around into the kept samples instead of spilling into the discarded ones. Model 2
+ * was trained for overlap-add, so its output gains more such edge artifacts. With an
+ * identity mask both reconstruct the input exactly.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverlapMode {
+    #[default]
+    Add,
+    Save,
+}
+
+impl Overlap {
+    pub const SUPPORTED: [f32; 3] = [0.5, 0.75, 0.875];
+
+    pub fn from_ratio(ratio: f32) -> Result<Overlap> {
+        if !Overlap::SUPPORTED.contains(&ratio) {
+            return Err(anyhow::anyhow!(
+                "Unsupported overlap {}, expected one of {:?}",
+                ratio,
+                Overlap::SUPPORTED
+            ));
+        }
+        Ok(Overlap {
+            block_shift: (DTLN_BLOCK_LEN as f32 * (1.0 - ratio)) as usize,
+            mode: OverlapMode::Add,
+        })
+    }
+
+    // The same overlap, reconstructing with `mode`.
+    pub fn with_mode(self, mode: OverlapMode) -> Overlap {
+        Overlap { mode, ..self }
+    }
+
+    pub fn mode(&self) -> OverlapMode {
+        self.mode
+    }
+
+    pub fn ratio(&self) -> f32 {
+        1.0 - self.block_shift as f32 / DTLN_BLOCK_LEN as f32
+    }
+
+    pub fn block_shift(&self) -> usize {
+        self.block_shift
+    }
+
+    pub fn latency(&self) -> usize {
+        match self.mode {
+            OverlapMode::Add => DTLN_BLOCK_LEN - self.block_shift,
+            OverlapMode::Save => 0,
+        }
+    }
+
+    // Start of the input in the analysis buffer that lines up with the output block.
+    fn aligned_input(&self) -> usize {
+        DTLN_BLOCK_LEN - self.block_shift - self.latency()
+    }
+
+    // Model invocations per second of audio.
+    pub fn blocks_per_second(&self) -> f32 {
+        DTLN_SAMPLE_RATE as f32 / self.block_shift as f32
+    }
+
+    // Scale making overlap-added rectangular frames sum to unity.
+    fn cola_gain(&self) -> f32 {
+        self.block_shift as f32 / DTLN_BLOCK_LEN as f32
+    }
+}
+
+impl Default for Overlap {
+    fn default() -> Self {
+        Overlap {
+            block_shift: DTLN_BLOCK_SHIFT,
+            mode: OverlapMode::Add,
+        }
+    }
+}
+
+// Configuration of the SNR driven auto-bypass, see DtlnEngine::set_auto_bypass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoBypass {
+    // Estimated SNR above which the engine blends to passthrough.
+    pub snr_threshold_db: f32,
+    // Skip inference while fully bypassed, saving CPU.
+    pub skip_inference: bool,
+}
+
+// Configuration of the input AGC, see DtlnEngine::set_input_agc.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgcConfig {
+    // RMS level the models are fed, in dBFS, a rough stand-in for loudness.
+    pub target_dbfs: f32,
+    // Time constants of the level envelope, for a rising and for a falling level.
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    // Most the input is boosted or cut, in dB.
+    pub max_gain_db: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        AgcConfig {
+            target_dbfs: -23.0,
+            attack_ms: 50.0,
+            release_ms: 500.0,
+            max_gain_db: 20.0,
+        }
+    }
+}
+
+// Blocks taken to crossfade between processed output and passthrough.
+pub const BYPASS_FADE_BLOCKS: usize = 8;
+
+struct BypassState {
+    config: AutoBypass,
+    snr: SnrEstimator,
+    // 0 for fully processed output, 1 for passthrough.
+    blend: f32,
+    // Blocks to run inference before fading out of passthrough, so the overlap-add
+    // buffer is complete again after skipped inference.
+    warmup: usize,
+}
+
+// Blend of one block, ramping from `from` to `to` across it.
+#[derive(Clone, Copy)]
+struct BypassBlock {
+    from: f32,
+    to: f32,
+    skip_inference: bool,
+}
+
+impl BypassState {
+    fn new(config: AutoBypass) -> Self {
+        BypassState {
+            config,
+            snr: SnrEstimator::default(),
+            blend: 0.0,
+            warmup: 0,
+        }
+    }
+
+    fn advance(&mut self, in_buffer: &[f32], block_shift: usize) -> BypassBlock {
+        let energy = in_buffer.iter().map(|x| x * x).sum::<f32>() / in_buffer.len() as f32;
+        let target = if self.snr.update(energy) >= self.config.snr_threshold_db {
+            1.0
+        } else {
+            0.0
+        };
+
+        let from = self.blend;
+        if self.warmup > 0 && target < self.blend {
+            self.warmup -= 1;
+        } else {
+            let step = 1.0 / BYPASS_FADE_BLOCKS as f32;
+            self.blend = if target > self.blend {
+                (self.blend + step).min(target)
+            } else {
+                (self.blend - step).max(target)
+            };
+        }
+
+        let skip_inference = self.config.skip_inference && from >= 1.0 && self.blend >= 1.0;
+        if skip_inference {
+            self.warmup = DTLN_BLOCK_LEN / block_shift - 1;
+        }
+        BypassBlock {
+            from,
+            to: self.blend,
+            skip_inference,
+        }
+    }
+}
+
+// Fraction of the reference magnitude subtracted from the input magnitude when
+// deriving the reference gain.
+const REFERENCE_SUBTRACTION: f32 = 1.0;
+
+// Lowest gain the reference can apply on top of the model mask.
+const REFERENCE_MIN_GAIN: f32 = 0.1;
+
+// Block energy below which the applied gain is considered undefined.
+const MIN_BLOCK_ENERGY: f32 = 1e-10;
+
+// Blocks the noise profile has to learn before a freeze takes effect, about 1 s.
+pub const NOISE_ADAPTATION_BLOCKS: usize = 125;
+
+// Smoothing factor of the noise profile's running averages.
+const NOISE_PROFILE_DECAY: f32 = 0.95;
+
+// Bins louder than this multiple of the learned noise magnitude count as signal and
+// keep the model mask while the profile is frozen.
+const NOISE_SIGNAL_RATIO: f32 = 2.0;
+
+// Running per-bin characterization of the noise: the input magnitude and the mask the
+// model applied to it.
+struct NoiseProfile {
+    magnitude: [f32; DTLN_FFT_OUT_SIZE],
+    mask: [f32; DTLN_FFT_OUT_SIZE],
+    blocks: usize,
+    frozen: bool,
+}
+
+impl NoiseProfile {
+    fn new() -> Self {
+        NoiseProfile {
+            magnitude: [0.0; DTLN_FFT_OUT_SIZE],
+            mask: [0.0; DTLN_FFT_OUT_SIZE],
+            blocks: 0,
+            frozen: false,
+        }
+    }
+
+    // Whether the profile replaces the model mask, only once it has learned enough.
+    fn applies(&self) -> bool {
+        self.frozen && self.blocks >= NOISE_ADAPTATION_BLOCKS
+    }
+
+    fn learn(&mut self, in_mag: &[f32], mask: &[f32]) {
+        for i in 0..DTLN_FFT_OUT_SIZE {
+            if self.blocks == 0 {
+                self.magnitude[i] = in_mag[i];
+                self.mask[i] = mask[i];
+            } else {
+                self.magnitude[i] = NOISE_PROFILE_DECAY * self.magnitude[i]
+                    + (1.0 - NOISE_PROFILE_DECAY) * in_mag[i];
+                self.mask[i] =
+                    NOISE_PROFILE_DECAY * self.mask[i] + (1.0 - NOISE_PROFILE_DECAY) * mask[i];
+            }
+        }
+        self.blocks += 1;
+    }
+
+    // Uses the frozen mask for bins that look like the learned noise.
+    fn apply(&self, in_mag: &[f32], mask: &mut [f32]) {
+        for i in 0..DTLN_FFT_OUT_SIZE {
+            if in_mag[i] <= NOISE_SIGNAL_RATIO * self.magnitude[i] {
+                mask[i] = self.mask[i];
+            }
+        }
+    }
+}
+
+// Where infer takes a block's mask from.
+#[derive(Clone, Copy)]
+enum MaskSource<'a> {
+    // Model 1 followed by the mask adjustments.
+    Model,
+    // As Model, stopping once the mask is known without synthesizing output.
+    ModelOnly,
+    // A mask computed elsewhere, used as is.
+    External(&'a [f32]),
+    // As Model, leaving the synthesized frame in the block scratch instead of
+    // overlap-adding it, for cascade passes.
+    Stage,
+}
+
+// Largest correction output smoothing applies to a block, relative to the block's own gain.
+// It only attenuates: raising a block the mask cut back would bring back what it removed.
+const SMOOTHING_MAX_CORRECTION: f32 = 1.0;
+
+// Mean square below which an input block counts as silence, -60 dBFS.
+const SILENCE_ENERGY: f32 = 1e-6;
+
+// Tones measure_frequency_response runs, a third of an octave apart, and their length.
+const RESPONSE_LOWEST_HZ: f32 = 100.0;
+const RESPONSE_HIGHEST_HZ: f32 = 7000.0;
+const RESPONSE_TONE_SAMPLES: usize = 4096;
+const RESPONSE_TONE_LEVEL: f32 = 0.3;
+
+// Consecutive failed invocations after which the model state is reset, see infer.
+const INVOKE_FAILURES_BEFORE_RESET: usize = 8;
+
+// Failed interpreter invocations, see DtlnEngine::take_invoke_failures.
+#[derive(Debug, Default)]
+struct InvokeFailures {
+    // Since the last take_invoke_failures.
+    count: u64,
+    consecutive: usize,
+    // Invocations that will fail before the interpreter is even asked, for tests.
+    #[cfg(test)]
+    injected: usize,
+}
+
+// Runs the interpreter, counting a failure.
+fn invoke(interpreter: *mut TfLiteInterpreter, failures: &mut InvokeFailures) -> bool {
+    #[cfg(test)]
+    let injected = match failures.injected {
+        0 => false,
+        _ => {
+            failures.injected -= 1;
+            true
+        }
+    };
+    #[cfg(not(test))]
+    let injected = false;
+
+    if injected
+        || unsafe { TfLiteInterpreterInvoke(interpreter) }
+            .to_result()
+            .is_err()
+    {
+        failures.count += 1;
+        failures.consecutive += 1;
+        return false;
+    }
+    failures.consecutive = 0;
+    true
+}
+
+// Blocks of artifact risk DtlnEngine keeps for take_artifact_risks, about 8 s.
+pub const ARTIFACT_RISK_HISTORY: usize = 1024;
+
+// Blocks of silence run by DtlnEngine::prewarm.
+const PREWARM_BLOCKS: usize = 4;
+
+// The models are compiled into the library, so creating an engine never searches the
+// filesystem and doesn't depend on the working directory or where the crate is vendored.
+pub(crate) const MODEL_1: &[u8] = include_bytes!("../model/model_quant_1.tflite");
+pub(crate) const MODEL_2: &[u8] = include_bytes!("../model/model_quant_2.tflite");
+const BUNDLED_MODELS: (&[u8], &[u8]) = (MODEL_1, MODEL_2);
+
+/**
+ * A pair of DTLN models an engine runs: model 1 estimates the spectral mask, model 2
+ * refines the synthesized frame. TFLite reads them in place, so they must outlive every
+ * engine created from them; use include_bytes! or leak a loaded buffer.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DtlnModel {
+    pub model_1: &'static [u8],
+    pub model_2: &'static [u8],
+}
+
+impl DtlnModel {
+    pub fn new(model_1: &'static [u8], model_2: &'static [u8]) -> DtlnModel {
+        DtlnModel { model_1, model_2 }
+    }
+
+    // The models compiled into the library, which DtlnEngine::new runs.
+    pub fn bundled() -> DtlnModel {
+        DtlnModel::new(MODEL_1, MODEL_2)
+    }
+
+    /**
+     * Bytes of the model weights. TFLite reads them in place, so they are held once per
+     * process however many engines run them, and aren't part of
+     * DtlnEngine::memory_footprint.
+     */
+    pub fn weights_size(&self) -> usize {
+        self.model_1.len() + self.model_2.len()
+    }
+}
+
+// The TFLite handles are raw pointers, so the engine isn't Send automatically. They are
+// owned by the engine, only used through &mut self and freed in Drop, and TFLite
+// interpreters may move between threads as long as only one thread uses them at a time.
+// The engine is deliberately not Sync, share it behind a Mutex.
 unsafe impl Send for DtlnEngine {}
 
 impl DtlnEngine {
+    /**
+     * Creates an engine from the embedded models. Nothing is loaded from disk, so there's
+     * no model search path: the only external dependency is the TFLite library itself,
+     * which is linked at build time.
+     */
     pub fn new() -> Option<Self> {
-        let model1_data = include_bytes!("../model/model_quant_1.tflite");
+        DtlnEngine::with_fft(default_fft())
+    }
+
+    // Creates an engine that uses the given FFT backend.
+    pub fn with_fft(fft: Box<dyn Fft>) -> Option<Self> {
+        DtlnEngine::create(fft, Scratch::owned(), Overlap::default(), 1, BUNDLED_MODELS)
+    }
+
+    // Creates an engine whose FFT backend uses the plans in `cache`, see FftPlanCache.
+    pub fn with_fft_cache(cache: &FftPlanCache) -> Option<Self> {
+        DtlnEngine::with_fft(cache.fft())
+    }
+
+    // Creates an engine whose interpreters may each use up to `threads` threads.
+    pub fn with_threads(threads: usize) -> Option<Self> {
+        DtlnEngine::create(
+            default_fft(),
+            Scratch::owned(),
+            Overlap::default(),
+            threads,
+            BUNDLED_MODELS,
+        )
+    }
+
+    /**
+     * Creates an engine running at the given overlap, see Overlap. The processors always
+     * use the default; an engine with another overlap must be driven directly, in
+     * multiples of its block_shift.
+     */
+    pub fn with_overlap(overlap: Overlap) -> Option<Self> {
+        DtlnEngine::create(default_fft(), Scratch::owned(), overlap, 1, BUNDLED_MODELS)
+    }
+
+    /**
+     * Creates an engine that works in caller-supplied memory instead of allocating its
+     * own per-block buffers. See ScratchBuffers for the sizes required. Fails if the
+     * buffers are too small or the engine can't be created.
+     */
+    pub fn with_scratch(scratch: ScratchBuffers) -> Result<Self> {
+        scratch.validate()?;
+        DtlnEngine::create(
+            default_fft(),
+            Scratch::Supplied(scratch),
+            Overlap::default(),
+            1,
+            BUNDLED_MODELS,
+        )
+        .ok_or_else(|| anyhow::anyhow!("Unable to create engine"))
+    }
+
+    /**
+     * Creates an engine from another pair of DTLN models, e.g. one trained on different
+     * material. TFLite reads the models in place, so they must outlive the engine; use
+     * include_bytes! or leak a loaded buffer. Fails unless both models take and return
+     * tensors of the sizes the bundled ones do.
+     */
+    pub fn with_models(model: DtlnModel) -> Result<Self> {
+        DtlnEngine::create_with_models(default_fft(), Overlap::default(), 1, model)
+    }
+
+    /**
+     * Creates an engine running `model` that is set up like this one: the same kind of
+     * FFT backend sharing its plans, the same overlap and threads, and the settings
+     * copy_settings_to lists. It has its own scratch memory, since the two run side by
+     * side during DtlnImmediateProcessor::swap_model. Fails as with_models does.
+     */
+    pub(crate) fn with_same_setup(&self, model: DtlnModel) -> Result<DtlnEngine> {
+        let mut engine = DtlnEngine::create_with_models(
+            self.fft.duplicate(),
+            self.overlap,
+            self.threads,
+            model,
+        )?;
+        self.copy_settings_to(&mut engine);
+        Ok(engine)
+    }
+
+    // with_models, with the given FFT backend, overlap and threads.
+    fn create_with_models(
+        fft: Box<dyn Fft>,
+        overlap: Overlap,
+        threads: usize,
+        model: DtlnModel,
+    ) -> Result<Self> {
+        let engine = DtlnEngine::create(
+            fft,
+            Scratch::owned(),
+            overlap,
+            threads,
+            (model.model_1, model.model_2),
+        )
+        .ok_or_else(|| anyhow::anyhow!("Unable to create engine"))?;
+        let magnitude = DTLN_FFT_OUT_SIZE * std::mem::size_of::<f32>();
+        let block = DTLN_BLOCK_LEN * std::mem::size_of::<f32>();
+        let tensors = [
+            (engine.details1[0] as *const TfLiteTensor, magnitude),
+            (engine.details1[1], block),
+            (engine.output_details_1[0], magnitude),
+            (engine.output_details_1[1], block),
+            (engine.details2[0], block),
+            (engine.details2[1], block),
+            (engine.output_details_2[0], block),
+            (engine.output_details_2[1], block),
+        ];
+        for (tensor, expected) in tensors {
+            let size = if tensor.is_null() {
+                0
+            } else {
+                unsafe { TfLiteTensorByteSize(tensor) }
+            };
+            if size != expected {
+                return Err(anyhow::anyhow!(
+                    "Model tensor of {} bytes, expected {}",
+                    size,
+                    expected
+                ));
+            }
+        }
+        Ok(engine)
+    }
+
+    fn create(
+        fft: Box<dyn Fft>,
+        scratch: Scratch,
+        overlap: Overlap,
+        threads: usize,
+        (model1_data, model2_data): (&'static [u8], &'static [u8]),
+    ) -> Option<Self> {
         let model1_size = model1_data.len();
 
         let model1 = unsafe { TfLiteModelCreate(model1_data.as_ptr() as *const _, model1_size) };
         if model1.is_null() {
-            eprintln!("[DTLN] Failed to create model 1");
+            dtln_error!("Failed to create model 1");
             return None;
         }
 
         let options = unsafe { TfLiteInterpreterOptionsCreate() };
-        unsafe { TfLiteInterpreterOptionsSetNumThreads(options, 1) };
+        let threads = threads.clamp(1, i32::MAX as usize);
+        unsafe { TfLiteInterpreterOptionsSetNumThreads(options, threads as i32) };
 
         let interpreter_1 = unsafe { TfLiteInterpreterCreate(model1, options) };
         if interpreter_1.is_null() {
-            eprintln!("[DTLN] Failed to create interpreter for DTLN model 1");
+            dtln_error!("Failed to create interpreter for DTLN model 1");
             unsafe { TfLiteModelDelete(model1) };
             return None;
         }
@@ -52,7 +648,7 @@ impl DtlnEngine {
             .to_result()
             .is_err()
         {
-            eprintln!("[DTLN] Failed to allocate tensors for DTLN model 1");
+            dtln_error!("Failed to allocate tensors for DTLN model 1");
             unsafe {
                 TfLiteInterpreterDelete(interpreter_1);
                 TfLiteModelDelete(model1);
@@ -60,12 +656,11 @@ impl DtlnEngine {
             return None;
         }
 
-        let model2_data = include_bytes!("../model/model_quant_2.tflite");
         let model2_size = model2_data.len();
 
         let model2 = unsafe { TfLiteModelCreate(model2_data.as_ptr() as *const _, model2_size) };
         if model2.is_null() {
-            eprintln!("[DTLN] Failed to create model 2");
+            dtln_error!("Failed to create model 2");
             unsafe {
                 TfLiteInterpreterDelete(interpreter_1);
                 TfLiteModelDelete(model1);
@@ -75,7 +670,7 @@ impl DtlnEngine {
 
         let interpreter_2 = unsafe { TfLiteInterpreterCreate(model2, options) };
         if interpreter_2.is_null() {
-            eprintln!("[DTLN] Failed to create interpreter for DTLN model 2");
+            dtln_error!("Failed to create interpreter for DTLN model 2");
             unsafe {
                 TfLiteInterpreterDelete(interpreter_1);
                 TfLiteModelDelete(model1);
@@ -88,7 +683,7 @@ impl DtlnEngine {
             .to_result()
             .is_err()
         {
-            eprintln!("[DTLN] Failed to allocate tensors for DTLN model 2");
+            dtln_error!("Failed to allocate tensors for DTLN model 2");
             unsafe {
                 TfLiteInterpreterDelete(interpreter_1);
                 TfLiteInterpreterDelete(interpreter_2);
@@ -110,6 +705,16 @@ impl DtlnEngine {
 
         unsafe { TfLiteInterpreterOptionsDelete(options) };
 
+        let info = EngineInfo::from_tflite(model1_data, model2_data);
+        dtln_info!(
+            "Created engine: {}, {} threads, {:?} overlap",
+            info.model_description(),
+            threads,
+            overlap.ratio()
+        );
+        // No delegate is attached, both interpreters run on the built-in CPU kernels.
+        dtln_info!("Running on the TFLite CPU kernels, no delegate");
+
         Some(DtlnEngine {
             model1,
             interpreter_1,
@@ -124,86 +729,923 @@ impl DtlnEngine {
             out_buffer: [0.0; DTLN_BLOCK_LEN],
             states_1: [0.0; DTLN_BLOCK_LEN],
             states_2: [0.0; DTLN_BLOCK_LEN],
+            fft,
+            ref_buffer: [0.0; DTLN_BLOCK_LEN],
+            reference_active: false,
+            output_smoothing: None,
+            previous_gain: None,
+            min_gain: 0.0,
+            floor_gate: HysteresisGate::new(0.0, 0.0),
+            floor_active: true,
+            floor_history: u32::MAX,
+            speech: 0.0,
+            noise_profile: NoiseProfile::new(),
+            applied_mask: [0.0; DTLN_FFT_OUT_SIZE],
+            info,
+            scratch,
+            auto_bypass: None,
+            overlap,
+            threads,
+            suppression_strength: 1.0,
+            mix: 1.0,
+            emphasis: PreEmphasis::default(),
+            reference_emphasis: PreEmphasis::default(),
+            dry_emphasis: PreEmphasis::default(),
+            stage2_enabled: true,
+            input_agc: None,
+            cascade: vec![],
+            reset_after_silence: None,
+            silent_blocks: 0,
+            gain_capture: None,
+            post_eq: None,
+            strength_curve: None,
+            mask_stats: MaskStats::default(),
+            artifact_risks: vec![],
+            invoke_failures: InvokeFailures::default(),
+            #[cfg(feature = "debug-export")]
+            trace: None,
         })
     }
 
+    // Metadata of the loaded models.
+    pub fn info(&self) -> &EngineInfo {
+        &self.info
+    }
+
+    /**
+     * Estimated bytes this engine holds on top of the shared weights, see
+     * DtlnModel::weights_size: the interpreters' tensor arenas, the engine's state and
+     * working memory (including supplied scratch buffers), FFT scratch, captured gains
+     * and any cascade passes. It is an estimate:
+     * the arenas are taken from the models' tensor sizes, and TFLite's own bookkeeping
+     * and kernel temporaries, typically a few kB per interpreter, aren't visible.
+     */
+    pub fn memory_footprint(&self) -> usize {
+        let scratch = match &self.scratch {
+            Scratch::Owned { real, complex } => {
+                real.capacity() * size_of::<f32>() + complex.capacity() * size_of::<Complex<f32>>()
+            }
+            Scratch::Supplied(buffers) => {
+                size_of_val(&buffers.real[..]) + size_of_val(&buffers.complex[..])
+            }
+        };
+        let captured = [&self.gain_capture, &self.post_eq]
+            .iter()
+            .filter_map(|buffer| buffer.as_ref())
+            .map(|buffer| buffer.capacity() * size_of::<f32>())
+            .sum::<usize>();
+        size_of::<DtlnEngine>()
+            + self.info.tensor_arena_size()
+            + scratch
+            + self.fft.memory_footprint()
+            + captured
+            + self
+                .cascade
+                .iter()
+                .map(|engine| engine.memory_footprint())
+                .sum::<usize>()
+    }
+
+    pub fn overlap(&self) -> Overlap {
+        self.overlap
+    }
+
+    /**
+     * Blocks of input each inference sees. The models take a fixed DTLN_BLOCK_LEN frame,
+     * so this is DTLN_BLOCK_LEN / block_shift and not configurable; beyond it the LSTMs
+     * carry history implicitly, with no fixed length. After a reset the first
+     * context_frames() - 1 blocks are inferred on frames partly filled with silence,
+     * which is the warm-up before the output reflects a complete frame.
+     */
+    pub fn context_frames(&self) -> usize {
+        DTLN_BLOCK_LEN / self.overlap.block_shift
+    }
+
     pub fn denoise(&mut self, samples: &[f32], out: &mut [f32]) {
+        self.process_blocks(samples, None, MaskSource::Model, out);
+    }
+
+    /**
+     * Speech probability of the last inferred block, in [0, 1]: the mean of the model
+     * mask over all bins, before any of the mask adjustments. This is the value the gain
+     * floor's speech gate runs on. Updated once per block, covering the DTLN_BLOCK_LEN
+     * window that ends at the block; 0 before the first block.
+     */
+    pub fn speech_probability(&self) -> f32 {
+        self.speech
+    }
+
+    // Mask applied to the last block, before reconstruction.
+    pub fn applied_mask(&self) -> &[f32] {
+        &self.applied_mask
+    }
+
+    // Bins in the spectrum the mask and the post EQ apply to, DTLN_FFT_OUT_SIZE.
+    pub fn fft_bins(&self) -> usize {
+        DTLN_FFT_OUT_SIZE
+    }
+
+    /**
+     * Sets a fixed EQ applied after denoising, as linear gains per FFT bin laid out as
+     * in take_gains. It is multiplied into the mask before the inverse FFT, so it costs
+     * no extra transform, and doesn't affect the mask adjustments or the speech
+     * probability. The synthesis model runs after it and may smooth sharp curves.
+     * Fails unless there are fft_bins() finite, non-negative gains.
+     */
+    pub fn set_post_eq(&mut self, bins: &[f32]) -> Result<()> {
+        if bins.len() != DTLN_FFT_OUT_SIZE {
+            return Err(anyhow::anyhow!(
+                "Expected {} post EQ bins, got {}",
+                DTLN_FFT_OUT_SIZE,
+                bins.len()
+            ));
+        }
+        if let Some(gain) = bins.iter().find(|gain| !gain.is_finite() || **gain < 0.0) {
+            return Err(anyhow::anyhow!("Invalid post EQ gain {}", gain));
+        }
+        self.post_eq = Some(bins.to_vec());
+        Ok(())
+    }
+
+    // Restores the flat default.
+    pub fn clear_post_eq(&mut self) {
+        self.post_eq = None;
+    }
+
+    pub fn post_eq(&self) -> Option<&[f32]> {
+        self.post_eq.as_deref()
+    }
+
+    /**
+     * Shapes how aggressively each bin is suppressed: the model mask gain of each
+     * fft_bins() bin is raised to the curve's exponent, so 1 leaves the bin as the model
+     * decided, 2 squares its gain (a mask of 0.5 becomes 0.25), 0.5 takes its square root
+     * and 0 stops suppressing it. Unlike set_post_eq, bins the model passes untouched
+     * stay untouched whatever the curve, only the attenuation is scaled. Applied before
+     * set_suppression_strength and the gain floor. Fails unless there are fft_bins()
+     * finite, non-negative exponents.
+     */
+    pub fn set_strength_curve(&mut self, curve: &[f32]) -> Result<()> {
+        if curve.len() != DTLN_FFT_OUT_SIZE {
+            return Err(anyhow::anyhow!(
+                "Expected {} strength curve bins, got {}",
+                DTLN_FFT_OUT_SIZE,
+                curve.len()
+            ));
+        }
+        if let Some(exponent) = curve
+            .iter()
+            .find(|value| !value.is_finite() || **value < 0.0)
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid strength curve exponent {}",
+                exponent
+            ));
+        }
+        self.strength_curve = Some(curve.to_vec());
+        Ok(())
+    }
+
+    // Restores the flat default.
+    pub fn clear_strength_curve(&mut self) {
+        self.strength_curve = None;
+    }
+
+    pub fn strength_curve(&self) -> Option<&[f32]> {
+        self.strength_curve.as_deref()
+    }
+
+    /**
+     * Returns the number of failed model invocations since the last call. A failure
+     * doesn't stop processing: the block is passed through instead, the dry input when
+     * model 1 fails and the first stage's output when model 2 does, with the same
+     * latency, so a transient failure is a short unprocessed stretch rather than a gap.
+     * After INVOKE_FAILURES_BEFORE_RESET consecutive failures the recurrent state is
+     * cleared and the tensors reallocated, in case the state was what went bad.
+     */
+    pub fn take_invoke_failures(&mut self) -> u64 {
+        std::mem::take(&mut self.invoke_failures.count)
+    }
+
+    /**
+     * Returns the mean MaskStats of the blocks inferred since the last call, or since
+     * the engine was created or reset; None if there were none. Computed from the
+     * applied mask, before the post EQ.
+     */
+    pub fn take_mask_stats(&mut self) -> Option<MaskStats> {
+        std::mem::take(&mut self.mask_stats).mean()
+    }
+
+    /**
+     * Returns the artifact risk (see dtln_stats::artifact_risk) of every block inferred
+     * since the last call, or since the engine was created or reset, oldest first. Only
+     * the newest ARTIFACT_RISK_HISTORY blocks or so are kept between calls.
+     */
+    pub fn take_artifact_risks(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.artifact_risks)
+    }
+
+    /**
+     * Starts or stops recording the effective gain of every processed block, see
+     * take_gains. Off by default, so nothing is allocated unless asked for.
+     */
+    pub fn set_gain_capture(&mut self, enabled: bool) {
+        self.gain_capture = enabled.then(Vec::new);
+    }
+
+    pub fn gain_capture(&self) -> bool {
+        self.gain_capture.is_some()
+    }
+
+    /**
+     * Returns the gains recorded since the last call: DTLN_FFT_OUT_SIZE per block, in
+     * block order. Bin k is centred on k * DTLN_SAMPLE_RATE / DTLN_BLOCK_LEN Hz (31.25 Hz
+     * steps, from DC to Nyquist). Each gain is the applied mask after the strength and
+     * floor adjustments, with the post EQ, dry mix and any bypass blend folded in. The output
+     * smoothing ramp is a broadband time domain fade and isn't included. Empty when
+     * capture is off.
+     */
+    pub fn take_gains(&mut self) -> Vec<f32> {
+        self.gain_capture
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /**
+     * Shifts one block of samples into the engine and returns the mask it would apply,
+     * without synthesizing any output. Used to derive a mask from one signal and apply
+     * it to others with denoise_with_mask.
+     */
+    pub fn estimate_mask(&mut self, block: &[f32]) -> &[f32] {
+        assert_eq!(block.len(), self.overlap.block_shift);
+        self.in_buffer.copy_within(block.len().., 0);
+        self.in_buffer[DTLN_BLOCK_LEN - block.len()..].copy_from_slice(block);
+        self.emphasis
+            .emphasize(&mut self.in_buffer[DTLN_BLOCK_LEN - block.len()..]);
+        self.reference_active = false;
+        self.infer(MaskSource::ModelOnly);
+        &self.applied_mask
+    }
+
+    /**
+     * Denoises one block using `mask` (DTLN_FFT_OUT_SIZE gains) in place of model 1 and
+     * the mask adjustments, only running the synthesis stage. The output processing
+     * (smoothing, mix) still applies.
+     */
+    pub fn denoise_with_mask(&mut self, block: &[f32], mask: &[f32], out: &mut [f32]) {
+        assert_eq!(block.len(), self.overlap.block_shift);
+        assert_eq!(mask.len(), DTLN_FFT_OUT_SIZE);
+        self.process_blocks(block, None, MaskSource::External(mask), out);
+    }
+
+    /**
+     * Measures the magnitude response of the engine with suppression disabled: tones a
+     * third of an octave apart from RESPONSE_LOWEST_HZ to RESPONSE_HIGHEST_HZ are run
+     * through the synthesis stage with an identity mask, and the output level of each is
+     * compared to the input. Returns (frequency in Hz, gain in dB) pairs, 0 dB being
+     * transparent, so any coloration from the framing, overlap-add or the synthesis model
+     * shows up. The output settings (smoothing, mix, ...) are included.
+     *
+     * Clears the stream state like reset.
+     */
+    pub fn measure_frequency_response(&mut self) -> Vec<(f32, f32)> {
+        let identity = [1.0; DTLN_FFT_OUT_SIZE];
+        let length = RESPONSE_TONE_SAMPLES / self.overlap.block_shift * self.overlap.block_shift;
+        // Skip the latency and the first frames, which still overlap the silence before.
+        let settled = self.overlap.latency() + DTLN_BLOCK_LEN;
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+
+        let mut response = vec![];
+        let mut out = vec![0.0; length];
+        let mut frequency = RESPONSE_LOWEST_HZ;
+        while frequency <= RESPONSE_HIGHEST_HZ {
+            let tone = (0..length)
+                .map(|n| {
+                    let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                    RESPONSE_TONE_LEVEL * (2.0 * std::f32::consts::PI * frequency * t).sin()
+                })
+                .collect::<Vec<f32>>();
+            self.reset();
+            self.process_blocks(&tone, None, MaskSource::External(&identity), &mut out);
+            let gain = rms(&out[settled..]) / rms(&tone[..length - settled]);
+            response.push((frequency, 20.0 * gain.max(1e-6).log10()));
+            frequency *= 2f32.powf(1.0 / 3.0);
+        }
+        self.reset();
+        response
+    }
+
+    /**
+     * Denoises `samples` using `reference` (a recording of the noise, or of audio known
+     * to leak into the input) as a hint. This is a heuristic, not echo cancellation: the
+     * reference magnitude is spectrally subtracted from the input to derive an extra
+     * gain, which is combined with the model mask. The reference must be time aligned
+     * with, and the same length as, the samples.
+     */
+    pub fn denoise_with_reference(&mut self, samples: &[f32], reference: &[f32], out: &mut [f32]) {
+        assert_eq!(samples.len(), reference.len());
+        self.process_blocks(samples, Some(reference), MaskSource::Model, out);
+    }
+
+    /**
+     * Denoises `samples` into `speech`, writing the noise that was removed to `noise`:
+     * the input that lines up with each output sample, minus the output. The two sum
+     * back to the input delayed by the latency. The samples must be whole blocks of
+     * block_shift samples, a partial block would be left out of both.
+     */
+    pub fn denoise_split(&mut self, samples: &[f32], speech: &mut [f32], noise: &mut [f32]) {
+        let block_shift = self.overlap.block_shift;
+        assert!(speech.len() >= samples.len() && noise.len() >= samples.len());
+        assert!(samples.len().is_multiple_of(block_shift));
+        for ((block, speech), noise) in samples
+            .chunks_exact(block_shift)
+            .zip(speech.chunks_exact_mut(block_shift))
+            .zip(noise.chunks_exact_mut(block_shift))
+        {
+            self.process_blocks(block, None, MaskSource::Model, speech);
+            let aligned = self.overlap.aligned_input();
+            noise.copy_from_slice(&self.in_buffer[aligned..aligned + block_shift]);
+            self.dry_emphasis.deemphasize(noise);
+            if let Some((_, agc)) = self.input_agc.as_ref() {
+                agc.invert(noise);
+            }
+            for (noise, speech) in noise.iter_mut().zip(speech.iter()) {
+                *noise -= speech;
+            }
+        }
+    }
+
+    fn process_blocks(
+        &mut self,
+        samples: &[f32],
+        reference: Option<&[f32]>,
+        mask: MaskSource,
+        out: &mut [f32],
+    ) {
         let sample_count = samples.len();
-        let num_blocks = sample_count / DTLN_BLOCK_SHIFT;
+        let block_shift = self.overlap.block_shift;
+        let aligned = self.overlap.aligned_input();
+        let num_blocks = sample_count / block_shift;
         assert!(out.len() >= sample_count);
+        self.reference_active = reference.is_some();
 
         for idx in 0..num_blocks {
-            // Shift in_buffer left by DTLN_BLOCK_SHIFT samples
-            self.in_buffer.copy_within(DTLN_BLOCK_SHIFT.., 0);
+            if let Some(limit) = self.reset_after_silence {
+                self.track_silence(&samples[idx * block_shift..(idx + 1) * block_shift], limit);
+            }
+
+            // Shift in_buffer left by block_shift samples
+            self.in_buffer.copy_within(block_shift.., 0);
 
-            // Copy next DTLN_BLOCK_SHIFT samples into in_buffer
-            self.in_buffer[(DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..]
-                .copy_from_slice(&samples[idx * DTLN_BLOCK_SHIFT..(idx + 1) * DTLN_BLOCK_SHIFT]);
+            // Copy next block_shift samples into in_buffer
+            self.in_buffer[(DTLN_BLOCK_LEN - block_shift)..]
+                .copy_from_slice(&samples[idx * block_shift..(idx + 1) * block_shift]);
+            let agc_ramp = self
+                .input_agc
+                .as_mut()
+                .map(|(_, agc)| agc.apply(&mut self.in_buffer[(DTLN_BLOCK_LEN - block_shift)..]));
+            self.emphasis
+                .emphasize(&mut self.in_buffer[(DTLN_BLOCK_LEN - block_shift)..]);
+
+            if let Some(reference) = reference {
+                self.ref_buffer.copy_within(block_shift.., 0);
+                self.ref_buffer[(DTLN_BLOCK_LEN - block_shift)..]
+                    .copy_from_slice(&reference[idx * block_shift..(idx + 1) * block_shift]);
+                if let Some(ramp) = agc_ramp {
+                    scale_ramp(
+                        &mut self.ref_buffer[(DTLN_BLOCK_LEN - block_shift)..],
+                        ramp,
+                        false,
+                    );
+                }
+                self.reference_emphasis
+                    .emphasize(&mut self.ref_buffer[(DTLN_BLOCK_LEN - block_shift)..]);
+            }
 
-            self.infer();
+            let bypass = self
+                .auto_bypass
+                .as_mut()
+                .map(|state| state.advance(&self.in_buffer, block_shift));
+            let skipped = bypass.is_some_and(|bypass| bypass.skip_inference);
+            if skipped {
+                // Keep the overlap-add buffer moving so it lines up once inference resumes.
+                synthesize_frame(&mut self.out_buffer, &[], self.overlap);
+            } else {
+                self.infer(mask);
+            }
+            self.floor_history = (self.floor_history << 1) | self.floor_active as u32;
+            if let Some(gains) = self.gain_capture.as_mut() {
+                let blend = bypass.map_or(0.0, |bypass| bypass.to);
+                for i in 0..DTLN_FFT_OUT_SIZE {
+                    let gain = if skipped { 1.0 } else { self.applied_mask[i] };
+                    let gain = gain * self.post_eq.as_ref().map_or(1.0, |eq| eq[i]);
+                    let gain = gain * self.mix + (1.0 - self.mix);
+                    gains.push(gain * (1.0 - blend) + blend);
+                }
+            }
 
-            // Copy DTLN_BLOCK_SHIFT samples from out_buffer to out
-            out[idx * DTLN_BLOCK_SHIFT..(idx + 1) * DTLN_BLOCK_SHIFT]
-                .copy_from_slice(&self.out_buffer[..DTLN_BLOCK_SHIFT]);
+            // Copy block_shift samples from out_buffer to out
+            let out_block = &mut out[idx * block_shift..(idx + 1) * block_shift];
+            out_block.copy_from_slice(&self.out_buffer[..block_shift]);
+            if self.min_gain > 0.0 && self.output_floor_active() {
+                self.apply_gain_floor(out_block);
+            }
+            if let Some(ramp) = self.output_smoothing {
+                self.smooth_output_gain(out_block, ramp);
+            }
+            if self.mix < 1.0 {
+                for (i, sample) in out_block.iter_mut().enumerate() {
+                    let dry = self.in_buffer[aligned + i];
+                    *sample = *sample * self.mix + dry * (1.0 - self.mix);
+                }
+            }
+            if let Some(bypass) = bypass {
+                // The emitted block lines up with the input at aligned_input.
+                for (i, sample) in out_block.iter_mut().enumerate() {
+                    let t = (i + 1) as f32 / block_shift as f32;
+                    let blend = bypass.from + (bypass.to - bypass.from) * t;
+                    *sample = *sample * (1.0 - blend) + self.in_buffer[aligned + i] * blend;
+                }
+            }
+            self.emphasis.deemphasize(out_block);
+            if let Some((_, agc)) = self.input_agc.as_mut() {
+                agc.restore(out_block);
+            }
         }
     }
 
-    fn infer(&mut self) {
+    /**
+     * Mixes the latency-aligned dry input back into the output: 1 (the default) is fully
+     * denoised, 0 passes the input through. Clamped to [0, 1].
+     */
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn mix(&self) -> f32 {
+        self.mix
+    }
+
+    /**
+     * Pre-emphasizes the input with a one-pole high-pass, x[n] - coeff * x[n - 1], before
+     * framing, and applies the exact inverse to the output after synthesis, so the
+     * models see a flatter spectrum with the upper bands (consonants, sibilance) lifted.
+     * Around 0.97 is typical for speech; 0 (the default) disables it. Fails unless
+     * 0 <= coeff < 1, beyond which the inverse is unstable. Takes effect on the next
+     * block; call reset first to change it mid-stream without a click.
+     */
+    pub fn set_preemphasis(&mut self, coeff: f32) -> Result<()> {
+        if !(0.0..1.0).contains(&coeff) {
+            return Err(anyhow::anyhow!(
+                "Invalid pre-emphasis coefficient {}",
+                coeff
+            ));
+        }
+        self.emphasis = PreEmphasis::new(coeff);
+        self.reference_emphasis = PreEmphasis::new(coeff);
+        self.dry_emphasis = PreEmphasis::new(coeff);
+        Ok(())
+    }
+
+    pub fn preemphasis(&self) -> f32 {
+        self.emphasis.coeff()
+    }
+
+    /**
+     * Conditions the input for the models with a slow AGC, see AgcConfig: each block is
+     * scaled towards `target_dbfs` RMS before framing, so quiet and loud recordings reach
+     * the models at a similar level, and the output is divided by the same gain once
+     * it comes out, latency() later, so the output level follows the input's. None (the
+     * default) disables it. Applies to the denoise calls, not estimate_mask.
+     *
+     * The inverse is exact wherever the models pass audio through unchanged; elsewhere
+     * the mask depends on the level the models saw, which is the point. Fails unless the
+     * target and gain limit are finite, the gain limit isn't negative and the time
+     * constants are positive.
+     */
+    pub fn set_input_agc(&mut self, config: Option<AgcConfig>) -> Result<()> {
+        if let Some(config) = config {
+            if !(config.target_dbfs.is_finite()
+                && config.max_gain_db.is_finite()
+                && config.max_gain_db >= 0.0
+                && config.attack_ms > 0.0
+                && config.release_ms > 0.0)
+            {
+                return Err(anyhow::anyhow!("Invalid input AGC {:?}", config));
+            }
+        }
+        self.input_agc = config.map(|config| (config, self.create_agc(&config)));
+        Ok(())
+    }
+
+    pub fn input_agc(&self) -> Option<AgcConfig> {
+        self.input_agc.as_ref().map(|(config, _)| *config)
+    }
+
+    fn create_agc(&self, config: &AgcConfig) -> InputAgc {
+        let blocks =
+            |ms: f32| ms * DTLN_SAMPLE_RATE as f32 / 1000.0 / self.overlap.block_shift as f32;
+        InputAgc::new(
+            10f32.powf(config.target_dbfs / 20.0),
+            blocks(config.attack_ms),
+            blocks(config.release_ms),
+            10f32.powf(config.max_gain_db / 20.0),
+            self.overlap.latency() / self.overlap.block_shift,
+        )
+    }
+
+    /**
+     * Runs or skips model 2, the time-domain stage. Disabled, the output is the first
+     * stage's masked reconstruction, overlap-added directly, which roughly halves the
+     * inference cost per block. The tradeoff is quality: model 2 cleans up the phase
+     * and the residual noise the magnitude mask leaves, so without it the output is
+     * noisier and more prone to musical noise. Cascade passes are skipped too.
+     * Enabled by default.
+     *
+     * Model 2's state is cleared on every change, so re-enabling it starts from a clean
+     * state rather than one left stale by the blocks it didn't see.
+     */
+    pub fn set_stage2_enabled(&mut self, enabled: bool) {
+        if enabled != self.stage2_enabled {
+            self.states_2.fill(0.0);
+        }
+        self.stage2_enabled = enabled;
+    }
+
+    pub fn stage2_enabled(&self) -> bool {
+        self.stage2_enabled
+    }
+
+    /**
+     * Scales how much of the model mask's attenuation is applied: 1 (the default) uses
+     * the mask as is, 0.5 applies half the attenuation of each bin, 0 none. Unlike
+     * set_mix this works per bin, before synthesis. Clamped to [0, 1].
+     */
+    pub fn set_suppression_strength(&mut self, strength: f32) {
+        self.suppression_strength = strength.clamp(0.0, 1.0);
+    }
+
+    pub fn suppression_strength(&self) -> f32 {
+        self.suppression_strength
+    }
+
+    /**
+     * Blends towards passthrough when the input already looks clean, avoiding artifacts
+     * (and optionally the inference cost) on high SNR audio. The SNR is estimated per
+     * block by SnrEstimator: the ratio of a slowly decaying peak level to a minimum
+     * tracking noise floor, over the DTLN_BLOCK_LEN analysis window. Above
+     * `snr_threshold_db` the output crossfades to the latency-aligned input over
+     * BYPASS_FADE_BLOCKS blocks, and back when the SNR drops. None (the default)
+     * disables it.
+     *
+     * With `skip_inference`, the models aren't run while fully bypassed; their state is
+     * then stale by the length of the bypass when processing resumes.
+     */
+    pub fn set_auto_bypass(&mut self, config: Option<AutoBypass>) {
+        self.auto_bypass = config.map(BypassState::new);
+    }
+
+    pub fn auto_bypass(&self) -> Option<AutoBypass> {
+        self.auto_bypass.as_ref().map(|state| state.config)
+    }
+
+    /**
+     * Resets the engine, as reset does, once the input has been silent (below -60 dBFS)
+     * for `ms`, so speech after a long gap starts from a clean state instead of the LSTM
+     * state left by the last burst. The noise profile is relearnt as well. The reset
+     * happens once per gap. None (the default) disables it.
+     */
+    pub fn set_reset_after_silence(&mut self, ms: Option<f32>) {
+        self.reset_after_silence = ms.map(|ms| {
+            let samples = ms * DTLN_SAMPLE_RATE as f32 / 1000.0;
+            (samples / self.overlap.block_shift as f32).ceil().max(1.0) as usize
+        });
+        self.silent_blocks = 0;
+    }
+
+    // Counts silent input blocks, resetting once `limit` are seen in a row.
+    fn track_silence(&mut self, block: &[f32], limit: usize) {
+        let energy = block.iter().map(|x| x * x).sum::<f32>() / block.len() as f32;
+        if energy >= SILENCE_ENERGY {
+            self.silent_blocks = 0;
+            return;
+        }
+        self.silent_blocks += 1;
+        if self.silent_blocks == limit {
+            dtln_debug!("Resetting after {} silent blocks", limit);
+            self.reset();
+            // Stay reset until the input resumes, rather than resetting every `limit` blocks.
+            self.silent_blocks = limit;
+        }
+    }
+
+    /**
+     * Ramps the broadband gain applied to each output block up from the previous block's
+     * gain over the first `ramp` samples (at most the block shift), so the output doesn't
+     * step at block boundaries where the gain rises, as frames at the new gain enter the
+     * overlap-add. Falling gain is left alone, since raising it would bring back noise the
+     * mask removed. This works on the synthesized output rather than the mask, and mostly
+     * helps on onsets where the mask opens within a block or two. None disables it.
+     */
+    pub fn set_output_smoothing(&mut self, ramp: Option<usize>) {
+        self.output_smoothing = ramp.map(|ramp| ramp.clamp(1, self.overlap.block_shift));
+        self.previous_gain = None;
+    }
+
+    pub fn output_smoothing(&self) -> Option<usize> {
+        self.output_smoothing
+    }
+
+    /**
+     * Limits suppression to a linear gain floor, clamped to 0..=1. The floor applies to
+     * the first stage mask, and since the second stage can attenuate further, also to
+     * the broadband gain of each output block. 0 (the default) disables the floor.
+     */
+    pub fn set_min_gain(&mut self, gain: f32) {
+        self.min_gain = if gain.is_nan() {
+            0.0
+        } else {
+            gain.clamp(0.0, 1.0)
+        };
+    }
+
+    pub fn min_gain(&self) -> f32 {
+        self.min_gain
+    }
+
+    /**
+     * Only applies the gain floor while the block looks like speech, judged by the mean
+     * of the model mask (0 to 1). The floor switches on once the mean reaches
+     * `open_threshold` and off again once it drops below `close_threshold`, so
+     * borderline frames don't flicker between the two. Noise outside speech is then
+     * suppressed fully. The default of 0 for both keeps the floor on all the time.
+     */
+    pub fn set_floor_hysteresis(&mut self, open_threshold: f32, close_threshold: f32) {
+        self.floor_gate = HysteresisGate::new(open_threshold, close_threshold);
+        self.floor_active = true;
+        self.floor_history = u32::MAX;
+    }
+
+    pub fn floor_hysteresis(&self) -> (f32, f32) {
+        self.floor_gate.thresholds()
+    }
+
+    /**
+     * Freezes the noise characterization for stationary noise (fans, engine hum). The
+     * engine keeps a running per-bin profile of the noise magnitude and the mask the
+     * model applies to it. While frozen, bins that look like the learned noise get the
+     * learned mask instead of the model's current one, giving steadier suppression.
+     * Bins well above the noise (speech) keep the model mask.
+     *
+     * The LSTMs carry on adapting regardless; freezing only pins the mask applied to the
+     * noise. If fewer than NOISE_ADAPTATION_BLOCKS blocks have been learned, learning
+     * continues until then and the freeze takes effect after. reset clears the learned
+     * profile but keeps the freeze setting.
+     */
+    pub fn freeze_noise(&mut self) {
+        self.noise_profile.frozen = true;
+    }
+
+    // Resumes adapting the noise profile, and the model mask is used everywhere again.
+    pub fn unfreeze_noise(&mut self) {
+        self.noise_profile.frozen = false;
+    }
+
+    pub fn is_noise_frozen(&self) -> bool {
+        self.noise_profile.frozen
+    }
+
+    /**
+     * Runs a few blocks of silence through both models, so the first real block doesn't
+     * pay for TFLite's lazy allocations and cold caches. Call it off the audio thread
+     * before going live. Clears the stream state like reset.
+     */
+    pub fn prewarm(&mut self) {
+        // Silence would otherwise skip inference under auto bypass.
+        let auto_bypass = self.auto_bypass.take();
+        let silence = vec![0.0; PREWARM_BLOCKS * self.overlap.block_shift];
+        let mut out = vec![0.0; silence.len()];
+        self.denoise(&silence, &mut out);
+        self.auto_bypass = auto_bypass;
+        self.reset();
+    }
+
+    /**
+     * Gives `to` this engine's settings: smoothing, gain floor, auto bypass, reset after
+     * silence, suppression strength and curve, mix, pre-emphasis, stage 2, input AGC,
+     * post EQ, gain capture and noise freezing. Stream state isn't copied, nor the
+     * overlap, FFT backend or cascade passes, which are fixed when an engine is created.
+     */
+    fn copy_settings_to(&self, to: &mut DtlnEngine) {
+        to.output_smoothing = self.output_smoothing;
+        to.min_gain = self.min_gain;
+        to.floor_gate = self.floor_gate;
+        to.floor_gate.reset();
+        to.auto_bypass = self
+            .auto_bypass
+            .as_ref()
+            .map(|state| BypassState::new(state.config));
+        to.reset_after_silence = self.reset_after_silence;
+        to.suppression_strength = self.suppression_strength;
+        to.strength_curve.clone_from(&self.strength_curve);
+        to.mix = self.mix;
+        to.emphasis = PreEmphasis::new(self.emphasis.coeff());
+        to.reference_emphasis = PreEmphasis::new(self.reference_emphasis.coeff());
+        to.dry_emphasis = PreEmphasis::new(self.dry_emphasis.coeff());
+        to.stage2_enabled = self.stage2_enabled;
+        let agc = self
+            .input_agc
+            .as_ref()
+            .map(|(config, _)| (*config, to.create_agc(config)));
+        to.input_agc = agc;
+        to.post_eq.clone_from(&self.post_eq);
+        to.gain_capture = self.gain_capture.as_ref().map(|_| Vec::new());
+        to.noise_profile.frozen = self.noise_profile.frozen;
+    }
+
+    // Clears the sample buffers and recurrent state, as if the engine were newly created.
+    pub fn reset(&mut self) {
+        self.in_buffer.fill(0.0);
+        self.out_buffer.fill(0.0);
+        self.states_1.fill(0.0);
+        self.states_2.fill(0.0);
+        self.ref_buffer.fill(0.0);
+        self.emphasis.reset();
+        self.reference_emphasis.reset();
+        self.dry_emphasis.reset();
+        if let Some((_, agc)) = self.input_agc.as_mut() {
+            agc.reset();
+        }
+        self.previous_gain = None;
+        self.silent_blocks = 0;
+        self.mask_stats = MaskStats::default();
+        self.artifact_risks.clear();
+        if let Some(gains) = self.gain_capture.as_mut() {
+            gains.clear();
+        }
+        self.floor_gate.reset();
+        self.floor_active = true;
+        self.floor_history = u32::MAX;
+        self.speech = 0.0;
+        if let Some(state) = self.auto_bypass.as_mut() {
+            *state = BypassState::new(state.config);
+        }
+        let frozen = self.noise_profile.frozen;
+        self.noise_profile = NoiseProfile::new();
+        self.noise_profile.frozen = frozen;
+        for stage in self.cascade.iter_mut() {
+            stage.reset();
+        }
+    }
+
+    // Runs `frame` through both models as a cascade pass, replacing it with the output.
+    fn refine_frame(&mut self, frame: &mut [f32]) {
+        self.in_buffer.copy_from_slice(frame);
+        self.infer(MaskSource::Stage);
+        frame.copy_from_slice(self.scratch.views().block);
+    }
+
+    fn infer(&mut self, source: MaskSource) {
         if !self.valid {
-            eprintln!("[DTLN] Engine not initialized");
+            dtln_error!("Engine not initialized");
             return;
         }
 
-        let mut in_mag = [0f32; DTLN_FFT_OUT_SIZE];
-        let mut in_phase = [0f32; DTLN_FFT_OUT_SIZE];
-        let mut estimated_block = [0f32; DTLN_BLOCK_LEN];
+        let ScratchViews {
+            in_mag,
+            in_phase,
+            mask: out_mask,
+            fft_in,
+            block: estimated_block,
+            spectrum: fft_spectrum,
+            reference_spectrum,
+        } = self.scratch.views();
 
         // Prepare FFT input
-        let mut planner = RealFftPlanner::<f32>::new();
-        let r2c = planner.plan_fft_forward(DTLN_BLOCK_LEN);
-        let mut fft_in = self.in_buffer.to_vec();
-        let mut fft_spectrum = r2c.make_output_vec();
+        fft_in.copy_from_slice(&self.in_buffer);
 
         // Perform real-to-complex FFT
-        r2c.process(&mut fft_in, &mut fft_spectrum).unwrap();
+        self.fft.forward(fft_in, fft_spectrum);
 
         // Generate magnitude and phase
+        magnitude(fft_spectrum, in_mag);
         for i in 0..DTLN_FFT_OUT_SIZE {
-            in_mag[i] = fft_spectrum[i].norm();
             in_phase[i] = fft_spectrum[i].arg();
         }
 
-        // Prepare inputs for model 1
-        let in_mag_ptr = unsafe { TfLiteTensorData(self.details1[0]) as *mut f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(in_mag.as_ptr(), in_mag_ptr, DTLN_FFT_OUT_SIZE);
-        }
-        let states1_ptr = unsafe { TfLiteTensorData(self.details1[1]) as *mut f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(self.states_1.as_ptr(), states1_ptr, DTLN_BLOCK_LEN);
-        }
+        if let MaskSource::External(mask) = source {
+            out_mask.copy_from_slice(mask);
+        } else {
+            // Prepare inputs for model 1
+            let in_mag_ptr = unsafe { TfLiteTensorData(self.details1[0]) as *mut f32 };
+            unsafe {
+                ptr::copy_nonoverlapping(in_mag.as_ptr(), in_mag_ptr, DTLN_FFT_OUT_SIZE);
+            }
+            let states1_ptr = unsafe { TfLiteTensorData(self.details1[1]) as *mut f32 };
+            unsafe {
+                ptr::copy_nonoverlapping(self.states_1.as_ptr(), states1_ptr, DTLN_BLOCK_LEN);
+            }
 
-        // Invoke model 1
-        if unsafe { TfLiteInterpreterInvoke(self.interpreter_1) }
-            .to_result()
-            .is_err()
-        {
-            eprintln!("[DTLN] Failed to invoke interpreter for model 1");
-            return;
-        }
+            // Invoke model 1, passing the frame straight through to synthesis if it fails.
+            if !invoke(self.interpreter_1, &mut self.invoke_failures) {
+                dtln_error!("Failed to invoke interpreter for model 1, passing the block through");
+                out_mask.fill(1.0);
+                self.applied_mask.fill(1.0);
+                match source {
+                    MaskSource::ModelOnly => {}
+                    MaskSource::Stage => estimated_block.copy_from_slice(&self.in_buffer),
+                    _ => {
+                        let gain = self.overlap.cola_gain();
+                        for (sample, input) in estimated_block.iter_mut().zip(self.in_buffer) {
+                            *sample = input * gain;
+                        }
+                        synthesize_frame(&mut self.out_buffer, estimated_block, self.overlap);
+                    }
+                }
+                self.recover_from_failures();
+                return;
+            }
 
-        // Get outputs
-        let out_mask_ptr = unsafe { TfLiteTensorData(self.output_details_1[0]) as *const f32 };
-        let out_mask = unsafe { slice::from_raw_parts(out_mask_ptr, DTLN_FFT_OUT_SIZE) };
+            // Get outputs
+            let out_mask_ptr = unsafe { TfLiteTensorData(self.output_details_1[0]) as *const f32 };
+            out_mask
+                .copy_from_slice(unsafe { slice::from_raw_parts(out_mask_ptr, DTLN_FFT_OUT_SIZE) });
 
-        let out_states1_ptr = unsafe { TfLiteTensorData(self.output_details_1[1]) as *const f32 };
-        unsafe {
-            ptr::copy_nonoverlapping(out_states1_ptr, self.states_1.as_mut_ptr(), DTLN_BLOCK_LEN);
+            let out_states1_ptr =
+                unsafe { TfLiteTensorData(self.output_details_1[1]) as *const f32 };
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    out_states1_ptr,
+                    self.states_1.as_mut_ptr(),
+                    DTLN_BLOCK_LEN,
+                );
+            }
+
+            if self.reference_active {
+                fft_in.copy_from_slice(&self.ref_buffer);
+                self.fft.forward(fft_in, reference_spectrum);
+                apply_reference_gain(in_mag, reference_spectrum, out_mask);
+            }
+            let speech = out_mask.iter().sum::<f32>() / DTLN_FFT_OUT_SIZE as f32;
+            self.speech = speech.clamp(0.0, 1.0);
+            self.floor_active = self.floor_gate.update(speech);
+            if self.noise_profile.applies() {
+                self.noise_profile.apply(in_mag, out_mask);
+            } else {
+                self.noise_profile.learn(in_mag, out_mask);
+            }
+            if let Some(curve) = self.strength_curve.as_ref() {
+                for (gain, exponent) in out_mask.iter_mut().zip(curve) {
+                    *gain = gain.max(0.0).powf(*exponent);
+                }
+            }
+            if self.suppression_strength < 1.0 {
+                for gain in out_mask.iter_mut() {
+                    *gain = 1.0 - self.suppression_strength * (1.0 - *gain);
+                }
+            }
+            if self.min_gain > 0.0 && self.floor_active {
+                for gain in out_mask.iter_mut() {
+                    *gain = gain.max(self.min_gain);
+                }
+            }
+        }
+
+        if !matches!(source, MaskSource::Stage) {
+            let stats = MaskStats::from_mask(out_mask, in_mag, &self.applied_mask);
+            if self.artifact_risks.len() >= ARTIFACT_RISK_HISTORY {
+                self.artifact_risks.drain(..ARTIFACT_RISK_HISTORY / 2);
+            }
+            self.artifact_risks.push(stats.artifact_risk);
+            self.mask_stats.add(stats);
+        }
+        self.applied_mask.copy_from_slice(out_mask);
+        #[cfg(feature = "debug-export")]
+        if let Some(trace) = self.trace.as_mut() {
+            trace.frame.extend_from_slice(&self.in_buffer);
+            trace.magnitude.extend_from_slice(in_mag);
+            trace.phase.extend_from_slice(in_phase);
+            trace.mask.extend_from_slice(out_mask);
+        }
+        if let MaskSource::ModelOnly = source {
+            return;
         }
 
         // Apply mask and reconstruct complex spectrum
+        multiply(in_mag, out_mask);
+        // Cascade passes refine an already equalized frame.
+        if let Some(eq) = self
+            .post_eq
+            .as_ref()
+            .filter(|_| !matches!(source, MaskSource::Stage))
+        {
+            multiply(in_mag, eq);
+        }
         for i in 0..DTLN_FFT_OUT_SIZE {
-            let magnitude = in_mag[i] * out_mask[i];
+            let magnitude = in_mag[i];
             let phase = in_phase[i];
             let real = magnitude * phase.cos();
             let imag = magnitude * phase.sin();
@@ -211,23 +1653,23 @@ impl DtlnEngine {
         }
 
         // Handle DC component (i = 0)
-        let magnitude = in_mag[0] * out_mask[0];
-        fft_spectrum[0] = Complex::new(magnitude, 0.0);
+        fft_spectrum[0] = Complex::new(in_mag[0], 0.0);
 
         // Handle Nyquist component (i = N/2)
-        let magnitude = in_mag[DTLN_FFT_OUT_SIZE - 1] * out_mask[DTLN_FFT_OUT_SIZE - 1];
-        fft_spectrum[DTLN_FFT_OUT_SIZE - 1] = Complex::new(magnitude, 0.0);
-
-        // Prepare for inverse FFT
-        let c2r = planner.plan_fft_inverse(DTLN_BLOCK_LEN);
-        let mut ifft_output = c2r.make_output_vec();
+        fft_spectrum[DTLN_FFT_OUT_SIZE - 1] = Complex::new(in_mag[DTLN_FFT_OUT_SIZE - 1], 0.0);
 
         // Perform complex-to-real IFFT
-        c2r.process(&mut fft_spectrum, &mut ifft_output).unwrap();
+        self.fft.inverse(fft_spectrum, estimated_block);
 
-        // Normalize the IFFT output
-        for i in 0..DTLN_BLOCK_LEN {
-            estimated_block[i] = ifft_output[i] / DTLN_BLOCK_LEN as f32;
+        // Normalize the IFFT output, the reciprocal of a power of two is exact.
+        scale(estimated_block, 1.0 / DTLN_BLOCK_LEN as f32);
+
+        if !self.stage2_enabled {
+            if !matches!(source, MaskSource::Stage) {
+                scale(estimated_block, self.overlap.cola_gain());
+                synthesize_frame(&mut self.out_buffer, estimated_block, self.overlap);
+            }
+            return;
         }
 
         // Prepare inputs for model 2
@@ -240,12 +1682,14 @@ impl DtlnEngine {
             ptr::copy_nonoverlapping(self.states_2.as_ptr(), states2_ptr, DTLN_BLOCK_LEN);
         }
 
-        // Invoke model 2
-        if unsafe { TfLiteInterpreterInvoke(self.interpreter_2) }
-            .to_result()
-            .is_err()
-        {
-            eprintln!("[DTLN] Failed to invoke interpreter for model 2");
+        // Invoke model 2, falling back to the first stage's output if it fails.
+        if !invoke(self.interpreter_2, &mut self.invoke_failures) {
+            dtln_error!("Failed to invoke interpreter for model 2, using the first stage");
+            if !matches!(source, MaskSource::Stage) {
+                scale(estimated_block, self.overlap.cola_gain());
+                synthesize_frame(&mut self.out_buffer, estimated_block, self.overlap);
+            }
+            self.recover_from_failures();
             return;
         }
 
@@ -258,16 +1702,150 @@ impl DtlnEngine {
             ptr::copy_nonoverlapping(out_states2_ptr, self.states_2.as_mut_ptr(), DTLN_BLOCK_LEN);
         }
 
-        // Overlap-add
-        self.out_buffer.copy_within(DTLN_BLOCK_SHIFT.., 0);
-        for i in (DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT)..DTLN_BLOCK_LEN {
-            self.out_buffer[i] = 0.0;
+        #[cfg(feature = "debug-export")]
+        if let Some(trace) = self.trace.as_mut() {
+            trace.stage2_out.extend_from_slice(out_block);
         }
 
-        for (i, item) in out_block.iter().enumerate().take(DTLN_BLOCK_LEN) {
-            self.out_buffer[i] += item;
+        if let MaskSource::Stage = source {
+            estimated_block.copy_from_slice(out_block);
+            return;
+        }
+
+        // Model 2 was trained to overlap-add at the default overlap, renormalize for ours.
+        let gain = self.overlap.cola_gain() / Overlap::default().cola_gain();
+        if gain == 1.0 && self.cascade.is_empty() {
+            synthesize_frame(&mut self.out_buffer, out_block, self.overlap);
+        } else {
+            estimated_block.copy_from_slice(out_block);
+            for stage in self.cascade.iter_mut() {
+                stage.refine_frame(estimated_block);
+            }
+            if gain != 1.0 {
+                scale(estimated_block, gain);
+            }
+            synthesize_frame(&mut self.out_buffer, estimated_block, self.overlap);
+        }
+        #[cfg(feature = "debug-export")]
+        if let Some(trace) = self.trace.as_mut() {
+            trace.overlap.extend_from_slice(&self.out_buffer);
         }
     }
+
+    // Clears the recurrent state and reallocates the tensors after a run of failures.
+    fn recover_from_failures(&mut self) {
+        if self.invoke_failures.consecutive < INVOKE_FAILURES_BEFORE_RESET {
+            return;
+        }
+        dtln_warn!(
+            "{} consecutive model failures, resetting the model state",
+            self.invoke_failures.consecutive
+        );
+        self.invoke_failures.consecutive = 0;
+        self.states_1.fill(0.0);
+        self.states_2.fill(0.0);
+        for interpreter in [self.interpreter_1, self.interpreter_2] {
+            if unsafe { TfLiteInterpreterAllocateTensors(interpreter) }
+                .to_result()
+                .is_err()
+            {
+                dtln_error!("Failed to reallocate tensors");
+            }
+        }
+    }
+}
+
+/**
+ * Adds a processed frame to the output buffer; the next block_shift samples of output
+ * are then at its start. Frames carry the overlap-add gain, an empty frame is silence.
+ */
+fn synthesize_frame(out_buffer: &mut [f32], frame: &[f32], overlap: Overlap) {
+    let block_shift = overlap.block_shift;
+    match overlap.mode {
+        OverlapMode::Add => overlap_add_frame(out_buffer, frame, block_shift),
+        OverlapMode::Save if frame.is_empty() => out_buffer[..block_shift].fill(0.0),
+        OverlapMode::Save => {
+            let gain = 1.0 / overlap.cola_gain();
+            let kept = &frame[frame.len() - block_shift..];
+            for (sample, frame) in out_buffer.iter_mut().zip(kept) {
+                *sample = frame * gain;
+            }
+        }
+    }
+}
+
+// Advances the overlap-add buffer by one block shift and accumulates the next frame.
+fn overlap_add_frame(out_buffer: &mut [f32], frame: &[f32], block_shift: usize) {
+    out_buffer.copy_within(block_shift.., 0);
+    let len = out_buffer.len();
+    out_buffer[len - block_shift..].fill(0.0);
+    overlap_add(out_buffer, frame);
+}
+
+// Scales the mask by a spectral subtraction gain derived from the reference spectrum.
+fn apply_reference_gain(in_mag: &[f32], reference_spectrum: &[Complex<f32>], mask: &mut [f32]) {
+    for i in 0..DTLN_FFT_OUT_SIZE {
+        let gain = if in_mag[i] > 0.0 {
+            1.0 - REFERENCE_SUBTRACTION * reference_spectrum[i].norm() / in_mag[i]
+        } else {
+            1.0
+        };
+        mask[i] *= gain.clamp(REFERENCE_MIN_GAIN, 1.0);
+    }
+}
+
+impl DtlnEngine {
+    // Whether the gain floor applies to the emitted block. The gate decides on the newest
+    // frame, but the block sits in the middle of an earlier one, a frame back per
+    // block_shift samples it's behind the centre.
+    fn output_floor_active(&self) -> bool {
+        let block_shift = self.overlap.block_shift;
+        let centre = (DTLN_BLOCK_LEN - block_shift) / 2;
+        let lag = centre.saturating_sub(self.overlap.aligned_input()) / block_shift;
+        (self.floor_history >> lag.min(31)) & 1 == 1
+    }
+
+    // Raises the output block to at least min_gain times the input it lines up with.
+    fn apply_gain_floor(&self, out_block: &mut [f32]) {
+        let aligned = self.overlap.aligned_input();
+        let input_energy: f32 = self.in_buffer[aligned..aligned + out_block.len()]
+            .iter()
+            .map(|x| x * x)
+            .sum();
+        let output_energy: f32 = out_block.iter().map(|x| x * x).sum();
+        let floor_energy = input_energy * self.min_gain * self.min_gain;
+        if output_energy >= floor_energy || output_energy < MIN_BLOCK_ENERGY {
+            return;
+        }
+        let scale = (floor_energy / output_energy).sqrt();
+        out_block.iter_mut().for_each(|sample| *sample *= scale);
+    }
+
+    // Scales the start of the output block so the applied gain ramps in from the
+    // previous block's gain.
+    fn smooth_output_gain(&mut self, out_block: &mut [f32], ramp: usize) {
+        // The emitted block lines up with the input at aligned_input.
+        let aligned = self.overlap.aligned_input();
+        let input_energy: f32 = self.in_buffer[aligned..aligned + out_block.len()]
+            .iter()
+            .map(|x| x * x)
+            .sum();
+        if input_energy < MIN_BLOCK_ENERGY {
+            return;
+        }
+        let output_energy: f32 = out_block.iter().map(|x| x * x).sum();
+        let gain = (output_energy / input_energy).sqrt();
+
+        if let Some(previous_gain) = self.previous_gain {
+            for (i, sample) in out_block[..ramp].iter_mut().enumerate() {
+                let t = (i + 1) as f32 / ramp as f32;
+                let target = previous_gain + (gain - previous_gain) * t;
+                let correction = if gain > 0.0 { target / gain } else { 1.0 };
+                *sample *= correction.min(SMOOTHING_MAX_CORRECTION);
+            }
+        }
+        self.previous_gain = Some(gain);
+    }
 }
 
 impl Drop for DtlnEngine {
@@ -285,10 +1863,70 @@ pub fn dtln_create() -> Option<DtlnEngine> {
     DtlnEngine::new()
 }
 
+// Most passes a Cascade may run.
+pub const MAX_CASCADE_PASSES: usize = 4;
+
+/**
+ * Runs the models several times per frame for heavy noise, inside a single framing and
+ * overlap-add pipeline. Every pass after the first takes the frame synthesized by the
+ * pass before it, before overlap-add, so the audio is framed and overlap-added once and
+ * the latency stays that of one engine. Compute grows linearly with the passes, and each
+ * pass keeps its own recurrent state.
+ *
+ * Returns diminish quickly: a second pass removes part of the residual noise the first
+ * leaves, later passes mostly eat into speech and leave musical noise, since the models
+ * were never trained on their own output.
+ */
+pub struct Cascade {
+    engine: DtlnEngine,
+}
+
+impl Cascade {
+    // Creates a cascade of 1 to MAX_CASCADE_PASSES passes, 1 being a plain engine.
+    pub fn new(passes: usize) -> Result<Cascade> {
+        if !(1..=MAX_CASCADE_PASSES).contains(&passes) {
+            return Err(anyhow::anyhow!(
+                "Cascade must have 1 to {} passes, got {}",
+                MAX_CASCADE_PASSES,
+                passes
+            ));
+        }
+        let create = || DtlnEngine::new().ok_or_else(|| anyhow::anyhow!("Unable to create engine"));
+        let mut engine = create()?;
+        for _ in 1..passes {
+            engine.cascade.push(create()?);
+        }
+        Ok(Cascade { engine })
+    }
+
+    pub fn passes(&self) -> usize {
+        self.engine.cascade.len() + 1
+    }
+
+    // Same as a single engine, DTLN_LATENCY.
+    pub fn latency(&self) -> usize {
+        self.engine.overlap().latency()
+    }
+
+    // Every pass's engine, see DtlnEngine::memory_footprint.
+    pub fn memory_footprint(&self) -> usize {
+        self.engine.memory_footprint()
+    }
+
+    // Denoises whole blocks of DTLN_BLOCK_SHIFT samples, as DtlnEngine::denoise.
+    pub fn denoise(&mut self, samples: &[f32], out: &mut [f32]) -> Result<()> {
+        dtln_denoise(&mut self.engine, samples, out)
+    }
+
+    pub fn reset(&mut self) {
+        self.engine.reset();
+    }
+}
+
 pub fn dtln_denoise(engine: &mut DtlnEngine, samples: &[f32], out: &mut [f32]) -> Result<()> {
     if out.len() < samples.len() {
-        eprintln!(
-            "[DTLN] Output buffer too small, {} vs {}",
+        dtln_error!(
+            "Output buffer too small, {} vs {}",
             out.len(),
             samples.len()
         );
@@ -297,3 +1935,761 @@ pub fn dtln_denoise(engine: &mut DtlnEngine, samples: &[f32], out: &mut [f32]) -
     engine.denoise(samples, out);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fft-realfft")]
+    use crate::dtln_fft::{RealFftBackend, RustFftBackend};
+    use crate::dtln_utilities::{generate_test_signal, TestSignal};
+    use std::time::Duration;
+
+    /**
+     * Noise made of tones on every bin centre between DC and Nyquist, with random
+     * amplitudes and phases. Every frame holds whole periods, so the DC and Nyquist bins
+     * are empty; the synthesis keeps their magnitude but not their sign, so only such
+     * input passes an identity mask unchanged.
+     */
+    fn frame_periodic_noise(len: usize) -> Vec<f32> {
+        let tones = (1..DTLN_FFT_OUT_SIZE - 1)
+            .map(|bin| {
+                let phase = rand::random::<f32>() * 2.0 * std::f32::consts::PI;
+                (bin, rand::random::<f32>() * 0.05, phase)
+            })
+            .collect::<Vec<_>>();
+        (0..len)
+            .map(|n| {
+                tones
+                    .iter()
+                    .map(|(bin, amplitude, phase)| {
+                        let cycles = (bin * n % DTLN_BLOCK_LEN) as f32 / DTLN_BLOCK_LEN as f32;
+                        amplitude * (2.0 * std::f32::consts::PI * cycles + phase).cos()
+                    })
+                    .sum::<f32>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_new_independent_of_cwd() {
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(std::env::temp_dir()).unwrap();
+        let engine = DtlnEngine::new();
+        std::env::set_current_dir(cwd).unwrap();
+        assert!(engine.is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "fft-realfft")]
+    fn test_fft_backends_engine_output() {
+        let samples = (0..16384)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+
+        let mut real_engine = DtlnEngine::with_fft(Box::new(RealFftBackend::new())).unwrap();
+        let mut rust_engine = DtlnEngine::with_fft(Box::new(RustFftBackend::new())).unwrap();
+        let mut real_out = vec![0.0; samples.len()];
+        let mut rust_out = vec![0.0; samples.len()];
+        real_engine.denoise(&samples, &mut real_out);
+        rust_engine.denoise(&samples, &mut rust_out);
+
+        for (a, b) in real_out.iter().zip(rust_out.iter()) {
+            assert!((a - b).abs() < 1e-3, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_stream_isolation() {
+        let streams = [0.5f32, 0.05].map(|level| {
+            (0..DTLN_BLOCK_SHIFT * 200)
+                .map(|_| (rand::random::<f32>() * 2.0 - 1.0) * level)
+                .collect::<Vec<f32>>()
+        });
+        let alone = streams.clone().map(|samples| {
+            let mut out = vec![0.0; samples.len()];
+            DtlnEngine::new().unwrap().denoise(&samples, &mut out);
+            out
+        });
+
+        // Alternate blocks between the two streams, each on its own engine.
+        let mut engines = [DtlnEngine::new().unwrap(), DtlnEngine::new().unwrap()];
+        let mut interleaved = [vec![], vec![]];
+        let mut out = [0.0; DTLN_BLOCK_SHIFT];
+        for block in 0..200 {
+            for stream in 0..2 {
+                let range = block * DTLN_BLOCK_SHIFT..(block + 1) * DTLN_BLOCK_SHIFT;
+                engines[stream].denoise(&streams[stream][range], &mut out);
+                interleaved[stream].extend_from_slice(&out);
+            }
+        }
+        assert_eq!(interleaved, alone);
+    }
+
+    #[test]
+    fn test_context_frames() {
+        for ratio in Overlap::SUPPORTED {
+            let mut engine = DtlnEngine::with_overlap(Overlap::from_ratio(ratio).unwrap()).unwrap();
+            let block_shift = engine.overlap().block_shift();
+            let block = vec![1.0; block_shift];
+            let mut out = vec![0.0; block_shift];
+            // The frame holds silence from before the stream until context_frames blocks in.
+            let mut warm_up = 0;
+            while engine.in_buffer.contains(&0.0) {
+                engine.denoise(&block, &mut out);
+                warm_up += 1;
+            }
+            assert_eq!(warm_up, engine.context_frames());
+        }
+        assert_eq!(DtlnEngine::new().unwrap().context_frames(), 4);
+    }
+
+    #[test]
+    fn test_frequency_response() {
+        let response = DtlnEngine::new().unwrap().measure_frequency_response();
+        assert_eq!(response.len(), 19);
+        assert_eq!(response[0].0, 100.0);
+        // Flat through the speech band. Between 150 and 300 Hz the synthesis model lifts
+        // the level by about 5 dB; the 100 and 125 Hz tones come out well below the input.
+        for (frequency, gain) in response {
+            let tolerance = match frequency {
+                f if (300.0..=6500.0).contains(&f) => 1.0,
+                f if (150.0..300.0).contains(&f) => 6.0,
+                _ => continue,
+            };
+            assert!(gain.abs() < tolerance, "{} dB at {} Hz", gain, frequency);
+        }
+    }
+
+    #[test]
+    fn test_invoke_failure_recovery() {
+        let tone = (0..DTLN_BLOCK_SHIFT * 60)
+            .map(|n| 0.3 * (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 16000.0).sin())
+            .collect::<Vec<f32>>();
+        let mut engine = DtlnEngine::new().unwrap();
+        let mut out = vec![0.0; tone.len()];
+        engine.denoise(
+            &tone[..DTLN_BLOCK_SHIFT * 20],
+            &mut out[..DTLN_BLOCK_SHIFT * 20],
+        );
+        assert_eq!(engine.take_invoke_failures(), 0);
+
+        // Model 1 fails for two blocks; processing carries on and the failures are counted.
+        engine.invoke_failures.injected = 2;
+        let range = DTLN_BLOCK_SHIFT * 20..DTLN_BLOCK_SHIFT * 40;
+        engine.denoise(&tone[range.clone()], &mut out[range]);
+        assert_eq!(engine.take_invoke_failures(), 2);
+        assert_eq!(engine.take_invoke_failures(), 0);
+        assert!(out.iter().all(|s| s.is_finite()));
+        assert!(out[DTLN_BLOCK_SHIFT * 20..DTLN_BLOCK_SHIFT * 40]
+            .iter()
+            .any(|s| *s != 0.0));
+
+        // A failed block passes the dry input through where the models would otherwise
+        // have had a say: a block on its own frame only, after a reset.
+        let mut engine = DtlnEngine::new().unwrap();
+        engine.invoke_failures.injected = DTLN_BLOCK_LEN / DTLN_BLOCK_SHIFT;
+        let mut passthrough = vec![0.0; DTLN_BLOCK_LEN];
+        engine.denoise(&tone[..DTLN_BLOCK_LEN], &mut passthrough);
+        let last = DTLN_BLOCK_LEN - DTLN_BLOCK_SHIFT;
+        for (out, input) in passthrough[last..].iter().zip(tone.iter()) {
+            assert!((out - input).abs() < 1e-5, "{} vs {}", out, input);
+        }
+
+        // A run of failures resets the state, and processing recovers afterwards.
+        engine.denoise(&tone[..DTLN_BLOCK_SHIFT], &mut out[..DTLN_BLOCK_SHIFT]);
+        engine.invoke_failures.injected = INVOKE_FAILURES_BEFORE_RESET;
+        engine.denoise(
+            &tone[..DTLN_BLOCK_SHIFT * 8],
+            &mut out[..DTLN_BLOCK_SHIFT * 8],
+        );
+        assert_eq!(engine.invoke_failures.consecutive, 0);
+        assert!(engine.states_1.iter().all(|s| *s == 0.0));
+        engine.denoise(
+            &tone[..DTLN_BLOCK_SHIFT * 8],
+            &mut out[..DTLN_BLOCK_SHIFT * 8],
+        );
+        assert_eq!(
+            engine.take_invoke_failures(),
+            (DTLN_BLOCK_LEN / DTLN_BLOCK_SHIFT + INVOKE_FAILURES_BEFORE_RESET) as u64
+        );
+        assert!(out.iter().all(|s| s.is_finite()));
+    }
+
+    #[test]
+    fn test_post_eq() {
+        // Tones on every 16th bin centre, periodic in the frame so none leaks into others.
+        let bins = (1..15).map(|k| k * 16).collect::<Vec<usize>>();
+        let frequency = |bin: usize| (bin * DTLN_SAMPLE_RATE as usize / DTLN_BLOCK_LEN) as f32;
+        let tones = (0..DTLN_BLOCK_SHIFT * 200)
+            .map(|n| {
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                bins.iter()
+                    .map(|bin| 0.03 * (2.0 * std::f32::consts::PI * frequency(*bin) * t).sin())
+                    .sum::<f32>()
+            })
+            .collect::<Vec<f32>>();
+        let levels = |engine: &mut DtlnEngine| {
+            let identity = [1.0; DTLN_FFT_OUT_SIZE];
+            let mut out = vec![0.0; tones.len()];
+            engine.process_blocks(&tones, None, MaskSource::External(&identity), &mut out);
+            let settled = &out[DTLN_BLOCK_LEN * 4..];
+            bins.iter()
+                .map(|bin| {
+                    let w = 2.0 * std::f32::consts::PI * frequency(*bin) / DTLN_SAMPLE_RATE as f32;
+                    let (re, im) =
+                        settled
+                            .iter()
+                            .enumerate()
+                            .fold((0.0, 0.0), |(re, im), (n, s)| {
+                                (re + s * (w * n as f32).cos(), im + s * (w * n as f32).sin())
+                            });
+                    (re * re + im * im).sqrt()
+                })
+                .collect::<Vec<f32>>()
+        };
+
+        let mut engine = DtlnEngine::new().unwrap();
+        assert_eq!(engine.fft_bins(), DTLN_FFT_OUT_SIZE);
+        assert!(engine.post_eq().is_none());
+        // Without the synthesis model, which reshapes the spectrum, the curve is exact.
+        engine.set_stage2_enabled(false);
+        let flat = levels(&mut engine);
+
+        let boosted = 64;
+        let mut curve = vec![1.0; engine.fft_bins()];
+        curve[boosted] = 2.0;
+        assert!(engine.set_post_eq(&curve[1..]).is_err());
+        curve[0] = -1.0;
+        assert!(engine.set_post_eq(&curve).is_err());
+        curve[0] = 1.0;
+        engine.set_post_eq(&curve).unwrap();
+        engine.reset();
+        let eq = levels(&mut engine);
+        for ((bin, flat), eq) in bins.iter().zip(flat.iter()).zip(eq.iter()) {
+            let db = 20.0 * (eq / flat).log10();
+            if *bin == boosted {
+                assert!(db > 3.0, "{} dB at bin {}", db, bin);
+            } else {
+                assert!(db.abs() < 1.0, "{} dB at bin {}", db, bin);
+            }
+        }
+
+        engine.clear_post_eq();
+        engine.reset();
+        assert_eq!(levels(&mut engine), flat);
+    }
+
+    #[test]
+    fn test_floor_gate_alignment() {
+        // A steady tone cut back by the mask, with the gate decision switching every 5
+        // blocks. Where the floor applies the output is raised to 30%.
+        let frequency = 5.0 * DTLN_SAMPLE_RATE as f32 / DTLN_BLOCK_LEN as f32;
+        let tone = (0..DTLN_BLOCK_SHIFT * 40)
+            .map(|n| {
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                0.5 * (2.0 * std::f32::consts::PI * frequency * t).sin()
+            })
+            .collect::<Vec<f32>>();
+        let decision = |block: usize| (block / 5).is_multiple_of(2);
+        let mut engine = DtlnEngine::new().unwrap();
+        engine.set_min_gain(0.3);
+        let mask = [0.05; DTLN_FFT_OUT_SIZE];
+        let latency = DTLN_LATENCY / DTLN_BLOCK_SHIFT;
+        for (block, samples) in tone.chunks(DTLN_BLOCK_SHIFT).enumerate() {
+            engine.floor_active = decision(block);
+            let mut out = [0.0; DTLN_BLOCK_SHIFT];
+            engine.denoise_with_mask(samples, &mask, &mut out);
+            if block <= latency {
+                continue;
+            }
+            let dry = &tone[(block - latency) * DTLN_BLOCK_SHIFT..][..DTLN_BLOCK_SHIFT];
+            let energy = |x: &[f32]| x.iter().map(|x| x * x).sum::<f32>();
+            let gain = (energy(&out) / energy(dry)).sqrt();
+            // The emitted block sits in the middle of the frame before the newest, so
+            // that frame's decision applies.
+            assert_eq!(gain > 0.2, decision(block - 1), "block {}: {}", block, gain);
+        }
+    }
+
+    #[test]
+    fn test_output_smoothing() {
+        // A tone on a bin centre, with the mask stepping up or down after 30 blocks.
+        let frequency = 5.0 * DTLN_SAMPLE_RATE as f32 / DTLN_BLOCK_LEN as f32;
+        let tone = (0..DTLN_BLOCK_SHIFT * 60)
+            .map(|n| {
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                0.5 * (2.0 * std::f32::consts::PI * frequency * t).sin()
+            })
+            .collect::<Vec<f32>>();
+        let run = |ramp: Option<usize>, before: f32, after: f32| {
+            let mut engine = DtlnEngine::new().unwrap();
+            engine.set_stage2_enabled(false);
+            engine.set_output_smoothing(ramp);
+            let mut out = vec![0.0; tone.len()];
+            for (k, (block, out)) in tone
+                .chunks(DTLN_BLOCK_SHIFT)
+                .zip(out.chunks_mut(DTLN_BLOCK_SHIFT))
+                .enumerate()
+            {
+                let mask = [if k < 30 { before } else { after }; DTLN_FFT_OUT_SIZE];
+                engine.denoise_with_mask(block, &mask, out);
+            }
+            out
+        };
+        let max_boundary_jump = |out: &[f32]| {
+            (8..out.len() / DTLN_BLOCK_SHIFT)
+                .map(|block| {
+                    let n = block * DTLN_BLOCK_SHIFT;
+                    (out[n] - out[n - 1]).abs()
+                })
+                .fold(0.0, f32::max)
+        };
+
+        // Rising gain steps where frames at the new gain enter the overlap-add.
+        let plain = run(None, 0.1, 1.0);
+        let smoothed = run(Some(DTLN_BLOCK_SHIFT), 0.1, 1.0);
+        assert!(
+            max_boundary_jump(&smoothed) < max_boundary_jump(&plain) * 0.75,
+            "{} vs {}",
+            max_boundary_jump(&smoothed),
+            max_boundary_jump(&plain)
+        );
+        // Falling gain is never raised back.
+        let plain = run(None, 1.0, 0.1);
+        let smoothed = run(Some(DTLN_BLOCK_SHIFT), 1.0, 0.1);
+        for (smoothed, plain) in smoothed.iter().zip(plain.iter()) {
+            assert!(smoothed.abs() <= plain.abs());
+        }
+    }
+
+    #[test]
+    fn test_strength_curve() {
+        // A speech-band tone, 250 Hz, in white noise.
+        let samples = (0..DTLN_BLOCK_SHIFT * 100)
+            .map(|n| {
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                0.3 * (2.0 * std::f32::consts::PI * 250.0 * t).sin()
+                    + (rand::random::<f32>() - 0.5) * 0.1
+            })
+            .collect::<Vec<f32>>();
+        let masks = |engine: &mut DtlnEngine| {
+            samples
+                .chunks(DTLN_BLOCK_SHIFT)
+                .map(|block| engine.estimate_mask(block).to_vec())
+                .collect::<Vec<Vec<f32>>>()
+        };
+
+        let mut engine = DtlnEngine::new().unwrap();
+        assert!(engine.strength_curve().is_none());
+        let flat = masks(&mut engine);
+
+        // Gentle up to 2 kHz (bin 64), aggressive above 4 kHz (bin 128).
+        let curve = (0..engine.fft_bins())
+            .map(|bin| match bin {
+                0..=64 => 0.25,
+                128.. => 3.0,
+                _ => 1.0,
+            })
+            .collect::<Vec<f32>>();
+        assert!(engine.set_strength_curve(&curve[1..]).is_err());
+        let mut invalid = curve.clone();
+        invalid[3] = f32::NAN;
+        assert!(engine.set_strength_curve(&invalid).is_err());
+        engine.set_strength_curve(&curve).unwrap();
+        engine.reset();
+        let shaped = masks(&mut engine);
+
+        let mean = |masks: &[Vec<f32>], bins: std::ops::Range<usize>| {
+            let len = bins.len();
+            masks[20..]
+                .iter()
+                .map(|mask| mask[bins.clone()].iter().sum::<f32>() / len as f32)
+                .sum::<f32>()
+                / (masks.len() - 20) as f32
+        };
+        // Out of the speech band the noise is suppressed harder, in it the tone is kept.
+        assert!(mean(&shaped, 128..DTLN_FFT_OUT_SIZE) < mean(&flat, 128..DTLN_FFT_OUT_SIZE));
+        assert!(mean(&shaped, 0..65) > mean(&flat, 0..65));
+        for (flat, shaped) in flat.iter().zip(shaped.iter()) {
+            for bin in 0..DTLN_FFT_OUT_SIZE {
+                assert!((flat[bin].powf(curve[bin]) - shaped[bin]).abs() < 1e-5);
+            }
+        }
+
+        engine.clear_strength_curve();
+        engine.reset();
+        assert_eq!(masks(&mut engine), flat);
+    }
+
+    #[test]
+    fn test_scratch_buffers() {
+        let samples = (0..16384)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+
+        let scratch = |real: usize, complex: usize| ScratchBuffers {
+            real: Box::leak(vec![0.0; real].into_boxed_slice()),
+            complex: Box::leak(vec![Complex::new(0.0, 0.0); complex].into_boxed_slice()),
+        };
+        let sizes = DtlnEngine::new().unwrap().info().scratch_sizes();
+        assert_eq!(sizes.real, ScratchBuffers::REAL_LEN);
+        assert_eq!(sizes.complex, ScratchBuffers::COMPLEX_LEN);
+
+        let mut default_engine = DtlnEngine::new().unwrap();
+        let mut scratch_engine =
+            DtlnEngine::with_scratch(scratch(sizes.real, sizes.complex)).unwrap();
+        let mut default_out = vec![0.0; samples.len()];
+        let mut scratch_out = vec![0.0; samples.len()];
+        default_engine.denoise(&samples, &mut default_out);
+        scratch_engine.denoise(&samples, &mut scratch_out);
+        assert_eq!(default_out, scratch_out);
+
+        assert!(DtlnEngine::with_scratch(scratch(sizes.real - 1, sizes.complex)).is_err());
+        assert!(DtlnEngine::with_scratch(scratch(sizes.real, sizes.complex - 1)).is_err());
+    }
+
+    #[test]
+    fn test_overlap_passthrough() {
+        let samples = frame_periodic_noise(DTLN_BLOCK_LEN * 16);
+        let identity = [1.0; DTLN_FFT_OUT_SIZE];
+
+        for ratio in Overlap::SUPPORTED {
+            let overlap = Overlap::from_ratio(ratio).unwrap();
+            assert_eq!(overlap.ratio(), ratio);
+            let block_shift = overlap.block_shift();
+
+            // The engine's analysis and synthesis around an identity mask, COLA gain included.
+            let mut engine = DtlnEngine::with_overlap(overlap).unwrap();
+            engine.set_stage2_enabled(false);
+            let mut out = vec![0.0; samples.len()];
+            for (block, out) in samples.chunks(block_shift).zip(out.chunks_mut(block_shift)) {
+                engine.denoise_with_mask(block, &identity, out);
+            }
+
+            // Once no frame the output overlaps starts before the input, the output is the
+            // input delayed by the latency.
+            for n in DTLN_BLOCK_LEN + overlap.latency()..samples.len() {
+                assert!((out[n] - samples[n - overlap.latency()]).abs() < 1e-4);
+            }
+        }
+
+        assert_eq!(Overlap::default().block_shift(), DTLN_BLOCK_SHIFT);
+        assert_eq!(Overlap::from_ratio(0.5).unwrap().blocks_per_second(), 62.5);
+        for ratio in [0.0, 0.6, 2.0 / 3.0, 1.0, f32::NAN] {
+            assert!(Overlap::from_ratio(ratio).is_err());
+        }
+    }
+
+    #[test]
+    fn test_preemphasis() {
+        let samples = (0..DTLN_BLOCK_SHIFT * 40)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+        let mut engine = DtlnEngine::new().unwrap();
+        assert_eq!(engine.preemphasis(), 0.0);
+        for coeff in [-0.1, 1.0, f32::NAN] {
+            assert!(engine.set_preemphasis(coeff).is_err());
+        }
+        engine.set_preemphasis(0.97).unwrap();
+
+        // With the dry mix, the de-emphasis undoes the pre-emphasis exactly.
+        engine.set_mix(0.0);
+        let mut out = vec![0.0; samples.len()];
+        engine.denoise(&samples, &mut out);
+        for n in DTLN_LATENCY..samples.len() {
+            assert!((out[n] - samples[n - DTLN_LATENCY]).abs() < 1e-5);
+        }
+
+        // The speech and noise still sum back to the input.
+        engine.set_mix(1.0);
+        engine.reset();
+        let mut noise = vec![0.0; samples.len()];
+        engine.denoise_split(&samples, &mut out, &mut noise);
+        for n in DTLN_LATENCY..samples.len() {
+            let restored = out[n] + noise[n];
+            assert!((restored - samples[n - DTLN_LATENCY]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_input_agc() {
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+        // Seeded: the two start out at different levels until the AGC settles, and for
+        // some noise the model's state then locks onto keeping the steady tone in one of
+        // them, so the denoised levels compared below must not depend on the run.
+        let noise = generate_test_signal(
+            TestSignal::WhiteNoise { seed: 194 },
+            Duration::from_secs(4),
+            DTLN_SAMPLE_RATE,
+        );
+        let base = noise
+            .iter()
+            .enumerate()
+            .map(|(n, noise)| {
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                0.3 * (2.0 * std::f32::consts::PI * 300.0 * t).sin() + noise * 0.2
+            })
+            .collect::<Vec<f32>>();
+        let mut engine = DtlnEngine::new().unwrap();
+        assert_eq!(engine.input_agc(), None);
+        let invalid = [
+            AgcConfig {
+                attack_ms: 0.0,
+                ..Default::default()
+            },
+            AgcConfig {
+                max_gain_db: -1.0,
+                ..Default::default()
+            },
+            AgcConfig {
+                target_dbfs: f32::NAN,
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(engine.set_input_agc(Some(config)).is_err());
+        }
+        let config = AgcConfig::default();
+        let target = 10f32.powf(config.target_dbfs / 20.0);
+
+        // Two recordings 26 dB apart.
+        let mut denoised = vec![];
+        for level in [0.05, 1.0] {
+            let input = base.iter().map(|x| x * level).collect::<Vec<f32>>();
+            let mut engine = DtlnEngine::new().unwrap();
+            engine.set_input_agc(Some(config)).unwrap();
+            let mut out = vec![0.0; input.len()];
+
+            // Passed through, the output is the input at its own level.
+            engine.set_mix(0.0);
+            engine.denoise(&input, &mut out);
+            for n in DTLN_LATENCY..input.len() {
+                assert!((out[n] - input[n - DTLN_LATENCY]).abs() < 1e-4 * level);
+            }
+            // While the models were fed the target level.
+            let fed = 20.0 * (rms(&engine.in_buffer) / target).log10();
+            assert!(fed.abs() < 1.0, "{} dB off the target at {}", fed, level);
+
+            engine.set_mix(1.0);
+            engine.reset();
+            engine.denoise(&input, &mut out);
+            let output_level = rms(&out[DTLN_SAMPLE_RATE as usize * 3..]);
+            assert!(output_level < level * 0.3, "{} at {}", output_level, level);
+            denoised.push(out.iter().map(|x| x / level).collect::<Vec<f32>>());
+        }
+
+        // Normalized alike, the two are denoised to the same level, relative to their own,
+        // once the AGC has settled.
+        let settled = DTLN_SAMPLE_RATE as usize * 3;
+        let difference =
+            20.0 * (rms(&denoised[0][settled..]) / rms(&denoised[1][settled..])).log10();
+        assert!(difference.abs() < 1.0, "{} dB", difference);
+    }
+
+    #[test]
+    fn test_stage2_disabled() {
+        let samples = frame_periodic_noise(DTLN_BLOCK_SHIFT * 200);
+        let mut out = vec![0.0; samples.len()];
+        let mut engine = DtlnEngine::new().unwrap();
+        assert!(engine.stage2_enabled());
+
+        let mut time = |engine: &mut DtlnEngine| {
+            engine.reset();
+            let start = std::time::Instant::now();
+            engine.denoise(&samples, &mut out);
+            start.elapsed()
+        };
+        let both = time(&mut engine);
+        engine.set_stage2_enabled(false);
+        let stage1 = time(&mut engine);
+        assert!(stage1 < both, "{:?} vs {:?}", stage1, both);
+
+        // The output is the first stage's reconstruction with the model 1 mask.
+        engine.reset();
+        engine.denoise(&samples, &mut out);
+        let mut masks = DtlnEngine::new().unwrap();
+        let mut reconstruction = DtlnEngine::new().unwrap();
+        reconstruction.set_stage2_enabled(false);
+        let mut expected = vec![0.0; samples.len()];
+        for (block, expected) in samples
+            .chunks(DTLN_BLOCK_SHIFT)
+            .zip(expected.chunks_mut(DTLN_BLOCK_SHIFT))
+        {
+            let mask = masks.estimate_mask(block).to_vec();
+            reconstruction.denoise_with_mask(block, &mask, expected);
+        }
+        for (a, b) in out.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+
+        // With an identity mask, that is the input delayed by the latency, once the frames
+        // it overlaps no longer start in the silence before the input.
+        reconstruction.reset();
+        let identity = [1.0; DTLN_FFT_OUT_SIZE];
+        for (block, out) in samples
+            .chunks(DTLN_BLOCK_SHIFT)
+            .zip(out.chunks_mut(DTLN_BLOCK_SHIFT))
+        {
+            reconstruction.denoise_with_mask(block, &identity, out);
+        }
+        for n in DTLN_LATENCY + DTLN_BLOCK_LEN..samples.len() {
+            assert!((out[n] - samples[n - DTLN_LATENCY]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_overlap_save() {
+        let samples = frame_periodic_noise(DTLN_BLOCK_SHIFT * 40);
+        let identity = [1.0; DTLN_FFT_OUT_SIZE];
+        let passthrough = |overlap: Overlap| {
+            let mut engine = DtlnEngine::with_overlap(overlap).unwrap();
+            engine.set_stage2_enabled(false);
+            let block_shift = overlap.block_shift();
+            let mut out = vec![0.0; samples.len()];
+            for (block, out) in samples.chunks(block_shift).zip(out.chunks_mut(block_shift)) {
+                engine.denoise_with_mask(block, &identity, out);
+            }
+            out
+        };
+
+        for ratio in Overlap::SUPPORTED {
+            let add = Overlap::from_ratio(ratio).unwrap();
+            let save = add.with_mode(OverlapMode::Save);
+            assert_eq!(add.mode(), OverlapMode::Add);
+            assert_eq!(add.latency(), DTLN_BLOCK_LEN - add.block_shift());
+            assert_eq!(save.latency(), 0);
+
+            // Overlap-add is exact once no frame the output overlaps starts before the input.
+            let out = passthrough(add);
+            for n in DTLN_BLOCK_LEN + add.latency()..samples.len() {
+                assert!((out[n] - samples[n - add.latency()]).abs() < 1e-4);
+            }
+            // Overlap-save keeps each frame's newest block, exact from the first full frame.
+            let out = passthrough(save);
+            for n in DTLN_BLOCK_LEN..samples.len() {
+                assert!((out[n] - samples[n]).abs() < 1e-4);
+            }
+        }
+
+        // With the models, the output stays finite and at a comparable level.
+        let mut engine =
+            DtlnEngine::with_overlap(Overlap::default().with_mode(OverlapMode::Save)).unwrap();
+        assert_eq!(engine.overlap().latency(), 0);
+        let mut out = vec![0.0; samples.len()];
+        engine.denoise(&samples, &mut out);
+        assert!(out.iter().all(|s| s.is_finite()));
+        let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+        assert!(energy(&out) < energy(&samples) * 2.0);
+    }
+
+    #[test]
+    fn test_freeze_noise() {
+        let noise = (0..DTLN_BLOCK_SHIFT * NOISE_ADAPTATION_BLOCKS * 4)
+            .map(|_| (rand::random::<f32>() - 0.5) * 0.3)
+            .collect::<Vec<f32>>();
+        let (learn, measure) = noise.split_at(DTLN_BLOCK_SHIFT * NOISE_ADAPTATION_BLOCKS * 2);
+
+        // Mean over bins of the variance of the applied mask across blocks.
+        let mask_variance = |engine: &mut DtlnEngine| {
+            let mut out = vec![0.0; learn.len()];
+            engine.denoise(learn, &mut out);
+            let masks = measure
+                .chunks(DTLN_BLOCK_SHIFT)
+                .map(|block| {
+                    engine.denoise(block, &mut out[..DTLN_BLOCK_SHIFT]);
+                    engine.applied_mask
+                })
+                .collect::<Vec<_>>();
+            (0..DTLN_FFT_OUT_SIZE)
+                .map(|bin| {
+                    let mean = masks.iter().map(|mask| mask[bin]).sum::<f32>() / masks.len() as f32;
+                    masks
+                        .iter()
+                        .map(|mask| (mask[bin] - mean).powi(2))
+                        .sum::<f32>()
+                        / masks.len() as f32
+                })
+                .sum::<f32>()
+                / DTLN_FFT_OUT_SIZE as f32
+        };
+
+        let mut adaptive = DtlnEngine::new().unwrap();
+        let mut frozen = DtlnEngine::new().unwrap();
+        frozen.freeze_noise();
+        // Too early for the freeze to take effect, the profile is still learning.
+        assert!(!frozen.noise_profile.applies());
+        assert!(mask_variance(&mut frozen) < mask_variance(&mut adaptive));
+        assert!(frozen.noise_profile.applies());
+
+        frozen.unfreeze_noise();
+        assert!(!frozen.is_noise_frozen());
+    }
+
+    #[test]
+    fn test_cascade() {
+        let clean = (0..DTLN_SAMPLE_RATE as usize * 3)
+            .map(|n| {
+                // Rising chirp, so there is a single best alignment.
+                let t = n as f32 / DTLN_SAMPLE_RATE as f32;
+                0.3 * (2.0 * std::f32::consts::PI * (200.0 + 300.0 * t) * t).sin()
+            })
+            .collect::<Vec<f32>>();
+        let noise = (0..clean.len())
+            .map(|_| (rand::random::<f32>() - 0.5) * 1.5)
+            .collect::<Vec<f32>>();
+        let input = clean
+            .iter()
+            .zip(noise.iter())
+            .map(|(c, n)| c + n)
+            .collect::<Vec<f32>>();
+
+        let run = |passes: usize, samples: &[f32]| {
+            let mut cascade = Cascade::new(passes).unwrap();
+            assert_eq!(cascade.passes(), passes);
+            assert_eq!(cascade.latency(), DTLN_LATENCY);
+            let mut out = vec![0.0; samples.len()];
+            cascade.denoise(samples, &mut out).unwrap();
+            out
+        };
+        let energy = |samples: &[f32]| {
+            samples[DTLN_SAMPLE_RATE as usize..]
+                .iter()
+                .map(|s| s * s)
+                .sum::<f32>()
+        };
+
+        // Noise alone: the second pass removes more of it.
+        let once = energy(&run(1, &noise));
+        let twice = energy(&run(2, &noise));
+        assert!(twice < once, "{} vs {}", twice, once);
+
+        // The chirp comes out at the same delay whatever the number of passes.
+        let best_lag = |output: &[f32]| {
+            (0..1024)
+                .max_by(|a, b| {
+                    let correlation = |lag: usize| {
+                        clean[..clean.len() - lag]
+                            .iter()
+                            .zip(output[lag..].iter())
+                            .map(|(c, o)| c * o)
+                            .sum::<f32>()
+                    };
+                    correlation(*a).total_cmp(&correlation(*b))
+                })
+                .unwrap()
+        };
+        let single_lag = best_lag(&run(1, &input));
+        let cascade_lag = best_lag(&run(2, &input));
+        assert!(
+            single_lag.abs_diff(cascade_lag) <= 8,
+            "{} vs {}",
+            single_lag,
+            cascade_lag
+        );
+
+        assert!(Cascade::new(0).is_err());
+        assert!(Cascade::new(MAX_CASCADE_PASSES + 1).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+// Error type shared by the processors and the FFI/NEON layers.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DtlnError {
+    // A mutex guarding processor state was poisoned by a panicking thread.
+    Poisoned,
+    // The processor thread has stopped and can't accept more samples.
+    Stopped,
+    // The engine failed to process the samples.
+    Processing(String),
+    // The input file doesn't exist.
+    NotFound(String),
+    // The input file exists but can't be opened or read, with the reason.
+    Unreadable(String, std::io::ErrorKind),
+    // The input isn't a RIFF/WAVE file.
+    NotWav(String),
+    // The WAV file uses a format the reader doesn't handle.
+    UnsupportedFormat(String),
+    // The WAV file ends before its declared chunks do.
+    Truncated(String),
+}
+
+impl fmt::Display for DtlnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DtlnError::Poisoned => write!(f, "Processor state poisoned by a panicked thread"),
+            DtlnError::Stopped => write!(f, "Processor thread has stopped"),
+            DtlnError::Processing(message) => write!(f, "Error in processor: {}", message),
+            DtlnError::NotFound(path) => write!(f, "File {} not found", path),
+            DtlnError::Unreadable(path, kind) => write!(f, "File {} can't be read: {}", path, kind),
+            DtlnError::NotWav(path) => write!(f, "File {} is not a wav file", path),
+            DtlnError::UnsupportedFormat(details) => {
+                write!(f, "Unsupported wav format, {}", details)
+            }
+            DtlnError::Truncated(path) => write!(f, "File {} is truncated", path),
+        }
+    }
+}
+
+impl std::error::Error for DtlnError {}
+
+impl DtlnError {
+    // The error for failing to open or read `path`, keeping the reason unless the file
+    // is simply missing.
+    pub fn from_io(path: &str, error: &std::io::Error) -> DtlnError {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => DtlnError::NotFound(path.to_string()),
+            kind => DtlnError::Unreadable(path.to_string(), kind),
+        }
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for DtlnError {
+    fn from(_: std::sync::PoisonError<T>) -> Self {
+        DtlnError::Poisoned
+    }
+}
@@ -0,0 +1,332 @@
+// FFT backends for the DTLN engine.
+//
+// The engine only needs a real forward transform of DTLN_BLOCK_LEN samples into
+// DTLN_FFT_OUT_SIZE bins and the matching inverse. The default backend is realfft, behind
+// the default `fft-realfft` feature. The `fft-rustfft` feature switches the engine to a
+// plain complex rustfft transform for targets where realfft isn't wanted, as does
+// building without `fft-realfft`, which also drops the realfft dependency.
+use std::sync::{Arc, OnceLock};
+
+use num::Complex;
+#[cfg(feature = "fft-realfft")]
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use rustfft::FftPlanner;
+
+use crate::constants::*;
+
+pub trait Fft: Send {
+    // Transforms DTLN_BLOCK_LEN real samples into DTLN_FFT_OUT_SIZE bins. `input` may be
+    // used as scratch space.
+    fn forward(&mut self, input: &mut [f32], spectrum: &mut [Complex<f32>]);
+    // Inverse of forward, without normalization: the output is scaled by DTLN_BLOCK_LEN.
+    // The imaginary parts of the DC and Nyquist bins must be zero.
+    fn inverse(&mut self, spectrum: &mut [Complex<f32>], output: &mut [f32]);
+    // Bytes of working memory the backend holds, not counting plans shared between
+    // backends. An estimate, see DtlnEngine::memory_footprint.
+    fn memory_footprint(&self) -> usize {
+        0
+    }
+    // Another backend of the same kind for a new engine, sharing this one's plans, see
+    // DtlnImmediateProcessor::swap_model. Defaults to default_fft().
+    fn duplicate(&self) -> Box<dyn Fft> {
+        default_fft()
+    }
+}
+
+#[cfg(feature = "fft-realfft")]
+pub struct RealFftBackend {
+    r2c: Arc<dyn RealToComplex<f32>>,
+    c2r: Arc<dyn ComplexToReal<f32>>,
+    // Preallocated so transforms never allocate.
+    r2c_scratch: Vec<Complex<f32>>,
+    c2r_scratch: Vec<Complex<f32>>,
+}
+
+#[cfg(feature = "fft-realfft")]
+impl RealFftBackend {
+    pub fn new() -> Self {
+        let (r2c, c2r) = plan_real();
+        RealFftBackend::with_plans(r2c, c2r)
+    }
+
+    fn with_plans(r2c: Arc<dyn RealToComplex<f32>>, c2r: Arc<dyn ComplexToReal<f32>>) -> Self {
+        RealFftBackend {
+            r2c_scratch: r2c.make_scratch_vec(),
+            c2r_scratch: c2r.make_scratch_vec(),
+            r2c,
+            c2r,
+        }
+    }
+}
+
+#[cfg(feature = "fft-realfft")]
+impl Default for RealFftBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "fft-realfft")]
+impl Fft for RealFftBackend {
+    fn forward(&mut self, input: &mut [f32], spectrum: &mut [Complex<f32>]) {
+        self.r2c
+            .process_with_scratch(input, spectrum, &mut self.r2c_scratch)
+            .unwrap();
+    }
+
+    fn inverse(&mut self, spectrum: &mut [Complex<f32>], output: &mut [f32]) {
+        self.c2r
+            .process_with_scratch(spectrum, output, &mut self.c2r_scratch)
+            .unwrap();
+    }
+
+    fn memory_footprint(&self) -> usize {
+        (self.r2c_scratch.capacity() + self.c2r_scratch.capacity())
+            * std::mem::size_of::<Complex<f32>>()
+    }
+
+    fn duplicate(&self) -> Box<dyn Fft> {
+        Box::new(RealFftBackend::with_plans(
+            self.r2c.clone(),
+            self.c2r.clone(),
+        ))
+    }
+}
+
+// Full length complex transform, with the hermitian half of the spectrum rebuilt for the
+// inverse.
+pub struct RustFftBackend {
+    forward: Arc<dyn rustfft::Fft<f32>>,
+    inverse: Arc<dyn rustfft::Fft<f32>>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl RustFftBackend {
+    pub fn new() -> Self {
+        let (forward, inverse) = plan_complex();
+        RustFftBackend::with_plans(forward, inverse)
+    }
+
+    fn with_plans(
+        forward: Arc<dyn rustfft::Fft<f32>>,
+        inverse: Arc<dyn rustfft::Fft<f32>>,
+    ) -> Self {
+        let scratch_len = forward
+            .get_inplace_scratch_len()
+            .max(inverse.get_inplace_scratch_len());
+        RustFftBackend {
+            forward,
+            inverse,
+            buffer: vec![Complex::new(0.0, 0.0); DTLN_BLOCK_LEN],
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+        }
+    }
+}
+
+impl Default for RustFftBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fft for RustFftBackend {
+    fn forward(&mut self, input: &mut [f32], spectrum: &mut [Complex<f32>]) {
+        for (bin, sample) in self.buffer.iter_mut().zip(input.iter()) {
+            *bin = Complex::new(*sample, 0.0);
+        }
+        self.forward
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        spectrum[..DTLN_FFT_OUT_SIZE].copy_from_slice(&self.buffer[..DTLN_FFT_OUT_SIZE]);
+    }
+
+    fn inverse(&mut self, spectrum: &mut [Complex<f32>], output: &mut [f32]) {
+        self.buffer[..DTLN_FFT_OUT_SIZE].copy_from_slice(&spectrum[..DTLN_FFT_OUT_SIZE]);
+        for k in DTLN_FFT_OUT_SIZE..DTLN_BLOCK_LEN {
+            self.buffer[k] = spectrum[DTLN_BLOCK_LEN - k].conj();
+        }
+        self.inverse
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        for (sample, bin) in output.iter_mut().zip(self.buffer.iter()) {
+            *sample = bin.re;
+        }
+    }
+
+    fn memory_footprint(&self) -> usize {
+        (self.buffer.capacity() + self.scratch.capacity()) * std::mem::size_of::<Complex<f32>>()
+    }
+
+    fn duplicate(&self) -> Box<dyn Fft> {
+        Box::new(RustFftBackend::with_plans(
+            self.forward.clone(),
+            self.inverse.clone(),
+        ))
+    }
+}
+
+#[cfg(feature = "fft-realfft")]
+type RealPlans = (Arc<dyn RealToComplex<f32>>, Arc<dyn ComplexToReal<f32>>);
+type ComplexPlans = (Arc<dyn rustfft::Fft<f32>>, Arc<dyn rustfft::Fft<f32>>);
+
+#[cfg(feature = "fft-realfft")]
+fn plan_real() -> RealPlans {
+    let mut planner = RealFftPlanner::<f32>::new();
+    (
+        planner.plan_fft_forward(DTLN_BLOCK_LEN),
+        planner.plan_fft_inverse(DTLN_BLOCK_LEN),
+    )
+}
+
+fn plan_complex() -> ComplexPlans {
+    let mut planner = FftPlanner::<f32>::new();
+    (
+        planner.plan_fft_forward(DTLN_BLOCK_LEN),
+        planner.plan_fft_inverse(DTLN_BLOCK_LEN),
+    )
+}
+
+/**
+ * Plans shared between engines, so creating many of them, e.g. one per request in a
+ * server, plans the transforms once instead of once per engine. Clones share the same
+ * plans. Each backend is planned on first use; the engine only ever uses transforms of
+ * DTLN_BLOCK_LEN, so there is one plan pair per backend.
+ *
+ * The cache is Send and Sync and clones are cheap, so one can be handed to every
+ * thread. Plans are immutable once built and only read by the transforms; each backend
+ * made from the cache still has its own scratch buffers, so engines never contend on
+ * it while processing.
+ */
+#[derive(Clone, Default)]
+pub struct FftPlanCache {
+    plans: Arc<CachedPlans>,
+}
+
+#[derive(Default)]
+struct CachedPlans {
+    #[cfg(feature = "fft-realfft")]
+    real: OnceLock<RealPlans>,
+    complex: OnceLock<ComplexPlans>,
+}
+
+impl FftPlanCache {
+    pub fn new() -> Self {
+        FftPlanCache::default()
+    }
+
+    // A realfft backend using the cached plans.
+    #[cfg(feature = "fft-realfft")]
+    pub fn real_fft(&self) -> RealFftBackend {
+        let (r2c, c2r) = self.plans.real.get_or_init(plan_real);
+        RealFftBackend::with_plans(r2c.clone(), c2r.clone())
+    }
+
+    // A rustfft backend using the cached plans.
+    pub fn rust_fft(&self) -> RustFftBackend {
+        let (forward, inverse) = self.plans.complex.get_or_init(plan_complex);
+        RustFftBackend::with_plans(forward.clone(), inverse.clone())
+    }
+
+    // The backend default_fft selects, using the cached plans.
+    #[cfg(all(feature = "fft-realfft", not(feature = "fft-rustfft")))]
+    pub fn fft(&self) -> Box<dyn Fft> {
+        Box::new(self.real_fft())
+    }
+
+    #[cfg(any(not(feature = "fft-realfft"), feature = "fft-rustfft"))]
+    pub fn fft(&self) -> Box<dyn Fft> {
+        Box::new(self.rust_fft())
+    }
+}
+
+// The backend used by DtlnEngine::new.
+#[cfg(all(feature = "fft-realfft", not(feature = "fft-rustfft")))]
+pub fn default_fft() -> Box<dyn Fft> {
+    Box::new(RealFftBackend::new())
+}
+
+#[cfg(any(not(feature = "fft-realfft"), feature = "fft-rustfft"))]
+pub fn default_fft() -> Box<dyn Fft> {
+    Box::new(RustFftBackend::new())
+}
+
+// Both backends are compared, so the tests need realfft.
+#[cfg(all(test, feature = "fft-realfft"))]
+mod tests {
+    use super::*;
+
+    fn round_trip(fft: &mut dyn Fft, input: &[f32]) -> (Vec<Complex<f32>>, Vec<f32>) {
+        let mut scratch = input.to_vec();
+        let mut spectrum = vec![Complex::new(0.0, 0.0); DTLN_FFT_OUT_SIZE];
+        fft.forward(&mut scratch, &mut spectrum);
+        let forward = spectrum.clone();
+
+        let mut output = vec![0.0; DTLN_BLOCK_LEN];
+        fft.inverse(&mut spectrum, &mut output);
+        (forward, output)
+    }
+
+    #[test]
+    fn test_backends_match() {
+        let input = (0..DTLN_BLOCK_LEN)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+
+        let (real_spectrum, real_output) = round_trip(&mut RealFftBackend::new(), &input);
+        let (rust_spectrum, rust_output) = round_trip(&mut RustFftBackend::new(), &input);
+
+        for (a, b) in real_spectrum.iter().zip(rust_spectrum.iter()) {
+            assert!((a - b).norm() < 1e-3);
+        }
+        for ((a, b), x) in real_output.iter().zip(rust_output.iter()).zip(input.iter()) {
+            assert!((a - b).abs() < 1e-3);
+            // Inverse is unnormalized.
+            assert!((a / DTLN_BLOCK_LEN as f32 - x).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_plan_cache() {
+        let input = (0..DTLN_BLOCK_LEN)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+        let cache = FftPlanCache::new();
+        let shared = cache.clone();
+        assert_eq!(
+            round_trip(&mut cache.real_fft(), &input),
+            round_trip(&mut RealFftBackend::new(), &input)
+        );
+        assert_eq!(
+            round_trip(&mut shared.rust_fft(), &input),
+            round_trip(&mut RustFftBackend::new(), &input)
+        );
+
+        // Backends from the cache and its clones share one plan pair per backend kind,
+        // which fresh backends don't.
+        let (real, real_shared) = (cache.real_fft(), shared.real_fft());
+        assert!(std::ptr::addr_eq(
+            Arc::as_ptr(&real.r2c),
+            Arc::as_ptr(&real_shared.r2c)
+        ));
+        assert!(std::ptr::addr_eq(
+            Arc::as_ptr(&real.c2r),
+            Arc::as_ptr(&real_shared.c2r)
+        ));
+        assert!(!std::ptr::addr_eq(
+            Arc::as_ptr(&real.r2c),
+            Arc::as_ptr(&RealFftBackend::new().r2c)
+        ));
+        let (complex, complex_shared) = (shared.rust_fft(), cache.rust_fft());
+        assert!(std::ptr::addr_eq(
+            Arc::as_ptr(&complex.forward),
+            Arc::as_ptr(&complex_shared.forward)
+        ));
+        assert!(std::ptr::addr_eq(
+            Arc::as_ptr(&complex.inverse),
+            Arc::as_ptr(&complex_shared.inverse)
+        ));
+        assert!(!std::ptr::addr_eq(
+            Arc::as_ptr(&complex.forward),
+            Arc::as_ptr(&RustFftBackend::new().forward)
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+// Logging macros used throughout the crate.
+//
+// With the `log` feature, events go to the `log` crate under the "dtln" target, so the
+// host's logger decides what is kept. Without it nothing is pulled in: debug and info
+// events are dropped, and warnings and errors are printed to stderr as they always were.
+
+macro_rules! dtln_debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::debug!(target: "dtln", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+macro_rules! dtln_info {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::info!(target: "dtln", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+macro_rules! dtln_warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::warn!(target: "dtln", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        eprintln!("[DTLN] {}", format_args!($($arg)+));
+    }};
+}
+
+macro_rules! dtln_error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::error!(target: "dtln", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        eprintln!("[DTLN] {}", format_args!($($arg)+));
+    }};
+}
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use std::sync::{Arc, Mutex, Once};
+    use std::thread::ThreadId;
+
+    use log::{Level, Log, Metadata, Record};
+
+    use crate::dtln_processor::{
+        request_thread_priority, DtlnDeferredProcessor, DtlnProcessEngine, ThreadPriority,
+    };
+
+    // Records every event, tagged with the thread that logged it since tests run in
+    // parallel against the one global logger.
+    struct CapturingLogger {
+        events: Mutex<Vec<(ThreadId, Level, String)>>,
+    }
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == "dtln"
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                self.events.lock().unwrap().push((
+                    std::thread::current().id(),
+                    record.level(),
+                    record.args().to_string(),
+                ));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger {
+        events: Mutex::new(vec![]),
+    };
+
+    // Events logged so far by the current thread.
+    fn captured() -> Vec<(Level, String)> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        let thread = std::thread::current().id();
+        LOGGER
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _, _)| *id == thread)
+            .map(|(_, level, message)| (*level, message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_log_events() -> anyhow::Result<()> {
+        captured();
+
+        // An executor that never runs its jobs starves the processor.
+        let mut processor = DtlnDeferredProcessor::with_executor(Arc::new(|_job| {}))?;
+        assert!(captured()
+            .iter()
+            .any(|(level, message)| *level == Level::Info && message.contains("Created engine")));
+        for _ in 0..3 {
+            processor.denoise(&[0.0; 128])?;
+        }
+        processor.stop();
+        let starved = captured()
+            .into_iter()
+            .filter(|(level, message)| *level == Level::Warn && message.contains("starved"))
+            .count();
+        // Only the start of the starvation run is reported.
+        assert_eq!(starved, 1);
+
+        request_thread_priority(ThreadPriority::Elevated, || false);
+        assert!(captured()
+            .iter()
+            .any(|(level, message)| *level == Level::Warn && message.contains("priority")));
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+// Metadata read from the TFLite flatbuffers the engine is built from.
+//
+// Only the few fields needed for provenance are read, straight from the flatbuffer
+// layout, so this doesn't need the TFLite schema bindings. Anything missing or malformed
+// is reported as absent rather than as an error.
+use std::fmt;
+
+use crate::dtln_scratch::ScratchBuffers;
+
+// Model table fields, by their index in the TFLite schema.
+const MODEL_VERSION_FIELD: usize = 0;
+const MODEL_SUBGRAPHS_FIELD: usize = 2;
+const MODEL_DESCRIPTION_FIELD: usize = 3;
+const MODEL_BUFFERS_FIELD: usize = 4;
+const MODEL_METADATA_FIELD: usize = 6;
+// Metadata and Buffer table fields.
+const METADATA_NAME_FIELD: usize = 0;
+const METADATA_BUFFER_FIELD: usize = 1;
+const BUFFER_DATA_FIELD: usize = 0;
+// SubGraph and Tensor table fields.
+const SUBGRAPH_TENSORS_FIELD: usize = 0;
+const TENSOR_SHAPE_FIELD: usize = 0;
+const TENSOR_TYPE_FIELD: usize = 1;
+const TENSOR_BUFFER_FIELD: usize = 2;
+
+const MIN_RUNTIME_VERSION_KEY: &str = "min_runtime_version";
+
+// Shown in place of a description when a model has none.
+pub const UNKNOWN_MODEL_DESCRIPTION: &str = "unknown";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelInfo {
+    // TFLite schema version, 0 when unreadable.
+    pub version: u32,
+    // Free form description set by the converter or the model author.
+    pub description: Option<String>,
+    // Oldest TFLite runtime the converter says can run the model.
+    pub min_runtime_version: Option<String>,
+    // Bytes of the tensors without constant data (activations and state). The
+    // interpreter allocates these in its arena, reusing memory between them, so this is
+    // an upper bound of the arena size. 0 when unreadable.
+    pub tensor_arena_size: usize,
+}
+
+impl ModelInfo {
+    /**
+     * Reads the metadata of a TFLite model. Returns defaults for fields that are missing,
+     * or for everything if `model` isn't a TFLite flatbuffer.
+     */
+    pub fn from_tflite(model: &[u8]) -> ModelInfo {
+        let reader = FlatBuffer(model);
+        let Some(root) = reader.root() else {
+            return ModelInfo::default();
+        };
+
+        ModelInfo {
+            version: reader
+                .field(root, MODEL_VERSION_FIELD)
+                .and_then(|pos| reader.u32(pos))
+                .unwrap_or(0),
+            description: reader
+                .field(root, MODEL_DESCRIPTION_FIELD)
+                .and_then(|pos| reader.string(pos)),
+            min_runtime_version: reader.metadata(root, MIN_RUNTIME_VERSION_KEY),
+            tensor_arena_size: reader.tensor_arena_size(root).unwrap_or(0),
+        }
+    }
+}
+
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (schema v{}, runtime >= {})",
+            self.description
+                .as_deref()
+                .unwrap_or(UNKNOWN_MODEL_DESCRIPTION),
+            self.version,
+            self.min_runtime_version
+                .as_deref()
+                .unwrap_or(UNKNOWN_MODEL_DESCRIPTION)
+        )
+    }
+}
+
+// Minimum lengths of the ScratchBuffers an engine needs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScratchSizes {
+    pub real: usize,
+    pub complex: usize,
+}
+
+// Metadata of the two models an engine runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EngineInfo {
+    pub model_1: ModelInfo,
+    pub model_2: ModelInfo,
+}
+
+impl EngineInfo {
+    pub fn from_tflite(model_1: &[u8], model_2: &[u8]) -> EngineInfo {
+        EngineInfo {
+            model_1: ModelInfo::from_tflite(model_1),
+            model_2: ModelInfo::from_tflite(model_2),
+        }
+    }
+
+    // Scratch memory required by DtlnEngine::with_scratch.
+    pub fn scratch_sizes(&self) -> ScratchSizes {
+        ScratchSizes {
+            real: ScratchBuffers::REAL_LEN,
+            complex: ScratchBuffers::COMPLEX_LEN,
+        }
+    }
+
+    // See ModelInfo::tensor_arena_size.
+    pub fn tensor_arena_size(&self) -> usize {
+        self.model_1.tensor_arena_size + self.model_2.tensor_arena_size
+    }
+
+    // One line summary of both models, suitable for logs and bug reports.
+    pub fn model_description(&self) -> String {
+        format!("model 1: {}; model 2: {}", self.model_1, self.model_2)
+    }
+}
+
+// Bounds checked reads from a flatbuffer.
+struct FlatBuffer<'a>(&'a [u8]);
+
+impl FlatBuffer<'_> {
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.0.get(pos..pos.checked_add(2)?)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = self.0.get(pos..pos.checked_add(4)?)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn root(&self) -> Option<usize> {
+        if self.0.get(4..8)? != b"TFL3" {
+            return None;
+        }
+        Some(self.u32(0)? as usize)
+    }
+
+    // Position of a table field, None if it is absent.
+    fn field(&self, table: usize, field: usize) -> Option<usize> {
+        let vtable = (table as i64 - self.u32(table)? as i32 as i64)
+            .try_into()
+            .ok()?;
+        let vtable_len = self.u16(vtable)? as usize;
+        let entry = 4 + 2 * field;
+        if entry + 2 > vtable_len {
+            return None;
+        }
+        match self.u16(vtable + entry)? {
+            0 => None,
+            offset => Some(table + offset as usize),
+        }
+    }
+
+    // Follows the offset stored at `pos` to a table, vector or string.
+    fn follow(&self, pos: usize) -> Option<usize> {
+        pos.checked_add(self.u32(pos)? as usize)
+    }
+
+    fn bytes(&self, pos: usize) -> Option<&[u8]> {
+        let vector = self.follow(pos)?;
+        let len = self.u32(vector)? as usize;
+        self.0.get(vector + 4..(vector + 4).checked_add(len)?)
+    }
+
+    fn string(&self, pos: usize) -> Option<String> {
+        let bytes = self.bytes(pos)?;
+        // Buffers holding strings are often padded with NULs.
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0');
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    // Length of the vector referenced at `pos`.
+    fn len(&self, pos: usize) -> Option<usize> {
+        Some(self.u32(self.follow(pos)?)? as usize)
+    }
+
+    // Element `index` of the vector of tables referenced at `pos`.
+    fn table_at(&self, pos: usize, index: usize) -> Option<usize> {
+        let vector = self.follow(pos)?;
+        if index >= self.u32(vector)? as usize {
+            return None;
+        }
+        self.follow(vector + 4 + 4 * index)
+    }
+
+    // Contents of the model metadata entry named `key`, as a string.
+    fn metadata(&self, root: usize, key: &str) -> Option<String> {
+        let metadata = self.field(root, MODEL_METADATA_FIELD)?;
+        let count = self.len(metadata)?;
+        let entry = (0..count)
+            .filter_map(|index| self.table_at(metadata, index))
+            .find(|entry| {
+                self.field(*entry, METADATA_NAME_FIELD)
+                    .and_then(|name| self.string(name))
+                    .is_some_and(|name| name == key)
+            })?;
+
+        let buffer_index = self.u32(self.field(entry, METADATA_BUFFER_FIELD)?)? as usize;
+        let buffer = self.table_at(self.field(root, MODEL_BUFFERS_FIELD)?, buffer_index)?;
+        self.string(self.field(buffer, BUFFER_DATA_FIELD)?)
+    }
+
+    // Total size of the main subgraph's tensors that have no constant data.
+    fn tensor_arena_size(&self, root: usize) -> Option<usize> {
+        let subgraph = self.table_at(self.field(root, MODEL_SUBGRAPHS_FIELD)?, 0)?;
+        let tensors = self.field(subgraph, SUBGRAPH_TENSORS_FIELD)?;
+        let buffers = self.field(root, MODEL_BUFFERS_FIELD)?;
+        let mut size = 0;
+        for index in 0..self.len(tensors)? {
+            let tensor = self.table_at(tensors, index)?;
+            // Buffer 0 is the empty sentinel, as is a missing field.
+            let constant = self
+                .field(tensor, TENSOR_BUFFER_FIELD)
+                .and_then(|pos| self.u32(pos))
+                .and_then(|buffer| self.table_at(buffers, buffer as usize))
+                .and_then(|buffer| self.field(buffer, BUFFER_DATA_FIELD))
+                .and_then(|data| self.bytes(data))
+                .is_some_and(|data| !data.is_empty());
+            if constant {
+                continue;
+            }
+            // Unknown (-1) dimensions count as 1, a scalar has no shape.
+            let mut elements = 1;
+            if let Some(shape) = self.field(tensor, TENSOR_SHAPE_FIELD) {
+                let dims = self.follow(shape)?;
+                for dim in 0..self.len(shape)? {
+                    let len = (self.u32(dims + 4 + 4 * dim)? as i32).max(1) as usize;
+                    elements = len.checked_mul(elements)?;
+                }
+            }
+            let element_type = self
+                .field(tensor, TENSOR_TYPE_FIELD)
+                .and_then(|pos| self.0.get(pos).copied())
+                .unwrap_or(0);
+            size = elements
+                .checked_mul(tensor_element_size(element_type))?
+                .checked_add(size)?;
+        }
+        Some(size)
+    }
+}
+
+// Bytes per element of a TFLite TensorType.
+fn tensor_element_size(element_type: u8) -> usize {
+    match element_type {
+        // FLOAT16, INT16, UINT16
+        1 | 7 | 16 => 2,
+        // UINT8, BOOL, INT8, INT4 (unpacked)
+        3 | 6 | 9 | 17 => 1,
+        // INT64, COMPLEX64, FLOAT64, UINT64
+        4 | 8 | 10 | 12 => 8,
+        // COMPLEX128
+        11 => 16,
+        // FLOAT32, INT32, UINT32 and anything else
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_model_info() {
+        let info = EngineInfo::from_tflite(
+            include_bytes!("../model/model_quant_1.tflite"),
+            include_bytes!("../model/model_quant_2.tflite"),
+        );
+        for model in [&info.model_1, &info.model_2] {
+            assert_eq!(model.version, 3);
+            assert_eq!(model.description.as_deref(), Some("MLIR Converted."));
+            assert_eq!(model.min_runtime_version.as_deref(), Some("1.14.0"));
+        }
+        assert!(info.model_description().contains("1.14.0"));
+        // The activations and LSTM states, tens of kB, not the MBs of weights.
+        for model in [&info.model_1, &info.model_2] {
+            assert!(
+                (10_000..200_000).contains(&model.tensor_arena_size),
+                "{}",
+                model.tensor_arena_size
+            );
+        }
+
+        // Anything that isn't a TFLite model falls back to defaults.
+        assert_eq!(ModelInfo::from_tflite(b"not a model"), ModelInfo::default());
+        assert_eq!(ModelInfo::from_tflite(&[]), ModelInfo::default());
+        let mut truncated = include_bytes!("../model/model_quant_1.tflite")[..64].to_vec();
+        truncated[0] = 0xff;
+        let _ = ModelInfo::from_tflite(&truncated);
+        assert!(ModelInfo::default()
+            .to_string()
+            .starts_with(UNKNOWN_MODEL_DESCRIPTION));
+    }
+}
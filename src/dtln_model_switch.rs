@@ -0,0 +1,290 @@
+// Switching between two models mid-stream, e.g. one for speech and one for music.
+use anyhow::{Context, Result};
+
+use crate::constants::{DTLN_BLOCK_SHIFT, DTLN_LATENCY};
+use crate::dtln_dsp::HysteresisGate;
+use crate::dtln_engine::{dtln_create, dtln_denoise, DtlnEngine};
+
+// Index of the bundled speech model, and of the caller's general model.
+pub const SPEECH_MODEL: usize = 0;
+pub const GENERAL_MODEL: usize = 1;
+
+// Blocks the incoming model runs unheard after its reset, until its frame holds only
+// audio from after the switch.
+pub(crate) const SWITCH_WARM_UP_BLOCKS: usize = 4;
+// Blocks the crossfade from the outgoing to the incoming model takes, 32 ms.
+pub(crate) const SWITCH_FADE_BLOCKS: usize = 4;
+// Fewest blocks between automatic switches, 400 ms, so borderline content can't flap.
+const AUTO_SWITCH_HOLD_BLOCKS: usize = 50;
+
+// A switch in progress: the model switched away from and the blocks run since.
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    from: usize,
+    blocks: usize,
+}
+
+/**
+ * Denoises with one of two engines, switching between them on select_model or on the
+ * speech probability (set_auto_switch). Each engine keeps its own stream state, and the
+ * incoming one is reset before use, so nothing carries over from the audio it last saw.
+ * It then runs alongside the outgoing one for SWITCH_WARM_UP_BLOCKS blocks before the
+ * output crossfades over SWITCH_FADE_BLOCKS, 64 ms in all.
+ *
+ * Cost: the latency is DTLN_LATENCY whichever model is active. Both engines are held
+ * at all times, so memory is that of two engines: two pairs of interpreters with their
+ * tensor arenas and state. TFLite reads the weights in place, so the bundled 4 MB are
+ * only counted once if both engines use them. CPU doubles only during a switch.
+ */
+pub struct ModelSwitcher {
+    engines: [DtlnEngine; 2],
+    active: usize,
+    transition: Option<Transition>,
+    // Model to switch to once the current transition is over.
+    requested: Option<usize>,
+    // Speech gate for automatic switching, None when switching manually.
+    auto_switch: Option<HysteresisGate>,
+    blocks_since_switch: usize,
+    // Input that doesn't yet fill a whole engine block.
+    pending: Vec<f32>,
+}
+
+impl ModelSwitcher {
+    /**
+     * Creates a switcher with the bundled models as SPEECH_MODEL and `general` as
+     * GENERAL_MODEL, e.g. from DtlnEngine::with_models. Starts on SPEECH_MODEL.
+     */
+    pub fn new(general: DtlnEngine) -> Result<ModelSwitcher> {
+        let speech = dtln_create().context("Unable to create engine.")?;
+        Ok(ModelSwitcher {
+            engines: [speech, general],
+            active: SPEECH_MODEL,
+            transition: None,
+            requested: None,
+            auto_switch: None,
+            blocks_since_switch: 0,
+            pending: Vec::with_capacity(DTLN_BLOCK_SHIFT),
+        })
+    }
+
+    // Model being switched to, or in use when no switch is in progress.
+    pub fn active_model(&self) -> usize {
+        self.active
+    }
+
+    pub fn is_switching(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    // One of the engines, to configure it. None for an index other than 0 or 1.
+    pub fn engine_mut(&mut self, index: usize) -> Option<&mut DtlnEngine> {
+        self.engines.get_mut(index)
+    }
+
+    pub fn latency(&self) -> usize {
+        DTLN_LATENCY
+    }
+
+    /**
+     * Switches to model `index` from the next block. A switch requested while another
+     * is in progress starts once that one is over.
+     */
+    pub fn select_model(&mut self, index: usize) -> Result<()> {
+        if index >= self.engines.len() {
+            return Err(anyhow::anyhow!("Invalid model {}, expected 0 or 1", index));
+        }
+        if self.transition.is_some() {
+            self.requested = Some(index);
+        } else {
+            self.switch_to(index);
+        }
+        Ok(())
+    }
+
+    /**
+     * Switches automatically on the active model's speech probability: to SPEECH_MODEL
+     * once it reaches `open_threshold`, to GENERAL_MODEL once it drops below
+     * `close_threshold`, at most once every AUTO_SWITCH_HOLD_BLOCKS blocks. None (the
+     * default) leaves switching to select_model.
+     */
+    pub fn set_auto_switch(&mut self, thresholds: Option<(f32, f32)>) {
+        self.auto_switch = thresholds.map(|(open, close)| HysteresisGate::new(open, close));
+    }
+
+    /**
+     * Denoises `input` with the active model. Like DtlnImmediateProcessor::denoise,
+     * only whole engine blocks are processed and the rest is held for the next call.
+     */
+    pub fn process(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+        self.pending.extend_from_slice(input);
+        let block_samples = self.pending.len() / DTLN_BLOCK_SHIFT * DTLN_BLOCK_SHIFT;
+        let remainder = self.pending.split_off(block_samples);
+        let blocks = std::mem::replace(&mut self.pending, remainder);
+
+        let mut output = vec![0.0; block_samples];
+        let mut incoming = [0.0; DTLN_BLOCK_SHIFT];
+        for (block, out) in blocks
+            .chunks(DTLN_BLOCK_SHIFT)
+            .zip(output.chunks_mut(DTLN_BLOCK_SHIFT))
+        {
+            match self.transition {
+                Some(mut transition) => {
+                    dtln_denoise(&mut self.engines[transition.from], block, out)?;
+                    dtln_denoise(&mut self.engines[self.active], block, &mut incoming)?;
+                    crossfade(out, &incoming, transition.blocks * DTLN_BLOCK_SHIFT);
+                    transition.blocks += 1;
+                    self.transition = Some(transition);
+                    if transition.blocks == SWITCH_WARM_UP_BLOCKS + SWITCH_FADE_BLOCKS {
+                        self.transition = None;
+                        if let Some(index) = self.requested.take() {
+                            self.switch_to(index);
+                        }
+                    }
+                }
+                None => dtln_denoise(&mut self.engines[self.active], block, out)?,
+            }
+            self.blocks_since_switch += 1;
+            self.update_auto_switch();
+        }
+        Ok(output)
+    }
+
+    // Clears the state and held input of both engines, staying on the active model.
+    pub fn reset(&mut self) {
+        for engine in self.engines.iter_mut() {
+            engine.reset();
+        }
+        self.transition = None;
+        self.requested = None;
+        if let Some(gate) = self.auto_switch.as_mut() {
+            gate.reset();
+        }
+        self.blocks_since_switch = 0;
+        self.pending.clear();
+    }
+
+    fn switch_to(&mut self, index: usize) {
+        if index == self.active {
+            return;
+        }
+        dtln_debug!("Switching from model {} to {}", self.active, index);
+        self.engines[index].reset();
+        self.transition = Some(Transition {
+            from: self.active,
+            blocks: 0,
+        });
+        self.active = index;
+        self.blocks_since_switch = 0;
+    }
+
+    fn update_auto_switch(&mut self) {
+        let Some(gate) = self.auto_switch.as_mut() else {
+            return;
+        };
+        let speech = gate.update(self.engines[self.active].speech_probability());
+        if self.transition.is_some() || self.blocks_since_switch < AUTO_SWITCH_HOLD_BLOCKS {
+            return;
+        }
+        self.switch_to(if speech { SPEECH_MODEL } else { GENERAL_MODEL });
+    }
+}
+
+/**
+ * Fades `out`, the outgoing model's output, towards `incoming`, that of the model
+ * switched to `run` samples before `out` starts. `out` is kept for the first
+ * SWITCH_WARM_UP_BLOCKS blocks, then faded out over SWITCH_FADE_BLOCKS, after which
+ * `incoming` is used alone. Shared with DtlnImmediateProcessor::swap_model.
+ */
+pub(crate) fn crossfade(out: &mut [f32], incoming: &[f32], run: usize) {
+    let warm_up = SWITCH_WARM_UP_BLOCKS * DTLN_BLOCK_SHIFT;
+    let fade_len = (SWITCH_FADE_BLOCKS * DTLN_BLOCK_SHIFT) as f32;
+    for (i, (sample, incoming)) in out.iter_mut().zip(incoming).enumerate() {
+        let weight = ((run + i + 1).saturating_sub(warm_up) as f32 / fade_len).min(1.0);
+        *sample = *sample * (1.0 - weight) + incoming * weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_engine::{DtlnModel, MODEL_1};
+
+    // The bundled models with suppression off, standing in for a general model.
+    fn general_engine() -> DtlnEngine {
+        let mut engine = DtlnEngine::with_models(DtlnModel::bundled()).unwrap();
+        engine.set_suppression_strength(0.0);
+        engine
+    }
+
+    #[test]
+    fn test_model_switch() -> Result<()> {
+        let input = (0..DTLN_BLOCK_SHIFT * 120)
+            .map(|n| {
+                let t = n as f32 / 16000.0;
+                0.3 * (2.0 * std::f32::consts::PI * 300.0 * t).sin()
+                    + (rand::random::<f32>() - 0.5) * 0.05
+            })
+            .collect::<Vec<f32>>();
+        let switch_at = DTLN_BLOCK_SHIFT * 60;
+
+        let mut switcher = ModelSwitcher::new(general_engine())?;
+        assert!(switcher.select_model(2).is_err());
+        let mut output = switcher.process(&input[..switch_at])?;
+        switcher.select_model(GENERAL_MODEL)?;
+        assert!(switcher.is_switching());
+        output.extend(switcher.process(&input[switch_at..])?);
+        assert!(!switcher.is_switching());
+        assert_eq!(switcher.active_model(), GENERAL_MODEL);
+
+        // Before the switch, the speech model alone.
+        let mut speech = vec![0.0; switch_at];
+        dtln_create()
+            .unwrap()
+            .denoise(&input[..switch_at], &mut speech);
+        assert_eq!(output[..switch_at], speech);
+
+        // After the crossfade, the general model alone, from a fresh state at the switch.
+        let mut general = vec![0.0; input.len() - switch_at];
+        general_engine().denoise(&input[switch_at..], &mut general);
+        let settled = (SWITCH_WARM_UP_BLOCKS + SWITCH_FADE_BLOCKS) * DTLN_BLOCK_SHIFT;
+        assert_eq!(output[switch_at + settled..], general[settled..]);
+
+        // No step across the transition larger than in the steady output around it.
+        let steps = |samples: &[f32]| {
+            samples
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .fold(0.0f32, f32::max)
+        };
+        let steady =
+            steps(&output[switch_at / 2..switch_at]).max(steps(&output[switch_at + settled..]));
+        let transition = steps(&output[switch_at - 1..switch_at + settled + 1]);
+        assert!(transition <= steady * 1.25, "{} vs {}", transition, steady);
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_switch() -> Result<()> {
+        let input = vec![0.1; DTLN_BLOCK_SHIFT * AUTO_SWITCH_HOLD_BLOCKS * 2];
+
+        // A threshold nothing reaches switches to the general model once the hold is over.
+        let mut switcher = ModelSwitcher::new(general_engine())?;
+        switcher.set_auto_switch(Some((2.0, 2.0)));
+        switcher.process(&input[..DTLN_BLOCK_SHIFT * (AUTO_SWITCH_HOLD_BLOCKS - 1)])?;
+        assert!(!switcher.is_switching());
+        switcher.process(&input[..DTLN_BLOCK_SHIFT])?;
+        assert!(switcher.is_switching());
+        assert_eq!(switcher.active_model(), GENERAL_MODEL);
+
+        // One anything reaches keeps the speech model.
+        let mut switcher = ModelSwitcher::new(general_engine())?;
+        switcher.set_auto_switch(Some((0.0, 0.0)));
+        switcher.process(&input)?;
+        assert!(!switcher.is_switching());
+        assert_eq!(switcher.active_model(), SPEECH_MODEL);
+
+        // Tensors of the wrong size are rejected.
+        assert!(DtlnEngine::with_models(DtlnModel::new(MODEL_1, MODEL_1)).is_err());
+        Ok(())
+    }
+}
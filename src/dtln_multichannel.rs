@@ -0,0 +1,295 @@
+// Multichannel denoising with the per-channel state kept in one place.
+use anyhow::{Context, Result};
+
+use crate::constants::{DTLN_BLOCK_SHIFT, DTLN_FFT_OUT_SIZE, DTLN_LATENCY};
+use crate::dtln_engine::{dtln_create, dtln_denoise, DtlnEngine};
+use crate::dtln_processor::ChannelMode;
+
+/**
+ * Denoises a fixed number of channels, planar or interleaved, in any ChannelMode. Input
+ * that doesn't fill a whole engine block is held for the next call, for every channel
+ * alike, so all channels always stay in step; reset and flush apply to all of them.
+ */
+pub struct MultiChannelDenoiser {
+    channels: usize,
+    mode: ChannelMode,
+    // One engine per channel, or the single engine of the downmix.
+    engines: Vec<DtlnEngine>,
+    // Estimates the shared mask from the channel mean in linked mode.
+    mask_engine: Option<DtlnEngine>,
+    // Per-channel input that doesn't yet fill a whole engine block.
+    pending: Vec<Vec<f32>>,
+}
+
+impl MultiChannelDenoiser {
+    pub fn new(channels: usize, mode: ChannelMode) -> Result<MultiChannelDenoiser> {
+        if channels == 0 {
+            return Err(anyhow::anyhow!("Channel count must be at least 1"));
+        }
+
+        let engine_count = match mode {
+            ChannelMode::Downmix => 1,
+            ChannelMode::Independent | ChannelMode::LinkedStereo => channels,
+        };
+        let engines = (0..engine_count)
+            .map(|_| dtln_create().context("Unable to create engine."))
+            .collect::<Result<Vec<DtlnEngine>>>()?;
+        let mask_engine = match mode {
+            ChannelMode::LinkedStereo => Some(dtln_create().context("Unable to create engine.")?),
+            ChannelMode::Independent | ChannelMode::Downmix => None,
+        };
+
+        Ok(MultiChannelDenoiser {
+            channels,
+            mode,
+            engines,
+            mask_engine,
+            pending: vec![Vec::with_capacity(DTLN_BLOCK_SHIFT); channels],
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn mode(&self) -> ChannelMode {
+        self.mode
+    }
+
+    // Delay in samples between an input sample and its denoised output, on every channel.
+    pub fn latency(&self) -> usize {
+        DTLN_LATENCY
+    }
+
+    /**
+     * Denoises one slice per channel, all of the same length, returning one output per
+     * channel. Like DtlnImmediateProcessor::denoise, only whole engine blocks are
+     * processed, so the outputs can be up to DTLN_BLOCK_SHIFT - 1 samples shorter or
+     * longer than the input.
+     */
+    pub fn process_planar(&mut self, input: &[&[f32]]) -> Result<Vec<Vec<f32>>> {
+        if input.len() != self.channels {
+            return Err(anyhow::anyhow!(
+                "Denoiser has {} channels, got {}",
+                self.channels,
+                input.len()
+            ));
+        }
+        let frames = input[0].len();
+        if input.iter().any(|channel| channel.len() != frames) {
+            return Err(anyhow::anyhow!("Channels differ in length"));
+        }
+
+        for (pending, channel) in self.pending.iter_mut().zip(input.iter()) {
+            pending.extend_from_slice(channel);
+        }
+        let block_samples = self.pending[0].len() / DTLN_BLOCK_SHIFT * DTLN_BLOCK_SHIFT;
+        let blocks = self
+            .pending
+            .iter_mut()
+            .map(|pending| {
+                let remainder = pending.split_off(block_samples);
+                std::mem::replace(pending, remainder)
+            })
+            .collect::<Vec<Vec<f32>>>();
+
+        let mut output = vec![vec![0.0; block_samples]; self.channels];
+        match self.mode {
+            ChannelMode::Independent => {
+                for ((engine, block), output) in
+                    self.engines.iter_mut().zip(&blocks).zip(output.iter_mut())
+                {
+                    dtln_denoise(engine, block, output)?;
+                }
+            }
+            ChannelMode::Downmix => {
+                let mix = mean(&blocks, block_samples);
+                let (first, rest) = output.split_at_mut(1);
+                dtln_denoise(&mut self.engines[0], &mix, &mut first[0])?;
+                for output in rest {
+                    output.copy_from_slice(&first[0]);
+                }
+            }
+            ChannelMode::LinkedStereo => {
+                let mix = mean(&blocks, block_samples);
+                let mask_engine = self.mask_engine.as_mut().expect("linked mask engine");
+                let mut mask = [0.0; DTLN_FFT_OUT_SIZE];
+                for start in (0..block_samples).step_by(DTLN_BLOCK_SHIFT) {
+                    let range = start..start + DTLN_BLOCK_SHIFT;
+                    mask.copy_from_slice(mask_engine.estimate_mask(&mix[range.clone()]));
+                    for ((engine, block), output) in
+                        self.engines.iter_mut().zip(&blocks).zip(output.iter_mut())
+                    {
+                        engine.denoise_with_mask(
+                            &block[range.clone()],
+                            &mask,
+                            &mut output[range.clone()],
+                        );
+                    }
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /**
+     * Denoises interleaved frames of channels() channels, same as process_planar. The
+     * input must hold whole frames.
+     */
+    pub fn process_interleaved(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+        if !input.len().is_multiple_of(self.channels) {
+            return Err(anyhow::anyhow!(
+                "Input length {} is not a multiple of {} channels",
+                input.len(),
+                self.channels
+            ));
+        }
+        let planar = (0..self.channels)
+            .map(|channel| {
+                input
+                    .iter()
+                    .skip(channel)
+                    .step_by(self.channels)
+                    .copied()
+                    .collect::<Vec<f32>>()
+            })
+            .collect::<Vec<Vec<f32>>>();
+        let planar = planar.iter().map(Vec::as_slice).collect::<Vec<&[f32]>>();
+        Ok(interleave(&self.process_planar(&planar)?))
+    }
+
+    // Number of samples per channel the next flush will return.
+    pub fn flush_len(&self) -> usize {
+        self.pending[0].len() + self.latency()
+    }
+
+    /**
+     * Ends the stream on every channel, returning the held input and the latency tail
+     * per channel, like DtlnImmediateProcessor::flush. Afterwards the denoiser is reset.
+     */
+    pub fn flush(&mut self) -> Result<Vec<Vec<f32>>> {
+        let tail_len = self.flush_len();
+        let padding =
+            tail_len.div_ceil(DTLN_BLOCK_SHIFT) * DTLN_BLOCK_SHIFT - self.pending[0].len();
+        let silence = vec![0.0; padding];
+        let mut tail = self.process_planar(&vec![silence.as_slice(); self.channels])?;
+        for channel in tail.iter_mut() {
+            channel.truncate(tail_len);
+        }
+        self.reset();
+        Ok(tail)
+    }
+
+    // flush, interleaved.
+    pub fn flush_interleaved(&mut self) -> Result<Vec<f32>> {
+        Ok(interleave(&self.flush()?))
+    }
+
+    // Clears the state and held input of every channel.
+    pub fn reset(&mut self) {
+        for engine in self.engines.iter_mut().chain(self.mask_engine.as_mut()) {
+            engine.reset();
+        }
+        for pending in self.pending.iter_mut() {
+            pending.clear();
+        }
+    }
+}
+
+// Mean of the channels over their first `len` samples.
+fn mean(channels: &[Vec<f32>], len: usize) -> Vec<f32> {
+    let scale = 1.0 / channels.len() as f32;
+    (0..len)
+        .map(|i| channels.iter().map(|channel| channel[i]).sum::<f32>() * scale)
+        .collect()
+}
+
+fn interleave(planar: &[Vec<f32>]) -> Vec<f32> {
+    let frames = planar.first().map_or(0, Vec::len);
+    (0..frames)
+        .flat_map(|frame| planar.iter().map(move |channel| channel[frame]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_processor::{DtlnImmediateProcessor, DtlnProcessEngine};
+
+    fn noise_channels(channels: usize, len: usize) -> Vec<Vec<f32>> {
+        (0..channels)
+            .map(|_| {
+                (0..len)
+                    .map(|_| rand::random::<f32>() - 0.5)
+                    .collect::<Vec<f32>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_multichannel_denoiser() -> Result<()> {
+        for channels in [2, 6] {
+            let input = noise_channels(channels, DTLN_BLOCK_SHIFT * 20 + 100);
+            let planar = input.iter().map(Vec::as_slice).collect::<Vec<&[f32]>>();
+
+            // Independent channels match mono processors, whatever the chunking.
+            let mut denoiser = MultiChannelDenoiser::new(channels, ChannelMode::Independent)?;
+            let mut output = denoiser.process_planar(&planar)?;
+            for (channel, tail) in output.iter_mut().zip(denoiser.flush()?) {
+                channel.extend(tail);
+            }
+            for (channel, output) in input.iter().zip(output.iter()) {
+                let mut processor = DtlnImmediateProcessor::new()?;
+                processor.set_fade_in_ms(0.0)?;
+                let mut expected = processor.denoise(channel)?.samples;
+                expected.extend(processor.flush()?);
+                assert_eq!(output, &expected);
+            }
+
+            // Interleaved gives the interleaved planar output.
+            let interleaved = interleave(&input);
+            let mut denoiser = MultiChannelDenoiser::new(channels, ChannelMode::Independent)?;
+            let mut output = denoiser.process_interleaved(&interleaved)?;
+            output.extend(denoiser.flush_interleaved()?);
+            let mut denoiser = MultiChannelDenoiser::new(channels, ChannelMode::Independent)?;
+            let mut planar_output = denoiser.process_planar(&planar)?;
+            for (channel, tail) in planar_output.iter_mut().zip(denoiser.flush()?) {
+                channel.extend(tail);
+            }
+            assert_eq!(output, interleave(&planar_output));
+
+            // Reset clears every channel.
+            denoiser.process_planar(&planar)?;
+            denoiser.reset();
+            assert_eq!(denoiser.flush_len(), DTLN_LATENCY);
+            let mut after_reset = denoiser.process_planar(&planar)?;
+            for (channel, tail) in after_reset.iter_mut().zip(denoiser.flush()?) {
+                channel.extend(tail);
+            }
+            assert_eq!(after_reset, planar_output);
+
+            // Downmix writes the denoised mean to every channel.
+            let mut denoiser = MultiChannelDenoiser::new(channels, ChannelMode::Downmix)?;
+            let output = denoiser.process_planar(&planar)?;
+            let mix = mean(&input, input[0].len());
+            let mut processor = DtlnImmediateProcessor::new()?;
+            processor.set_fade_in_ms(0.0)?;
+            let expected = processor.denoise(&mix)?.samples;
+            assert!(output.iter().all(|channel| channel == &expected));
+
+            // Linked channels share a mask, so identical inputs stay identical.
+            let same = vec![input[0].as_slice(); channels];
+            let mut denoiser = MultiChannelDenoiser::new(channels, ChannelMode::LinkedStereo)?;
+            let output = denoiser.process_planar(&same)?;
+            assert!(output.iter().all(|channel| channel == &output[0]));
+            assert_eq!(output[0].len(), DTLN_BLOCK_SHIFT * 20);
+        }
+
+        let mut denoiser = MultiChannelDenoiser::new(2, ChannelMode::Independent)?;
+        assert!(denoiser.process_planar(&[&[0.0; 4]]).is_err());
+        assert!(denoiser.process_planar(&[&[0.0; 4], &[0.0; 3]]).is_err());
+        assert!(denoiser.process_interleaved(&[0.0; 5]).is_err());
+        assert!(MultiChannelDenoiser::new(0, ChannelMode::Independent).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+// ndarray entry points for DtlnImmediateProcessor, behind the `ndarray` feature.
+use anyhow::Result;
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2};
+
+use crate::dtln_processor::{DtlnImmediateProcessor, DtlnProcessEngine};
+
+impl DtlnImmediateProcessor {
+    /**
+     * Denoises a mono array, same as denoise. Non-contiguous views are copied first.
+     */
+    pub fn process_ndarray(&mut self, input: ArrayView1<f32>) -> Result<Array1<f32>> {
+        let samples = match input.as_slice() {
+            Some(samples) => self.denoise(samples)?.samples,
+            None => self.denoise(&input.to_vec())?.samples,
+        };
+        Ok(Array1::from_vec(samples))
+    }
+
+    /**
+     * Denoises a [channels, frames] array, one row per channel, same as
+     * denoise_interleaved. The number of rows must match channel_count(), and the
+     * output has as many columns as denoise_interleaved returned frames.
+     */
+    pub fn process_ndarray2(&mut self, input: ArrayView2<f32>) -> Result<Array2<f32>> {
+        let channels = input.nrows();
+        // Transposing then iterating in logical order interleaves the channels.
+        let interleaved = input.t().iter().copied().collect::<Vec<f32>>();
+        let output = self.denoise_interleaved(&interleaved, channels)?;
+        // Empty input returns nothing, whatever the channel count.
+        let frames = output.len().checked_div(channels).unwrap_or(0);
+        Ok(Array2::from_shape_vec((frames, channels), output)?
+            .reversed_axes()
+            .as_standard_layout()
+            .into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{s, Array};
+
+    #[test]
+    fn test_ndarray_matches_slices() -> Result<()> {
+        let samples = (0..4096)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+        let expected = DtlnImmediateProcessor::new()?.denoise(&samples)?.samples;
+        let output = DtlnImmediateProcessor::new()?.process_ndarray(ArrayView1::from(&samples))?;
+        assert_eq!(output.to_vec(), expected);
+
+        // Strided views take the copying path.
+        let doubled = Array::from_iter(samples.iter().flat_map(|s| [*s, 0.0]));
+        let output = DtlnImmediateProcessor::new()?.process_ndarray(doubled.slice(s![..;2]))?;
+        assert_eq!(output.to_vec(), expected);
+
+        let stereo = Array2::from_shape_fn((2, 2048), |(channel, frame)| {
+            samples[channel * 2048 + frame]
+        });
+        let interleaved = stereo.t().iter().copied().collect::<Vec<f32>>();
+        let expected =
+            DtlnImmediateProcessor::with_channels(2)?.denoise_interleaved(&interleaved, 2)?;
+        let output = DtlnImmediateProcessor::with_channels(2)?.process_ndarray2(stereo.view())?;
+        assert_eq!(output.dim(), (2, 2048));
+        assert_eq!(output.t().iter().copied().collect::<Vec<f32>>(), expected);
+        assert!(DtlnImmediateProcessor::new()?
+            .process_ndarray2(stereo.view())
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+// Processor configuration loaded from JSON, behind the `serde` feature.
+//
+// A preset is a JSON object, every key optional, unknown keys rejected:
+//
+//   {
+//     "sample_rate": 16000,          // must be DTLN_SAMPLE_RATE
+//     "threads": 1,                  // TFLite threads per interpreter, at least 1
+//     "mix": 1.0,                    // 0 to 1, see DtlnEngine::set_mix
+//     "suppression_strength": 1.0,   // 0 to 1, see DtlnEngine::set_suppression_strength
+//     "min_gain": 0.0,               // 0 to 1, see DtlnEngine::set_min_gain
+//     "max_attenuation_db": -20.0,   // <= 0, limits suppression, overrides min_gain
+//     "output_smoothing": 32,        // ramp in samples, 1 to DTLN_BLOCK_SHIFT
+//     "low_band_bypass_hz": 120.0    // passes the band below this through untouched
+//   }
+//
+// Missing keys keep the processor defaults.
+//
+// There are no high-pass or limiter keys: the processor has neither stage, and a key
+// for one would be accepted without changing the output. low_band_bypass_hz is the
+// only filter setting. A preset naming either is rejected like any other unknown key,
+// so a profile written for a pipeline that had them fails loudly instead of running
+// unfiltered.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::dtln_engine::DtlnEngine;
+use crate::dtln_processor::{DtlnImmediateProcessor, DtlnOptions, DtlnProcessEngine};
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DtlnPreset {
+    pub sample_rate: Option<u32>,
+    pub threads: Option<usize>,
+    pub mix: Option<f32>,
+    pub suppression_strength: Option<f32>,
+    pub min_gain: Option<f32>,
+    pub max_attenuation_db: Option<f32>,
+    pub output_smoothing: Option<usize>,
+    pub low_band_bypass_hz: Option<f32>,
+}
+
+impl DtlnPreset {
+    pub fn from_json(json: &str) -> Result<DtlnPreset> {
+        serde_json::from_str(json).context("Invalid preset")
+    }
+
+    // The preset's settings shared with DtlnOptions, defaults for the missing ones.
+    pub fn options(&self) -> DtlnOptions {
+        let defaults = DtlnOptions::default();
+        DtlnOptions {
+            sample_rate: self.sample_rate.unwrap_or(defaults.sample_rate),
+            threads: self.threads.unwrap_or(defaults.threads),
+            mix: self.mix.unwrap_or(defaults.mix),
+            suppression_strength: self
+                .suppression_strength
+                .unwrap_or(defaults.suppression_strength),
+            queue_capacity: None,
+        }
+    }
+}
+
+impl DtlnImmediateProcessor {
+    /**
+     * Creates a mono processor from a JSON preset, see the top of this file for the
+     * schema. Fails with the offending key if the JSON is malformed or a value is out
+     * of range.
+     */
+    pub fn from_preset(json: &str) -> Result<DtlnImmediateProcessor> {
+        let preset = DtlnPreset::from_json(json)?;
+        let options = preset.options();
+        options.validate().context("Invalid preset")?;
+
+        let mut processor = DtlnImmediateProcessor::new()?;
+        if options.threads != 1 {
+            processor.engine =
+                DtlnEngine::with_threads(options.threads).context("Unable to create engine.")?;
+        }
+        processor.engine.set_mix(options.mix);
+        processor
+            .engine
+            .set_suppression_strength(options.suppression_strength);
+        if let Some(gain) = preset.min_gain {
+            processor
+                .set_min_gain(gain)
+                .context("Invalid preset: min_gain")?;
+        }
+        if let Some(db) = preset.max_attenuation_db {
+            processor
+                .set_max_attenuation_db(db)
+                .context("Invalid preset: max_attenuation_db")?;
+        }
+        if let Some(ramp) = preset.output_smoothing {
+            processor
+                .set_output_smoothing(Some(ramp))
+                .context("Invalid preset: output_smoothing")?;
+        }
+        if let Some(split_hz) = preset.low_band_bypass_hz {
+            processor
+                .set_low_band_bypass(Some(split_hz))
+                .context("Invalid preset: low_band_bypass_hz")?;
+        }
+        Ok(processor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DTLN_LATENCY;
+    use crate::dtln_dsp::LinearPhaseCrossover;
+
+    #[test]
+    fn test_from_preset() -> Result<()> {
+        let processor = DtlnImmediateProcessor::from_preset(
+            r#"{
+                "mix": 0.5,
+                "suppression_strength": 0.8,
+                "min_gain": 0.1,
+                "output_smoothing": 32,
+                "low_band_bypass_hz": 120
+            }"#,
+        )?;
+        assert_eq!(processor.engine.mix(), 0.5);
+        assert_eq!(processor.engine.suppression_strength(), 0.8);
+        assert_eq!(processor.engine.min_gain(), 0.1);
+        assert_eq!(processor.engine.output_smoothing(), Some(32));
+        assert_eq!(
+            processor.latency(),
+            DTLN_LATENCY + LinearPhaseCrossover::latency()
+        );
+
+        // An empty preset is the default processor.
+        let processor = DtlnImmediateProcessor::from_preset("{}")?;
+        assert_eq!(processor.engine.mix(), 1.0);
+        assert_eq!(processor.latency(), DTLN_LATENCY);
+
+        let error = |json: &str| {
+            format!(
+                "{:#}",
+                DtlnImmediateProcessor::from_preset(json).err().unwrap()
+            )
+        };
+        assert!(error(r#"{"mix": 1.5}"#).contains("Mix 1.5"));
+        assert!(error(r#"{"sample_rate": 44100}"#).contains("44100"));
+        assert!(error(r#"{"min_gain": -1}"#).contains("min_gain"));
+        assert!(error(r#"{"output_smoothing": 0}"#).contains("output_smoothing"));
+        assert!(error(r#"{"volume": 2}"#).contains("volume"));
+        assert!(error(r#"{"high_pass_hz": 80}"#).contains("high_pass_hz"));
+        assert!(error(r#"{"limiter_ceiling_db": -1}"#).contains("limiter_ceiling_db"));
+        assert!(error(r#"{"mix": "loud"}"#).contains("Invalid preset"));
+        Ok(())
+    }
+}
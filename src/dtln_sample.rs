@@ -0,0 +1,142 @@
+// Conversion between the engine's f32 samples and other sample formats.
+
+/**
+ * A sample format DtlnImmediateProcessor::process can take and return. to_f32 maps the
+ * format's full scale to -1.0..=1.0, and from_f32 maps back, rounding to the nearest
+ * value and clamping anything beyond full scale instead of wrapping around.
+ *
+ * Implemented for f32, i16, i32 and 8 bit unsigned (offset binary, 128 is silence);
+ * implement it for other formats, e.g. mu-law, to process them the same way.
+ */
+pub trait Sample: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+// Scaled by i16::MAX, as in process_into_i16, so -i16::MAX..=i16::MAX round-trips.
+impl Sample for i16 {
+    fn to_f32(self) -> f32 {
+        (self as f32 / i16::MAX as f32).max(-1.0)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        let full_scale = i16::MAX as f64;
+        (value as f64 * full_scale)
+            .round()
+            .clamp(-full_scale, full_scale) as i16
+    }
+}
+
+// Scaled by i32::MAX, as in process_into_i32. The conversion goes through f64, but an
+// f32 only holds the top 24 bits.
+impl Sample for i32 {
+    fn to_f32(self) -> f32 {
+        (self as f64 / i32::MAX as f64).max(-1.0) as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        let full_scale = i32::MAX as f64;
+        (value as f64 * full_scale)
+            .round()
+            .clamp(-full_scale, full_scale) as i32
+    }
+}
+
+// 8 bit WAV samples: offset by 128 and scaled by 128, so 0 maps to -1.0 and 255 to
+// just under 1.0.
+impl Sample for u8 {
+    fn to_f32(self) -> f32 {
+        (self as f32 - 128.0) / 128.0
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+/**
+ * Byte layout of the samples DenoiseStream reads and writes: mono, 16 kHz
+ * (DTLN_SAMPLE_RATE), little endian, with no header. F32Le is 4 byte IEEE floats at
+ * full scale +-1.0, I16Le 2 byte signed integers scaled as the i16 Sample.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PcmFormat {
+    #[default]
+    F32Le,
+    I16Le,
+}
+
+impl PcmFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            PcmFormat::F32Le => 4,
+            PcmFormat::I16Le => 2,
+        }
+    }
+
+    // Decodes one sample of bytes_per_sample() bytes.
+    pub fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            PcmFormat::F32Le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            PcmFormat::I16Le => i16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+        }
+    }
+
+    pub fn encode(&self, sample: f32, out: &mut impl Extend<u8>) {
+        match self {
+            PcmFormat::F32Le => out.extend(sample.to_le_bytes()),
+            PcmFormat::I16Le => out.extend(i16::from_f32(sample).to_le_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_scaling() {
+        assert_eq!(0.25f32.to_f32(), 0.25);
+        assert_eq!(f32::from_f32(-1.5), -1.5);
+
+        assert_eq!(i16::MAX.to_f32(), 1.0);
+        assert_eq!(i16::MIN.to_f32(), -1.0);
+        assert_eq!(i16::from_f32(0.5), 16384);
+        assert_eq!(i16::from_f32(2.0), i16::MAX);
+        assert_eq!(i16::from_f32(-2.0), -i16::MAX);
+
+        assert_eq!(i32::MAX.to_f32(), 1.0);
+        assert_eq!(i32::from_f32(-0.5), -(i32::MAX / 2) - 1);
+        assert_eq!(i32::from_f32(3.0), i32::MAX);
+
+        assert_eq!(128u8.to_f32(), 0.0);
+        assert_eq!(0u8.to_f32(), -1.0);
+        assert_eq!(u8::from_f32(0.5), 192);
+        assert_eq!(u8::from_f32(1.0), 255);
+        assert_eq!(u8::from_f32(-1.5), 0);
+
+        // Every integer value survives a round trip.
+        for value in -i16::MAX..=i16::MAX {
+            assert_eq!(i16::from_f32(value.to_f32()), value);
+        }
+        for value in 0..=u8::MAX {
+            assert_eq!(u8::from_f32(value.to_f32()), value);
+        }
+        // i32 to within the 24 bits an f32 holds.
+        for _ in 0..1000 {
+            let value = rand::random::<i32>().max(-i32::MAX);
+            let restored = i32::from_f32(value.to_f32());
+            assert!((restored as i64 - value as i64).abs() <= 1 << 7);
+        }
+    }
+}
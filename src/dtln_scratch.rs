@@ -0,0 +1,94 @@
+// Working memory the engine uses for every block.
+use anyhow::Result;
+use num::Complex;
+
+use crate::constants::*;
+
+/**
+ * Caller-supplied working memory for DtlnEngine::with_scratch, for hosts that need the
+ * per-block buffers in memory of their choosing (static, DMA-accessible, ...).
+ *
+ * `real` must hold at least ScratchBuffers::REAL_LEN samples (3 * DTLN_FFT_OUT_SIZE +
+ * 2 * DTLN_BLOCK_LEN = 1795) and `complex` at least ScratchBuffers::COMPLEX_LEN bins
+ * (2 * DTLN_FFT_OUT_SIZE = 514); EngineInfo::scratch_sizes reports the same values. Any
+ * excess is left unused. The FFT plans and the TFLite tensors are still allocated when
+ * the engine is built, but processing never allocates.
+ */
+pub struct ScratchBuffers {
+    pub real: &'static mut [f32],
+    pub complex: &'static mut [Complex<f32>],
+}
+
+impl ScratchBuffers {
+    // Magnitude, phase and mask spectra, plus two time domain blocks.
+    pub const REAL_LEN: usize = 3 * DTLN_FFT_OUT_SIZE + 2 * DTLN_BLOCK_LEN;
+    // Input and reference spectra.
+    pub const COMPLEX_LEN: usize = 2 * DTLN_FFT_OUT_SIZE;
+
+    pub fn validate(&self) -> Result<()> {
+        if self.real.len() < Self::REAL_LEN || self.complex.len() < Self::COMPLEX_LEN {
+            return Err(anyhow::anyhow!(
+                "Scratch buffers too small, need {} real and {} complex, got {} and {}",
+                Self::REAL_LEN,
+                Self::COMPLEX_LEN,
+                self.real.len(),
+                self.complex.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Engine working memory, either allocated by the engine or supplied by the caller.
+pub(crate) enum Scratch {
+    Owned {
+        real: Vec<f32>,
+        complex: Vec<Complex<f32>>,
+    },
+    Supplied(ScratchBuffers),
+}
+
+// The scratch memory carved into the buffers infer works with.
+pub(crate) struct ScratchViews<'a> {
+    pub in_mag: &'a mut [f32],
+    pub in_phase: &'a mut [f32],
+    pub mask: &'a mut [f32],
+    pub fft_in: &'a mut [f32],
+    pub block: &'a mut [f32],
+    pub spectrum: &'a mut [Complex<f32>],
+    pub reference_spectrum: &'a mut [Complex<f32>],
+}
+
+impl Scratch {
+    pub fn owned() -> Self {
+        Scratch::Owned {
+            real: vec![0.0; ScratchBuffers::REAL_LEN],
+            complex: vec![Complex::new(0.0, 0.0); ScratchBuffers::COMPLEX_LEN],
+        }
+    }
+
+    // The buffers must have been validated.
+    pub fn views(&mut self) -> ScratchViews<'_> {
+        let (real, complex) = match self {
+            Scratch::Owned { real, complex } => (real.as_mut_slice(), complex.as_mut_slice()),
+            Scratch::Supplied(buffers) => (&mut *buffers.real, &mut *buffers.complex),
+        };
+        let (in_mag, real) = real.split_at_mut(DTLN_FFT_OUT_SIZE);
+        let (in_phase, real) = real.split_at_mut(DTLN_FFT_OUT_SIZE);
+        let (mask, real) = real.split_at_mut(DTLN_FFT_OUT_SIZE);
+        let (fft_in, real) = real.split_at_mut(DTLN_BLOCK_LEN);
+        let block = &mut real[..DTLN_BLOCK_LEN];
+        let (spectrum, complex) = complex.split_at_mut(DTLN_FFT_OUT_SIZE);
+        let reference_spectrum = &mut complex[..DTLN_FFT_OUT_SIZE];
+
+        ScratchViews {
+            in_mag,
+            in_phase,
+            mask,
+            fft_in,
+            block,
+            spectrum,
+            reference_spectrum,
+        }
+    }
+}
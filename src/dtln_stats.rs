@@ -0,0 +1,351 @@
+// Cumulative processing counters, cheap enough to keep on the audio path.
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Snapshot of a processor's counters, laid out for the C FFI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DtlnStats {
+    // Engine blocks (DTLN_BLOCK_SHIFT samples each) run through the models.
+    pub blocks_processed: u64,
+    // Results returned while the processor was starved.
+    pub starvation_events: u64,
+    // NaN or infinite input samples replaced with silence.
+    pub non_finite_sanitized: u64,
+    // Samples run through the models.
+    pub samples_processed: u64,
+    // Times the deferred processor's worker woke up. The worker blocks on its queue
+    // while no audio arrives, so this only grows with the blocks fed to it.
+    pub worker_wakeups: u64,
+    // Model invocations that failed, whose blocks were passed through instead. See
+    // DtlnEngine::take_invoke_failures.
+    pub invoke_failures: u64,
+    // Blocks the deferred processor dropped unreturned to stay within its latency
+    // budget, see DtlnDeferredProcessor::set_max_latency_ms.
+    pub dropped_blocks: u64,
+    // Input samples the deferred processor dropped unprocessed because its queue was
+    // full, see DtlnOptions::queue_capacity.
+    pub dropped_input_samples: u64,
+}
+
+#[derive(Default)]
+pub struct StatsCounters {
+    blocks_processed: AtomicU64,
+    starvation_events: AtomicU64,
+    non_finite_sanitized: AtomicU64,
+    samples_processed: AtomicU64,
+    worker_wakeups: AtomicU64,
+    invoke_failures: AtomicU64,
+    dropped_blocks: AtomicU64,
+    dropped_input_samples: AtomicU64,
+}
+
+impl StatsCounters {
+    pub fn add_processed(&self, samples: usize, blocks: usize) {
+        self.samples_processed
+            .fetch_add(samples as u64, Ordering::Relaxed);
+        self.blocks_processed
+            .fetch_add(blocks as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_starvation(&self) {
+        self.starvation_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_wakeup(&self) {
+        self.worker_wakeups.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_invoke_failures(&self, failures: u64) {
+        self.invoke_failures.fetch_add(failures, Ordering::Relaxed);
+    }
+
+    pub fn add_dropped_blocks(&self, blocks: u64) {
+        self.dropped_blocks.fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    pub fn add_dropped_input(&self, samples: usize) {
+        self.dropped_input_samples
+            .fetch_add(samples as u64, Ordering::Relaxed);
+    }
+
+    /**
+     * Replaces non-finite samples with silence so they can't corrupt the recurrent
+     * state, counting them. Borrows the input when it is already clean.
+     */
+    pub fn sanitize<'a>(&self, input: &'a [f32]) -> Cow<'a, [f32]> {
+        if input.iter().all(|sample| sample.is_finite()) {
+            return Cow::Borrowed(input);
+        }
+
+        let mut sanitized = 0;
+        let clean = input
+            .iter()
+            .map(|sample| {
+                if sample.is_finite() {
+                    *sample
+                } else {
+                    sanitized += 1;
+                    0.0
+                }
+            })
+            .collect::<Vec<f32>>();
+        self.non_finite_sanitized
+            .fetch_add(sanitized, Ordering::Relaxed);
+        dtln_debug!(
+            "Replaced {} non-finite input samples with silence",
+            sanitized
+        );
+        Cow::Owned(clean)
+    }
+
+    pub fn snapshot(&self) -> DtlnStats {
+        DtlnStats {
+            blocks_processed: self.blocks_processed.load(Ordering::Relaxed),
+            starvation_events: self.starvation_events.load(Ordering::Relaxed),
+            non_finite_sanitized: self.non_finite_sanitized.load(Ordering::Relaxed),
+            samples_processed: self.samples_processed.load(Ordering::Relaxed),
+            worker_wakeups: self.worker_wakeups.load(Ordering::Relaxed),
+            invoke_failures: self.invoke_failures.load(Ordering::Relaxed),
+            dropped_blocks: self.dropped_blocks.load(Ordering::Relaxed),
+            dropped_input_samples: self.dropped_input_samples.load(Ordering::Relaxed),
+        }
+    }
+
+    // Returns the counters and resets them to zero.
+    pub fn take(&self) -> DtlnStats {
+        DtlnStats {
+            blocks_processed: self.blocks_processed.swap(0, Ordering::Relaxed),
+            starvation_events: self.starvation_events.swap(0, Ordering::Relaxed),
+            non_finite_sanitized: self.non_finite_sanitized.swap(0, Ordering::Relaxed),
+            samples_processed: self.samples_processed.swap(0, Ordering::Relaxed),
+            worker_wakeups: self.worker_wakeups.swap(0, Ordering::Relaxed),
+            invoke_failures: self.invoke_failures.swap(0, Ordering::Relaxed),
+            dropped_blocks: self.dropped_blocks.swap(0, Ordering::Relaxed),
+            dropped_input_samples: self.dropped_input_samples.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+// Gain below which a bin counts as suppressed in MaskStats.
+pub const MASK_SUPPRESSED_GAIN: f32 = 0.5;
+
+/**
+ * Summary of the applied masks, for tuning the suppression strength in a closed loop:
+ * a mean gain near 1 means the mask is barely suppressing, a high suppressed fraction
+ * that it is gating hard. Averaged over the blocks it covers.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaskStats {
+    // Mask weighted by the input energy of each bin: the share of the signal kept.
+    pub mean_gain: f32,
+    // Fraction of the bins with a gain below MASK_SUPPRESSED_GAIN.
+    pub suppressed_fraction: f32,
+    // Shannon entropy of the mask across the bins, normalized to [0, 1]: 1 when every
+    // bin has the same gain, lower the more the gain is concentrated in a few bins.
+    pub entropy: f32,
+    // Heuristic risk of musical noise, from 0 to 1, see artifact_risk.
+    pub artifact_risk: f32,
+    // Blocks averaged.
+    pub blocks: usize,
+}
+
+impl MaskStats {
+    // Statistics of one block from its mask, the input magnitude it applies to and the
+    // mask of the block before.
+    pub(crate) fn from_mask(mask: &[f32], magnitude: &[f32], previous: &[f32]) -> MaskStats {
+        let mut energy = 0.0;
+        let mut kept = 0.0;
+        let mut suppressed = 0;
+        let mut total = 0.0;
+        for (gain, magnitude) in mask.iter().zip(magnitude.iter()) {
+            let power = magnitude * magnitude;
+            energy += power;
+            kept += gain * power;
+            total += gain.max(0.0);
+            suppressed += (*gain < MASK_SUPPRESSED_GAIN) as usize;
+        }
+        let entropy = if total > 0.0 {
+            let entropy = mask
+                .iter()
+                .map(|gain| gain.max(0.0) / total)
+                .filter(|p| *p > 0.0)
+                .map(|p| -p * p.ln())
+                .sum::<f32>();
+            entropy / (mask.len() as f32).ln()
+        } else {
+            0.0
+        };
+        MaskStats {
+            mean_gain: if energy > 0.0 { kept / energy } else { 1.0 },
+            suppressed_fraction: suppressed as f32 / mask.len() as f32,
+            entropy,
+            artifact_risk: artifact_risk(mask, previous),
+            blocks: 1,
+        }
+    }
+
+    // Adds a block to a running total, see mean.
+    pub(crate) fn add(&mut self, block: MaskStats) {
+        self.mean_gain += block.mean_gain;
+        self.suppressed_fraction += block.suppressed_fraction;
+        self.entropy += block.entropy;
+        self.artifact_risk += block.artifact_risk;
+        self.blocks += block.blocks;
+    }
+
+    // Mean of the blocks added, None when there were none.
+    pub(crate) fn mean(self) -> Option<MaskStats> {
+        let blocks = self.blocks as f32;
+        (self.blocks > 0).then(|| MaskStats {
+            mean_gain: self.mean_gain / blocks,
+            suppressed_fraction: self.suppressed_fraction / blocks,
+            entropy: self.entropy / blocks,
+            artifact_risk: self.artifact_risk / blocks,
+            blocks: self.blocks,
+        })
+    }
+}
+
+/**
+ * Heuristic risk, from 0 to 1, that a mask produces musical noise: the mean of the RMS
+ * change of the mask since the `previous` block's and of twice the fraction of isolated
+ * bins, active (a gain of at least MASK_SUPPRESSED_GAIN) where both neighbours and the
+ * same bin in the previous block aren't. Short-lived isolated peaks are what is heard as
+ * musical noise, so a steady mask, or one whose active bins form lasting bands like the
+ * harmonics of speech, scores low. A cheap indicator for warnings or for raising the
+ * output smoothing, not a perceptual measure.
+ */
+pub fn artifact_risk(mask: &[f32], previous: &[f32]) -> f32 {
+    if mask.is_empty() || mask.len() != previous.len() {
+        return 0.0;
+    }
+    let active = |gain: f32| gain >= MASK_SUPPRESSED_GAIN;
+    let change = mask
+        .iter()
+        .zip(previous)
+        .map(|(gain, previous)| (gain - previous) * (gain - previous))
+        .sum::<f32>()
+        / mask.len() as f32;
+    let isolated = (0..mask.len())
+        .filter(|&k| {
+            active(mask[k])
+                && !active(previous[k])
+                && (k == 0 || !active(mask[k - 1]))
+                && mask.get(k + 1).is_none_or(|gain| !active(*gain))
+        })
+        .count() as f32
+        / mask.len() as f32;
+    ((change.sqrt().min(1.0) + (2.0 * isolated).min(1.0)) / 2.0).clamp(0.0, 1.0)
+}
+
+// Proportion of the quietest blocks taken as the noise floor in ProcessingReport.
+const NOISE_FLOOR_PERCENTILE: f32 = 0.1;
+// Samples per block when measuring the noise floor.
+const NOISE_FLOOR_BLOCK: usize = 512;
+
+// Summary of an offline run, for checking the denoiser did something reasonable.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessingReport {
+    pub input_samples: usize,
+    pub output_samples: usize,
+    pub input_peak: f32,
+    pub input_rms: f32,
+    pub output_peak: f32,
+    pub output_rms: f32,
+    // Drop of the noise floor, the level of the quietest 10% of blocks, from input to
+    // output. An estimate: it assumes the quietest blocks are noise only.
+    pub noise_reduction_db: f32,
+    // Samples at or beyond full scale.
+    pub input_clipped: usize,
+    pub output_clipped: usize,
+    // Whether the input was resampled to and from the model's rate.
+    pub resampled: bool,
+}
+
+// Accumulates a ProcessingReport while the audio streams through.
+#[derive(Default)]
+pub(crate) struct ReportBuilder {
+    input: LevelMeter,
+    output: LevelMeter,
+}
+
+impl ReportBuilder {
+    pub(crate) fn add_input(&mut self, samples: &[f32]) {
+        self.input.add(samples);
+    }
+
+    pub(crate) fn add_output(&mut self, samples: &[f32]) {
+        self.output.add(samples);
+    }
+
+    pub(crate) fn finish(self, resampled: bool) -> ProcessingReport {
+        let input_floor = self.input.noise_floor();
+        let output_floor = self.output.noise_floor().max(f64::MIN_POSITIVE);
+        let noise_reduction_db = if input_floor > 0.0 {
+            (10.0 * (input_floor / output_floor).log10()) as f32
+        } else {
+            0.0
+        };
+        ProcessingReport {
+            input_samples: self.input.samples,
+            output_samples: self.output.samples,
+            input_peak: self.input.peak,
+            input_rms: self.input.rms(),
+            output_peak: self.output.peak,
+            output_rms: self.output.rms(),
+            noise_reduction_db,
+            input_clipped: self.input.clipped,
+            output_clipped: self.output.clipped,
+            resampled,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LevelMeter {
+    samples: usize,
+    peak: f32,
+    energy: f64,
+    clipped: usize,
+    // Mean square of every whole NOISE_FLOOR_BLOCK, and the energy of the partial one.
+    block_power: Vec<f64>,
+    block_energy: f64,
+}
+
+impl LevelMeter {
+    fn add(&mut self, samples: &[f32]) {
+        for sample in samples {
+            let square = (*sample as f64) * (*sample as f64);
+            self.peak = self.peak.max(sample.abs());
+            self.energy += square;
+            self.clipped += (sample.abs() >= 1.0) as usize;
+            self.block_energy += square;
+            self.samples += 1;
+            if self.samples.is_multiple_of(NOISE_FLOOR_BLOCK) {
+                self.block_power
+                    .push(self.block_energy / NOISE_FLOOR_BLOCK as f64);
+                self.block_energy = 0.0;
+            }
+        }
+    }
+
+    fn rms(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        (self.energy / self.samples as f64).sqrt() as f32
+    }
+
+    // Mean power of the quietest NOISE_FLOOR_PERCENTILE of the blocks.
+    fn noise_floor(&self) -> f64 {
+        let mut power = self.block_power.clone();
+        power.sort_by(f64::total_cmp);
+        let count = ((power.len() as f32 * NOISE_FLOOR_PERCENTILE).ceil() as usize).max(1);
+        let quietest = &power[..count.min(power.len())];
+        if quietest.is_empty() {
+            return 0.0;
+        }
+        quietest.iter().sum::<f64>() / quietest.len() as f64
+    }
+}
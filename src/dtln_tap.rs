@@ -0,0 +1,219 @@
+// Recording the raw input and denoised output of a processor, for reproducible bug reports.
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result};
+
+use crate::constants::DTLN_SAMPLE_RATE;
+use crate::dtln_utilities::WavWriter;
+
+// Environment variable naming the directory the tap records to, enabling it.
+pub const DEBUG_TAP_ENV: &str = "DTLN_DEBUG_TAP";
+
+// Default length of each recorded file, 10 minutes.
+const DEFAULT_MAX_FILE_SAMPLES: usize = DTLN_SAMPLE_RATE as usize * 600;
+// Default number of raw and denoised file pairs kept.
+const DEFAULT_MAX_FILES: usize = 4;
+
+// Taps created in this process, numbering their files apart.
+static TAPS: AtomicUsize = AtomicUsize::new(0);
+
+// Where and how much a DebugTap records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TapConfig {
+    pub dir: PathBuf,
+    // Samples per file; a full pair of files is closed and a new pair started.
+    pub max_file_samples: usize,
+    // File pairs kept in `dir`, the oldest are deleted beyond this.
+    pub max_files: usize,
+}
+
+impl TapConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> TapConfig {
+        TapConfig {
+            dir: dir.into(),
+            max_file_samples: DEFAULT_MAX_FILE_SAMPLES,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+
+    // The default limits with the directory named by DEBUG_TAP_ENV, None when unset.
+    pub fn from_env() -> Option<TapConfig> {
+        std::env::var_os(DEBUG_TAP_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(TapConfig::new)
+    }
+}
+
+/**
+ * Records the raw input and the denoised output of a stream to a pair of 32-bit float
+ * WAV files, dtln_tap_<pid>_<tap>_<file>_raw.wav and ..._denoised.wav. The raw file
+ * holds the input exactly as given, so feeding it through a processor with the same
+ * settings reproduces the denoised file. Files rotate every max_file_samples input
+ * samples, keeping the newest max_files pairs.
+ *
+ * The denoised file runs behind the raw one by the processor's latency and the input it
+ * holds for a whole block; the files are finalized when the tap is dropped.
+ */
+pub struct DebugTap {
+    config: TapConfig,
+    tap: usize,
+    // Open raw and denoised writers.
+    writers: Option<(WavWriter, WavWriter)>,
+    // Index of the next pair of files.
+    file: usize,
+    // Paths of the pairs written, oldest first.
+    files: VecDeque<(PathBuf, PathBuf)>,
+}
+
+impl DebugTap {
+    pub fn new(config: TapConfig) -> Result<DebugTap> {
+        if config.max_file_samples == 0 || config.max_files == 0 {
+            return Err(anyhow::anyhow!("Debug tap limits must be positive"));
+        }
+        std::fs::create_dir_all(&config.dir)
+            .with_context(|| format!("Unable to create {}", config.dir.display()))?;
+        Ok(DebugTap {
+            config,
+            tap: TAPS.fetch_add(1, Ordering::Relaxed),
+            writers: None,
+            file: 0,
+            files: VecDeque::new(),
+        })
+    }
+
+    pub fn config(&self) -> &TapConfig {
+        &self.config
+    }
+
+    // Paths of the (raw, denoised) file pairs still kept, oldest first.
+    pub fn files(&self) -> impl Iterator<Item = &(PathBuf, PathBuf)> {
+        self.files.iter()
+    }
+
+    // Appends one call's input and output, rotating files as the raw one fills up.
+    pub fn write(&mut self, raw: &[f32], denoised: &[f32]) -> Result<()> {
+        if self
+            .writers
+            .as_ref()
+            .is_some_and(|(raw, _)| raw.len() >= self.config.max_file_samples)
+        {
+            self.rotate()?;
+        }
+        if self.writers.is_none() {
+            self.open()?;
+        }
+        if let Some((raw_writer, denoised_writer)) = self.writers.as_mut() {
+            raw_writer.write(raw)?;
+            denoised_writer.write(denoised)?;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<()> {
+        let path = |kind: &str| {
+            self.config.dir.join(format!(
+                "dtln_tap_{}_{}_{}_{}.wav",
+                std::process::id(),
+                self.tap,
+                self.file,
+                kind
+            ))
+        };
+        let paths = (path("raw"), path("denoised"));
+        let writer = |path: &PathBuf| -> Result<WavWriter> {
+            let name = path
+                .to_str()
+                .with_context(|| format!("Invalid tap path {}", path.display()))?;
+            Ok(WavWriter::new(name, DTLN_SAMPLE_RATE, 32)?)
+        };
+        self.writers = Some((writer(&paths.0)?, writer(&paths.1)?));
+        self.files.push_back(paths);
+        self.file += 1;
+
+        while self.files.len() > self.config.max_files {
+            if let Some((raw, denoised)) = self.files.pop_front() {
+                for path in [raw, denoised] {
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        dtln_warn!("Unable to remove {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        if let Some((raw, denoised)) = self.writers.take() {
+            raw.finalize()?;
+            denoised.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtln_processor::{DtlnImmediateProcessor, DtlnProcessEngine};
+    use crate::dtln_utilities::read_wav_to_pcm32;
+
+    #[test]
+    fn test_debug_tap() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dtln_debug_tap_{}", std::process::id()));
+        let input = (0..DTLN_SAMPLE_RATE as usize / 2)
+            .map(|_| (rand::random::<f32>() - 0.5) * 0.5)
+            .collect::<Vec<f32>>();
+
+        let mut processor = DtlnImmediateProcessor::new()?;
+        processor.set_debug_tap(Some(TapConfig::new(&dir)))?;
+        let mut output = vec![];
+        for chunk in input.chunks(1000) {
+            output.extend(processor.denoise(chunk)?.samples);
+        }
+        let files = processor
+            .debug_tap()
+            .unwrap()
+            .files()
+            .cloned()
+            .collect::<Vec<_>>();
+        processor.set_debug_tap(None)?;
+
+        // One pair, holding the input and the output exactly.
+        assert_eq!(files.len(), 1);
+        let (mut raw, mut denoised) = (vec![], vec![]);
+        read_wav_to_pcm32(files[0].0.to_str().unwrap(), &mut raw)?;
+        read_wav_to_pcm32(files[0].1.to_str().unwrap(), &mut denoised)?;
+        assert_eq!(raw, input);
+        assert_eq!(denoised, output);
+
+        // Rotation keeps the newest pairs, each up to the size limit.
+        let mut config = TapConfig::new(dir.join("rotation"));
+        config.max_file_samples = 3000;
+        config.max_files = 2;
+        let mut tap = DebugTap::new(config)?;
+        for chunk in input.chunks(1000) {
+            tap.write(chunk, chunk)?;
+        }
+        let kept = tap.files().cloned().collect::<Vec<_>>();
+        drop(tap);
+        assert_eq!(kept.len(), 2);
+        for (raw, denoised) in kept.iter() {
+            let mut samples = vec![];
+            read_wav_to_pcm32(raw.to_str().unwrap(), &mut samples)?;
+            assert!(samples.len() <= 3000);
+            assert!(denoised.exists());
+        }
+        let recorded = std::fs::read_dir(dir.join("rotation"))?
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with("_raw.wav")
+            })
+            .count();
+        assert_eq!(recorded, 2);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
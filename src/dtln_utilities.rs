@@ -1,48 +1,822 @@
 // FFI Wrappers and raw interfaces to DTLN engine.
+use byteorder::{ByteOrder, LittleEndian};
 use once_cell::sync::Lazy;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::collections::HashMap;
+use std::f32::consts::PI;
 use std::fs::File;
-use std::io::Result;
+use std::io::{BufWriter, Cursor, Read, Result, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::Mutex;
-use wav::Header;
+use std::time::Duration;
 
+use crate::constants::{DTLN_BLOCK_SHIFT, DTLN_LATENCY, DTLN_SAMPLE_RATE};
+use crate::dtln_dsp::resample_linear;
 use crate::dtln_engine::{dtln_create, dtln_denoise, DtlnEngine};
+use crate::dtln_error::DtlnError;
+use crate::dtln_processor::{offline_processor, trim_tail_silence, DtlnProcessEngine, TailTrim};
+use crate::dtln_stats::{ProcessingReport, ReportBuilder};
 
 pub fn write_pcm32_to_wav(samples: Vec<f32>, filename: &str, audiorate: u32) -> Result<()> {
-    // Convert to s16
-    let header = Header::new(wav::WAV_FORMAT_IEEE_FLOAT, 1, audiorate, 32);
-    let mut writer = File::create(Path::new(filename))?;
-    wav::write(header, &wav::BitDepth::ThirtyTwoFloat(samples), &mut writer)?;
+    let bytes = write_pcm32_to_wav_bytes(samples, audiorate)?;
+    File::create(Path::new(filename))?.write_all(&bytes)
+}
+
+/**
+ * Encodes the samples as a 32-bit float WAV (WAVE_FORMAT_IEEE_FLOAT) in memory. The
+ * samples are stored as is, without clamping or quantization, so reading the file back
+ * recovers them bit for bit.
+ */
+pub fn write_pcm32_to_wav_bytes(samples: Vec<f32>, audiorate: u32) -> Result<Vec<u8>> {
+    let mut bytes = wav_header(32, audiorate, samples.len() * 4)?;
+    bytes.reserve(samples.len() * 4);
+    for sample in samples.iter() {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+// Size of the RIFF, fmt and data chunk headers of an integer PCM file.
+const WAV_HEADER_SIZE: usize = 44;
+// A float file's fmt chunk adds the 2 byte cbSize field, and its fact chunk 12 bytes.
+const WAV_FLOAT_HEADER_SIZE: usize = WAV_HEADER_SIZE + 2 + 12;
+
+/**
+ * Header of a mono WAV with `data_len` bytes of samples, `bit_depth` 16 or 24 for
+ * integer PCM or 32 for float. Float files carry the cbSize field the format requires
+ * of non-PCM fmt chunks and a fact chunk with the sample count, which strict readers
+ * insist on.
+ */
+fn wav_header(bit_depth: u16, audiorate: u32, data_len: usize) -> Result<Vec<u8>> {
+    let (format, len) = match bit_depth {
+        16 | 24 => (wav::WAV_FORMAT_PCM, WAV_HEADER_SIZE),
+        32 => (wav::WAV_FORMAT_IEEE_FLOAT, WAV_FLOAT_HEADER_SIZE),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unsupported bit depth {}", bit_depth),
+            ))
+        }
+    };
+    if data_len > u32::MAX as usize - len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "WAV file too large",
+        ));
+    }
+
+    let block_align = bit_depth / 8;
+    let fmt_len = if format == wav::WAV_FORMAT_PCM {
+        16
+    } else {
+        18
+    };
+    let mut header = vec![0u8; len];
+    header[0..4].copy_from_slice(b"RIFF");
+    // The data chunk is padded to an even length.
+    LittleEndian::write_u32(
+        &mut header[4..8],
+        (len - 8 + data_len + (data_len & 1)) as u32,
+    );
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    LittleEndian::write_u32(&mut header[16..20], fmt_len);
+    LittleEndian::write_u16(&mut header[20..22], format);
+    LittleEndian::write_u16(&mut header[22..24], 1);
+    LittleEndian::write_u32(&mut header[24..28], audiorate);
+    LittleEndian::write_u32(&mut header[28..32], audiorate * block_align as u32);
+    LittleEndian::write_u16(&mut header[32..34], block_align);
+    LittleEndian::write_u16(&mut header[34..36], bit_depth);
+    let mut offset = 36;
+    if format == wav::WAV_FORMAT_IEEE_FLOAT {
+        // cbSize stays 0, then the fact chunk.
+        header[38..42].copy_from_slice(b"fact");
+        LittleEndian::write_u32(&mut header[42..46], 4);
+        LittleEndian::write_u32(
+            &mut header[46..50],
+            (data_len / block_align as usize) as u32,
+        );
+        offset = 50;
+    }
+    header[offset..offset + 4].copy_from_slice(b"data");
+    LittleEndian::write_u32(&mut header[offset + 4..offset + 8], data_len as u32);
+    Ok(header)
+}
+
+/**
+ * Writes a mono WAV file incrementally, so offline processing doesn't need to hold the
+ * whole output in memory. `bit_depth` is 16 or 24 for integer PCM, or 32 for float.
+ * The chunk sizes are back-patched by finalize; a writer dropped without calling it is
+ * finalized on drop, with any error reported on stderr since it can't be returned.
+ */
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    audiorate: u32,
+    bit_depth: u16,
+    data_len: usize,
+    finalized: bool,
+}
+
+impl WavWriter {
+    pub fn new(filename: &str, audiorate: u32, bit_depth: u16) -> Result<WavWriter> {
+        let header = wav_header(bit_depth, audiorate, 0)?;
+        let mut writer = BufWriter::new(File::create(Path::new(filename))?);
+        writer.write_all(&header)?;
+        Ok(WavWriter {
+            writer,
+            audiorate,
+            bit_depth,
+            data_len: 0,
+            finalized: false,
+        })
+    }
+
+    // Appends samples, clamped to [-1, 1] for the integer formats.
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let sample_len = self.bit_depth as usize / 8;
+        // Checks the size limit.
+        wav_header(
+            self.bit_depth,
+            self.audiorate,
+            self.data_len + samples.len() * sample_len,
+        )?;
+
+        let mut bytes = vec![0u8; samples.len() * sample_len];
+        for (sample, bytes) in samples.iter().zip(bytes.chunks_exact_mut(sample_len)) {
+            match self.bit_depth {
+                16 => LittleEndian::write_i16(bytes, (sample.clamp(-1.0, 1.0) * 32767.0) as i16),
+                24 => LittleEndian::write_i24(bytes, (sample.clamp(-1.0, 1.0) * 8388607.0) as i32),
+                _ => LittleEndian::write_f32(bytes, *sample),
+            }
+        }
+        self.writer.write_all(&bytes)?;
+        self.data_len += bytes.len();
+        Ok(())
+    }
+
+    // Number of samples written so far.
+    pub fn len(&self) -> usize {
+        self.data_len / (self.bit_depth as usize / 8)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data_len == 0
+    }
+
+    // Writes the final chunk sizes and flushes the file.
+    pub fn finalize(mut self) -> Result<()> {
+        self.finalized = true;
+        self.patch_sizes()
+    }
+
+    // Rewrites the header with the sizes and sample count of the data written so far.
+    fn patch_sizes(&mut self) -> Result<()> {
+        // The data chunk is padded to an even length.
+        if self.finalized && self.data_len & 1 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let header = wav_header(self.bit_depth, self.audiorate, self.data_len)?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finalized {
+            self.finalized = true;
+            if let Err(e) = self.patch_sizes() {
+                dtln_error!("Failed to finalize WAV file: {}", e);
+            }
+        }
+    }
+}
+
+/**
+ * Ancillary WAV chunks carrying sample positions (cue markers and sampler loop points).
+ * The chunks are kept as raw bytes so they are re-emitted unchanged, apart from any
+ * position adjustment made with offset_positions.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WavMarkers {
+    pub cue: Option<Vec<u8>>,
+    pub smpl: Option<Vec<u8>>,
+}
+
+const WAV_CUE_HEADER_SIZE: usize = 4;
+const WAV_CUE_POINT_SIZE: usize = 24;
+const WAV_SMPL_HEADER_SIZE: usize = 36;
+const WAV_SMPL_LOOP_SIZE: usize = 24;
+
+impl WavMarkers {
+    pub fn is_empty(&self) -> bool {
+        self.cue.is_none() && self.smpl.is_none()
+    }
+
+    /**
+     * Shifts every sample position by `delta` samples, clamping at the start of the file.
+     * Use a negative delta when samples have been trimmed from the start of the audio.
+     */
+    pub fn offset_positions(&mut self, delta: i64) {
+        let shift = |data: &mut [u8], offset: usize| {
+            let position = LittleEndian::read_u32(&data[offset..]) as i64;
+            let shifted = (position + delta).clamp(0, u32::MAX as i64) as u32;
+            LittleEndian::write_u32(&mut data[offset..], shifted);
+        };
+
+        if let Some(cue) = self.cue.as_mut() {
+            for point in 0..wav_record_count(cue, 0, WAV_CUE_HEADER_SIZE, WAV_CUE_POINT_SIZE) {
+                let base = WAV_CUE_HEADER_SIZE + point * WAV_CUE_POINT_SIZE;
+                // Position and sample offset fields.
+                shift(cue, base + 4);
+                shift(cue, base + 20);
+            }
+        }
+
+        if let Some(smpl) = self.smpl.as_mut() {
+            for sample_loop in
+                0..wav_record_count(smpl, 28, WAV_SMPL_HEADER_SIZE, WAV_SMPL_LOOP_SIZE)
+            {
+                let base = WAV_SMPL_HEADER_SIZE + sample_loop * WAV_SMPL_LOOP_SIZE;
+                // Loop start and end fields.
+                shift(smpl, base + 8);
+                shift(smpl, base + 12);
+            }
+        }
+    }
+}
+
+// Number of fixed size records in a chunk, bounded by the bytes actually present.
+fn wav_record_count(data: &[u8], count_offset: usize, header: usize, record: usize) -> usize {
+    if data.len() < header {
+        return 0;
+    }
+    let declared = LittleEndian::read_u32(&data[count_offset..]) as usize;
+    declared.min((data.len() - header) / record)
+}
+
+/**
+ * Reads the cue and smpl chunks of a WAV file, if present.
+ */
+pub fn read_wav_markers(input: &str) -> Result<WavMarkers> {
+    let mut bytes = vec![];
+    File::open(Path::new(input))?.read_to_end(&mut bytes)?;
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Not a RIFF/WAVE file",
+        ));
+    }
+
+    let mut markers = WavMarkers::default();
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = LittleEndian::read_u32(&bytes[offset + 4..offset + 8]) as usize;
+        let start = offset + 8;
+        let end = (start + size).min(bytes.len());
+
+        match id {
+            b"cue " => markers.cue = Some(bytes[start..end].to_vec()),
+            b"smpl" => markers.smpl = Some(bytes[start..end].to_vec()),
+            _ => {}
+        }
+
+        // Chunks are padded to an even size.
+        offset = start + size + (size & 1);
+    }
+
+    Ok(markers)
+}
+
+/**
+ * Writes the samples as a 32-bit float WAV, followed by the given cue/smpl chunks.
+ */
+pub fn write_pcm32_to_wav_with_markers(
+    samples: Vec<f32>,
+    filename: &str,
+    audiorate: u32,
+    markers: &WavMarkers,
+) -> Result<()> {
+    let mut bytes = write_pcm32_to_wav_bytes(samples, audiorate)?;
+    append_wav_markers(&mut bytes, markers);
+
+    File::create(Path::new(filename))?.write_all(&bytes)
+}
+
+// Appends the marker chunks to an encoded WAV and fixes up the RIFF size.
+fn append_wav_markers(bytes: &mut Vec<u8>, markers: &WavMarkers) {
+    for (id, data) in [(b"cue ", &markers.cue), (b"smpl", &markers.smpl)] {
+        let Some(data) = data else {
+            continue;
+        };
+        bytes.extend_from_slice(id);
+        let mut size = [0u8; 4];
+        LittleEndian::write_u32(&mut size, data.len() as u32);
+        bytes.extend_from_slice(&size);
+        bytes.extend_from_slice(data);
+        if data.len() & 1 == 1 {
+            bytes.push(0);
+        }
+    }
+
+    let riff_size = bytes.len() as u32 - 8;
+    LittleEndian::write_u32(&mut bytes[4..8], riff_size);
+}
+
+/**
+ * A WAV sample encoding, for listing formats. The discriminants are the FFI values, see
+ * dtln_rs_supported_wav_formats.
+ */
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    // 16 bit signed integer PCM.
+    I16 = 0,
+    // 24 bit signed integer PCM.
+    I24 = 1,
+    // 32-bit IEEE float.
+    F32 = 2,
+}
+
+impl SampleFormat {
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            SampleFormat::I16 => 16,
+            SampleFormat::I24 => 24,
+            SampleFormat::F32 => 32,
+        }
+    }
+
+    // Short name, e.g. for a UI or the Neon bindings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SampleFormat::I16 => "i16",
+            SampleFormat::I24 => "i24",
+            SampleFormat::F32 => "f32",
+        }
+    }
+}
+
+/**
+ * Mono WAV sample formats both read_wav_to_pcm32 and WavWriter handle. WavWriter also
+ * writes SampleFormat::I24, which the reader doesn't accept.
+ */
+pub fn supported_wav_formats() -> &'static [SampleFormat] {
+    &[SampleFormat::I16, SampleFormat::F32]
+}
+
+// Format details of a WAV file read by read_wav_to_pcm32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WavInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub sample_count: usize,
+}
+
+/**
+ * Checks that `name` looks like a WAV path, and if `check_exists` is set, that it is an
+ * existing file.
+ */
+pub fn validate_wav_path(name: &str, check_exists: bool) -> std::result::Result<(), DtlnError> {
+    let path = Path::new(name);
+
+    if check_exists {
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Err(DtlnError::NotFound(name.to_string())),
+            Err(error) => return Err(DtlnError::from_io(name, &error)),
+        }
+    }
+
+    match path.extension() {
+        Some(extension) if extension.eq_ignore_ascii_case("wav") => Ok(()),
+        _ => Err(DtlnError::NotWav(name.to_string())),
+    }
+}
+
+// Checks the RIFF structure, so truncated files are reported as such rather than as
+// generic decode errors.
+fn validate_wav_bytes(name: &str, bytes: &[u8]) -> std::result::Result<(), DtlnError> {
+    if bytes.len() < 12 {
+        if bytes.len() >= 4 && &bytes[0..4] == b"RIFF" {
+            return Err(DtlnError::Truncated(name.to_string()));
+        }
+        return Err(DtlnError::NotWav(name.to_string()));
+    }
+    if &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(DtlnError::NotWav(name.to_string()));
+    }
+
+    let mut has_data = false;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let size = LittleEndian::read_u32(&bytes[offset + 4..offset + 8]) as usize;
+        if offset + 8 + size > bytes.len() {
+            return Err(DtlnError::Truncated(name.to_string()));
+        }
+        has_data |= &bytes[offset..offset + 4] == b"data";
+        offset += 8 + size + (size & 1);
+    }
+
+    if !has_data {
+        return Err(DtlnError::Truncated(name.to_string()));
+    }
     Ok(())
 }
 
-pub fn read_wav_to_pcm32(input: &str, samples: &mut Vec<f32>) -> Result<u32> {
+/**
+ * Reads a 16 bit or 32-bit float mono WAV file into `samples` as 32-bit float. Float
+ * samples are returned exactly as stored.
+ */
+pub fn read_wav_to_pcm32(
+    input: &str,
+    samples: &mut Vec<f32>,
+) -> std::result::Result<WavInfo, DtlnError> {
+    let mut bytes = vec![];
+    File::open(Path::new(input))
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|error| DtlnError::from_io(input, &error))?;
+
+    decode_wav_to_pcm32(input, &bytes, samples)
+}
+
+/**
+ * Decodes a 16 bit or 32-bit float mono WAV held in memory into `samples` as 32-bit
+ * float.
+ */
+pub fn read_wav_bytes_to_pcm32(
+    bytes: &[u8],
+    samples: &mut Vec<f32>,
+) -> std::result::Result<WavInfo, DtlnError> {
+    decode_wav_to_pcm32("input", bytes, samples)
+}
+
+// Shared by the file and in-memory readers, `input` names the source in errors.
+fn decode_wav_to_pcm32(
+    input: &str,
+    bytes: &[u8],
+    samples: &mut Vec<f32>,
+) -> std::result::Result<WavInfo, DtlnError> {
     samples.clear();
-    let mut inp_file = File::open(Path::new(input))?;
+    validate_wav_bytes(input, bytes)?;
 
-    let (header, data) = wav::read(&mut inp_file)?;
-    let data = data.try_into_sixteen().unwrap();
+    let (header, data) = wav::read(&mut Cursor::new(bytes))
+        .map_err(|e| DtlnError::UnsupportedFormat(format!("{}: {}", input, e)))?;
 
-    samples.reserve(data.len());
+    // The sample clips are 16 bit mono, and this crate's own output float mono.
+    match data {
+        wav::BitDepth::Sixteen(data) if header.channel_count == 1 => {
+            samples.reserve(data.len());
 
-    // The sample clips are only 16 bit mono.
-    assert_eq!(header.bits_per_sample, 16);
-    assert_eq!(header.channel_count, 1);
+            // Convert 16 bit pcm samples in data to 32-bit float
+            for sample in data.iter() {
+                samples.push((*sample as f32 / u16::MAX as f32).clamp(-1.0, 1.0));
+            }
+        }
+        wav::BitDepth::ThirtyTwoFloat(data) if header.channel_count == 1 => {
+            samples.extend_from_slice(&data);
+        }
+        _ => {
+            return Err(DtlnError::UnsupportedFormat(format!(
+                "{}: {} bit, {} channels, expected 16 bit or 32-bit float mono",
+                input, header.bits_per_sample, header.channel_count
+            )))
+        }
+    }
+
+    Ok(WavInfo {
+        sample_rate: header.sampling_rate,
+        channels: header.channel_count,
+        bits_per_sample: header.bits_per_sample,
+        sample_count: samples.len(),
+    })
+}
+
+/**
+ * Denoises a complete WAV file held in memory and returns it encoded as a 32-bit float
+ * WAV at the input's sample rate, for offline use in the browser. Input at other rates
+ * is resampled to DTLN_SAMPLE_RATE for processing and back afterwards. The output is
+ * aligned with the input, with the processing latency removed.
+ */
+pub fn denoise_wav_bytes(input: &[u8]) -> std::result::Result<Vec<u8>, DtlnError> {
+    Ok(denoise_wav_bytes_with_report(input)?.0)
+}
+
+/**
+ * denoise_wav_bytes, also returning a ProcessingReport on the input and output at the
+ * input's sample rate.
+ */
+pub fn denoise_wav_bytes_with_report(
+    input: &[u8],
+) -> std::result::Result<(Vec<u8>, ProcessingReport), DtlnError> {
+    let mut samples = vec![];
+    let info = read_wav_bytes_to_pcm32(input, &mut samples)?;
+    let (output, mut report) = denoise_wav_samples(&samples, info.sample_rate)?;
+    report.add_output(&output);
+    let report = report.finish(info.sample_rate != DTLN_SAMPLE_RATE);
+    let bytes = write_pcm32_to_wav_bytes(output, info.sample_rate)
+        .map_err(|e| DtlnError::Processing(e.to_string()))?;
+    Ok((bytes, report))
+}
+
+// Denoises decoded WAV samples at `sample_rate` for denoise_wav_bytes_with_report and
+// denoise_wav_file, returning the aligned output and a report holding the input levels.
+fn denoise_wav_samples(
+    samples: &[f32],
+    sample_rate: u32,
+) -> std::result::Result<(Vec<f32>, ReportBuilder), DtlnError> {
+    if sample_rate == 0 {
+        return Err(DtlnError::UnsupportedFormat("sample rate of 0".to_string()));
+    }
 
-    // Convert 16 bit pcm samples in data to 32-bit float
-    for sample in data.iter() {
-        let mut fsample = *sample as f32 / std::u16::MAX as f32;
-        if fsample > 1.0 {
-            fsample = 1.0;
+    let mut report = ReportBuilder::default();
+    report.add_input(samples);
+    let samples = resample_linear(samples, sample_rate, DTLN_SAMPLE_RATE);
+    let processing_error = |e: anyhow::Error| DtlnError::Processing(e.to_string());
+    let mut processor = offline_processor().map_err(processing_error)?;
+    let mut output = processor.try_denoise(&samples)?.samples;
+    output.extend(processor.flush().map_err(processing_error)?);
+    let output = output
+        .get(DTLN_LATENCY..DTLN_LATENCY + samples.len())
+        .unwrap_or_default();
+    Ok((
+        resample_linear(output, DTLN_SAMPLE_RATE, sample_rate),
+        report,
+    ))
+}
+
+/**
+ * Denoises a WAV file like denoise_wav_bytes, writing the output to `output`. With a
+ * `trim`, trailing silence is dropped from the output, see trim_tail_silence; None keeps
+ * it as long as the input. Returns a ProcessingReport on the input and the output as
+ * written, so output_samples is the number of samples written.
+ */
+pub fn denoise_wav_file(
+    input: &str,
+    output: &str,
+    trim: Option<TailTrim>,
+) -> std::result::Result<ProcessingReport, DtlnError> {
+    let mut samples = vec![];
+    let info = read_wav_to_pcm32(input, &mut samples)?;
+    let (mut samples, mut report) = denoise_wav_samples(&samples, info.sample_rate)?;
+    if let Some(trim) = trim {
+        trim_tail_silence(&mut samples, trim, info.sample_rate);
+    }
+    report.add_output(&samples);
+    write_pcm32_to_wav(samples, output, info.sample_rate)
+        .map_err(|e| DtlnError::Processing(e.to_string()))?;
+    Ok(report.finish(info.sample_rate != DTLN_SAMPLE_RATE))
+}
+
+/**
+ * Denoises a WAV file like denoise_wav_bytes, writing the output to `output` and the
+ * per-block speech probability (see DtlnEngine::speech_probability) to `sidecar`, for
+ * driving segmentation downstream. The sidecar has one value per DTLN_BLOCK_SHIFT
+ * samples at DTLN_SAMPLE_RATE, 125 per second, with value n covering the input up to
+ * the end of block n, and is written as JSON if its name ends in .json, otherwise as a
+ * mono 32-bit float WAV at that rate. Returns the values written.
+ */
+pub fn denoise_wav_file_with_vad(
+    input: &str,
+    output: &str,
+    sidecar: &str,
+) -> std::result::Result<Vec<f32>, DtlnError> {
+    let mut samples = vec![];
+    let info = read_wav_to_pcm32(input, &mut samples)?;
+    if info.sample_rate == 0 {
+        return Err(DtlnError::UnsupportedFormat("sample rate of 0".to_string()));
+    }
+    let samples = resample_linear(&samples, info.sample_rate, DTLN_SAMPLE_RATE);
+
+    let processing_error = |e: anyhow::Error| DtlnError::Processing(e.to_string());
+    let mut processor = offline_processor().map_err(processing_error)?;
+    // Pad to whole blocks, so every block, including a partial last one, is inferred
+    // while its speech probability can still be read.
+    let blocks = samples.len().div_ceil(DTLN_BLOCK_SHIFT);
+    let mut padded = samples.clone();
+    padded.resize(blocks * DTLN_BLOCK_SHIFT, 0.0);
+    let mut denoised = Vec::with_capacity(padded.len() + DTLN_LATENCY);
+    let mut speech = Vec::with_capacity(blocks);
+    for block in padded.chunks(DTLN_BLOCK_SHIFT) {
+        denoised.extend(processor.try_denoise(block)?.samples);
+        speech.push(processor.engine.speech_probability());
+    }
+    denoised.extend(processor.flush().map_err(processing_error)?);
+
+    let denoised = denoised
+        .get(DTLN_LATENCY..DTLN_LATENCY + samples.len())
+        .unwrap_or_default();
+    let denoised = resample_linear(denoised, DTLN_SAMPLE_RATE, info.sample_rate);
+    let write_error = |e: std::io::Error| DtlnError::Processing(e.to_string());
+    write_pcm32_to_wav(denoised, output, info.sample_rate).map_err(write_error)?;
+
+    let block_rate = DTLN_SAMPLE_RATE / DTLN_BLOCK_SHIFT as u32;
+    if Path::new(sidecar)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+    {
+        let values = speech
+            .iter()
+            .map(|value| format!("{:.4}", value))
+            .collect::<Vec<String>>()
+            .join(",");
+        let json = format!("{{\"block_rate\":{},\"speech\":[{}]}}", block_rate, values);
+        std::fs::write(sidecar, json).map_err(write_error)?;
+    } else {
+        write_pcm32_to_wav(speech.clone(), sidecar, block_rate).map_err(write_error)?;
+    }
+    Ok(speech)
+}
+
+// Checks that `len` samples split into whole frames of `channels` channels.
+fn validate_channel_layout(len: usize, channels: usize) -> Result<usize> {
+    if channels == 0 || !len.is_multiple_of(channels) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} samples don't divide into {} channels", len, channels),
+        ));
+    }
+    Ok(len / channels)
+}
+
+/**
+ * Converts planar audio, each channel's samples contiguous one channel after the other,
+ * to interleaved frames. Works for f32 and i16 samples alike. Fails if the length isn't
+ * a multiple of `channels`.
+ */
+pub fn interleave<T: Copy>(planar: &[T], channels: usize) -> Result<Vec<T>> {
+    let frames = validate_channel_layout(planar.len(), channels)?;
+    Ok((0..planar.len())
+        .map(|i| planar[(i % channels) * frames + i / channels])
+        .collect())
+}
+
+// Inverse of interleave.
+pub fn deinterleave<T: Copy>(interleaved: &[T], channels: usize) -> Result<Vec<T>> {
+    let frames = validate_channel_layout(interleaved.len(), channels)?;
+    Ok((0..interleaved.len())
+        .map(|i| interleaved[(i % frames) * channels + i / frames])
+        .collect())
+}
+
+// RMS level of the Babble and NoisySpeech speech, -20 dBFS, unless that would peak
+// above TEST_SPEECH_PEAK.
+const TEST_SPEECH_RMS: f32 = 0.1;
+const TEST_SPEECH_PEAK: f32 = 0.9;
+// Peak level of the Sine and Sweep signals and of the uniform white noise.
+const TEST_TONE_PEAK: f32 = 0.5;
+
+// Synthetic signals produced by generate_test_signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TestSignal {
+    Sine {
+        hz: f32,
+    },
+    // Linear frequency sweep.
+    Sweep {
+        from_hz: f32,
+        to_hz: f32,
+    },
+    // Uniform white noise.
+    WhiteNoise {
+        seed: u64,
+    },
+    /**
+     * Speech-like babble: a glottal pulse train with a wandering pitch, shaped by three
+     * formant resonators that move every syllable, with syllabic loudness and short
+     * pauses. Not intelligible, but with the spectral and temporal structure of speech.
+     */
+    Babble {
+        seed: u64,
+    },
+    /**
+     * Babble with the same seed, plus white noise scaled so the speech to noise power
+     * ratio over the whole signal is exactly `snr_db`. Not clipped, so low SNRs can
+     * exceed full scale.
+     */
+    NoisySpeech {
+        snr_db: f32,
+        seed: u64,
+    },
+}
+
+/**
+ * Generates `duration` of a synthetic mono test signal at `sample_rate`, so tests and
+ * examples don't need recorded fixtures. The output depends only on the arguments.
+ */
+pub fn generate_test_signal(kind: TestSignal, duration: Duration, sample_rate: u32) -> Vec<f32> {
+    let len = (duration.as_secs_f64() * sample_rate as f64).round() as usize;
+    let rate = sample_rate as f32;
+    match kind {
+        TestSignal::Sine { hz } => (0..len)
+            .map(|i| TEST_TONE_PEAK * (2.0 * PI * hz * i as f32 / rate).sin())
+            .collect(),
+        TestSignal::Sweep { from_hz, to_hz } => {
+            let seconds = len as f64 / sample_rate as f64;
+            (0..len)
+                .map(|i| {
+                    let t = i as f64 / sample_rate as f64;
+                    let slope = (to_hz - from_hz) as f64 / seconds.max(f64::EPSILON);
+                    let phase = 2.0 * std::f64::consts::PI * (from_hz as f64 + slope * t / 2.0) * t;
+                    TEST_TONE_PEAK * phase.sin() as f32
+                })
+                .collect()
         }
-        if fsample < -1.0 {
-            fsample = -1.0;
+        TestSignal::WhiteNoise { seed } => {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..len)
+                .map(|_| rng.gen_range(-TEST_TONE_PEAK..TEST_TONE_PEAK))
+                .collect()
+        }
+        TestSignal::Babble { seed } => babble(len, rate, seed),
+        TestSignal::NoisySpeech { snr_db, seed } => {
+            let speech = babble(len, rate, seed);
+            // A different stream from the babble's, so the noise isn't correlated with it.
+            let noise = generate_test_signal(
+                TestSignal::WhiteNoise {
+                    seed: seed ^ 0x9e37_79b9_7f4a_7c15,
+                },
+                duration,
+                sample_rate,
+            );
+            let power = |samples: &[f32]| samples.iter().map(|s| (s * s) as f64).sum::<f64>();
+            let gain = match power(&noise) {
+                0.0 => 0.0,
+                noise_power => {
+                    (power(&speech) / noise_power / 10f64.powf(snr_db as f64 / 10.0)).sqrt()
+                }
+            };
+            speech
+                .iter()
+                .zip(noise.iter())
+                .map(|(speech, noise)| speech + noise * gain as f32)
+                .collect()
         }
-        samples.push(fsample);
     }
+}
 
-    Ok(header.sampling_rate)
+fn babble(len: usize, rate: f32, seed: u64) -> Vec<f32> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut output = Vec::with_capacity(len);
+    // Two pole resonator state per formant.
+    let mut formants = [[0.0f32; 2]; 3];
+    let mut phase = 0.0f32;
+    while output.len() < len {
+        // One syllable, or a pause of silence.
+        let syllable = (rate * rng.gen_range(0.12..0.3)) as usize;
+        if rng.gen_bool(0.15) {
+            output.extend(std::iter::repeat_n(0.0, syllable.min(len - output.len())));
+            continue;
+        }
+        let pitch = rng.gen_range(90.0..240.0);
+        let glide = rng.gen_range(-0.3..0.3);
+        let centres = [
+            rng.gen_range(300.0..900.0),
+            rng.gen_range(900.0..2400.0),
+            rng.gen_range(2400.0..3400.0),
+        ];
+        let coefficients = centres.map(|hz: f32| {
+            // Bandwidths widen with frequency, roughly as in speech.
+            let radius = (-PI * (60.0 + hz * 0.06) / rate).exp();
+            let theta = 2.0 * PI * hz.min(rate * 0.45) / rate;
+            (2.0 * radius * theta.cos(), -radius * radius)
+        });
+        let loudness = rng.gen_range(0.5..1.0);
+        for n in 0..syllable.min(len - output.len()) {
+            let progress = n as f32 / syllable as f32;
+            phase += pitch * (1.0 + glide * progress) / rate;
+            let mut sample = 0.0;
+            if phase >= 1.0 {
+                phase -= 1.0;
+                sample = 1.0;
+            }
+            // Aspiration noise, as in breathy voicing.
+            sample += rng.gen_range(-0.02..0.02);
+            let mut voiced = 0.0;
+            for (state, (a1, a2)) in formants.iter_mut().zip(coefficients.iter()) {
+                let y = sample + a1 * state[0] + a2 * state[1];
+                state[1] = state[0];
+                state[0] = y;
+                voiced += y;
+            }
+            let envelope = loudness * (PI * progress).sin();
+            output.push(voiced * envelope);
+        }
+    }
+
+    let rms = (output.iter().map(|s| (s * s) as f64).sum::<f64>() / len.max(1) as f64).sqrt();
+    let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if rms > 0.0 {
+        let gain = (TEST_SPEECH_RMS / rms as f32).min(TEST_SPEECH_PEAK / peak);
+        output.iter_mut().for_each(|sample| *sample *= gain);
+    }
+    output
 }
 
 const WASM_AUDIO_BLOCK_SIZE: usize = 512;
@@ -122,9 +896,582 @@ pub fn dtln_denoise_global(id: u32) -> Result<()> {
         audio_buffer.data.copy_from_slice(&out);
         Ok(())
     } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Failed to denoise",
-        ))
+        Err(std::io::Error::other("Failed to denoise"))
+    }
+}
+
+// Number of samples dtln_process_block_global takes and returns for the engine.
+pub fn dtln_block_shift_global(id: u32) -> Option<usize> {
+    let engine_map = ENGINE_MAP.lock().unwrap();
+    engine_map
+        .get(&id)
+        .map(|engine| engine.overlap().block_shift())
+}
+
+/**
+ * Denoise exactly one engine block of caller-owned samples, for callers that do their
+ * own buffering. `input` and `output` must both hold dtln_block_shift_global samples.
+ * The engine keeps its state between calls, so blocks must be passed in order.
+ * @param id The unique id of the engine to use.
+ */
+pub fn dtln_process_block_global(
+    id: u32,
+    input: &[f32],
+    output: &mut [f32],
+) -> std::result::Result<(), DtlnError> {
+    let mut engine_map = ENGINE_MAP.lock()?;
+    let Some(engine) = engine_map.get_mut(&id) else {
+        return Err(DtlnError::Processing(format!(
+            "Engine not found for {}",
+            id
+        )));
+    };
+
+    let block_shift = engine.overlap().block_shift();
+    if input.len() != block_shift || output.len() != block_shift {
+        return Err(DtlnError::Processing(format!(
+            "Expected blocks of exactly {} samples, got {} in and {} out",
+            block_shift,
+            input.len(),
+            output.len()
+        )));
+    }
+    engine.denoise(input, output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wav::Header;
+
+    fn u32_bytes(values: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![0u8; values.len() * 4];
+        LittleEndian::write_u32_into(values, &mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn test_generate_test_signal() {
+        let second = Duration::from_secs(1);
+        let seed = 7;
+        for kind in [
+            TestSignal::Sine { hz: 440.0 },
+            TestSignal::Sweep {
+                from_hz: 100.0,
+                to_hz: 7000.0,
+            },
+            TestSignal::WhiteNoise { seed },
+            TestSignal::Babble { seed },
+        ] {
+            let signal = generate_test_signal(kind, second, DTLN_SAMPLE_RATE);
+            assert_eq!(signal.len(), DTLN_SAMPLE_RATE as usize);
+            assert!(signal.iter().all(|s| s.is_finite() && s.abs() <= 1.0));
+            assert!(signal.iter().any(|s| *s != 0.0));
+        }
+
+        // Reproducible for a seed, different across seeds.
+        let noisy = TestSignal::NoisySpeech { snr_db: 5.0, seed };
+        let signal = generate_test_signal(noisy, 3 * second, DTLN_SAMPLE_RATE);
+        assert_eq!(
+            signal,
+            generate_test_signal(noisy, 3 * second, DTLN_SAMPLE_RATE)
+        );
+        let other = TestSignal::NoisySpeech {
+            snr_db: 5.0,
+            seed: 8,
+        };
+        assert_ne!(
+            signal,
+            generate_test_signal(other, 3 * second, DTLN_SAMPLE_RATE)
+        );
+
+        // The speech is the babble of the same seed, the rest is noise at the set SNR.
+        for snr_db in [-5.0, 0.0, 10.0, 20.0] {
+            let kind = TestSignal::NoisySpeech { snr_db, seed };
+            let signal = generate_test_signal(kind, 3 * second, DTLN_SAMPLE_RATE);
+            let speech =
+                generate_test_signal(TestSignal::Babble { seed }, 3 * second, DTLN_SAMPLE_RATE);
+            let (speech_power, noise_power) = speech.iter().zip(signal.iter()).fold(
+                (0.0f64, 0.0f64),
+                |(speech_power, noise_power), (speech, signal)| {
+                    let noise = (signal - speech) as f64;
+                    (
+                        speech_power + (speech * speech) as f64,
+                        noise_power + noise * noise,
+                    )
+                },
+            );
+            let measured = 10.0 * (speech_power / noise_power).log10();
+            assert!(
+                (measured - snr_db as f64).abs() < 0.1,
+                "{} vs {}",
+                measured,
+                snr_db
+            );
+        }
+    }
+
+    #[test]
+    fn test_vad_sidecar() -> std::result::Result<(), DtlnError> {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let input = dir.join(format!("dtln_vad_in_{}.wav", id));
+        let output = dir.join(format!("dtln_vad_out_{}.wav", id));
+        let json = dir.join(format!("dtln_vad_{}.json", id));
+        let wav = dir.join(format!("dtln_vad_{}.wav", id));
+        std::fs::write(&input, include_bytes!("../clips/airconditioning.wav")).unwrap();
+        let mut samples = vec![];
+        let info = read_wav_to_pcm32(input.to_str().unwrap(), &mut samples)?;
+
+        let speech = denoise_wav_file_with_vad(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            json.to_str().unwrap(),
+        )?;
+        assert_eq!(speech.len(), info.sample_count.div_ceil(DTLN_BLOCK_SHIFT));
+        assert!(speech.iter().all(|value| (0.0..=1.0).contains(value)));
+        let text = std::fs::read_to_string(&json).unwrap();
+        assert!(text.starts_with("{\"block_rate\":125,"));
+        assert_eq!(text.matches(',').count(), speech.len());
+
+        let again = denoise_wav_file_with_vad(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            wav.to_str().unwrap(),
+        )?;
+        assert_eq!(again, speech);
+        let (header, data) = wav::read(&mut File::open(&wav).unwrap()).unwrap();
+        assert_eq!(header.sampling_rate, 125);
+        assert_eq!(
+            data.try_into_thirty_two_float().unwrap().len(),
+            speech.len()
+        );
+
+        for path in [input, output, json, wav] {
+            std::fs::remove_file(path).unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_denoise_wav_file_trim() -> std::result::Result<(), DtlnError> {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let input = dir.join(format!("dtln_trim_in_{}.wav", id));
+        let output = dir.join(format!("dtln_trim_out_{}.wav", id));
+        // Speech followed by a second of digital silence.
+        let mut samples = generate_test_signal(
+            TestSignal::Babble { seed: 2 },
+            Duration::from_secs(1),
+            16000,
+        );
+        samples.resize(32000, 0.0);
+        write_pcm32_to_wav(samples.clone(), input.to_str().unwrap(), 16000).unwrap();
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+        let report = denoise_wav_file(input, output, None)?;
+        assert_eq!(report.input_samples, samples.len());
+        assert_eq!(report.output_samples, samples.len());
+        assert!(!report.resampled);
+        let trimmed = denoise_wav_file(input, output, Some(TailTrim::default()))?.output_samples;
+        assert!(trimmed < 24000, "{}", trimmed);
+        let mut written = vec![];
+        assert_eq!(
+            read_wav_to_pcm32(output, &mut written)?.sample_count,
+            trimmed
+        );
+
+        for path in [input, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_processing_report() -> std::result::Result<(), DtlnError> {
+        let input = include_bytes!("../clips/airconditioning.wav");
+        let mut samples = vec![];
+        let info = read_wav_bytes_to_pcm32(input, &mut samples)?;
+
+        let (output, report) = denoise_wav_bytes_with_report(input)?;
+        assert_eq!(report.input_samples, info.sample_count);
+        assert_eq!(report.output_samples, info.sample_count);
+        assert!(!report.resampled);
+        assert!(report.noise_reduction_db > 0.0, "{:?}", report);
+        assert!(report.output_rms < report.input_rms);
+        assert!(report.input_peak > 0.0 && report.input_peak < 1.0);
+        assert_eq!(report.input_clipped, 0);
+        assert_eq!(output, denoise_wav_bytes(input)?);
+
+        // The file-level function reports the same pass.
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let input_path = dir.join(format!("dtln_report_in_{}.wav", id));
+        let output_path = dir.join(format!("dtln_report_out_{}.wav", id));
+        std::fs::write(&input_path, input).unwrap();
+        let (input_path, output_path) =
+            (input_path.to_str().unwrap(), output_path.to_str().unwrap());
+        assert_eq!(denoise_wav_file(input_path, output_path, None)?, report);
+        for path in [input_path, output_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wav_markers_round_trip() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("dtln_markers_in_{}.wav", std::process::id()));
+        let output_path = dir.join(format!("dtln_markers_out_{}.wav", std::process::id()));
+        let input_name = input_path.to_str().unwrap();
+        let output_name = output_path.to_str().unwrap();
+
+        // One cue point at sample 1000 and one sampler loop over 2000..3000.
+        let mut cue = u32_bytes(&[1, 7, 1000]);
+        cue.extend_from_slice(b"data");
+        cue.extend(u32_bytes(&[0, 0, 1000]));
+        let smpl = u32_bytes(&[0, 0, 62500, 60, 0, 0, 0, 1, 0, 7, 0, 2000, 3000, 0, 0]);
+        let markers = WavMarkers {
+            cue: Some(cue),
+            smpl: Some(smpl),
+        };
+
+        // 16 bit mono input, as the reader expects.
+        let header = Header::new(wav::WAV_FORMAT_PCM, 1, 16000, 16);
+        let mut bytes = Cursor::new(vec![]);
+        wav::write(header, &wav::BitDepth::Sixteen(vec![0; 4000]), &mut bytes)?;
+        let mut bytes = bytes.into_inner();
+        append_wav_markers(&mut bytes, &markers);
+        File::create(&input_path)?.write_all(&bytes)?;
+
+        let mut samples = vec![];
+        read_wav_to_pcm32(input_name, &mut samples)?;
+        let mut read_markers = read_wav_markers(input_name)?;
+        assert_eq!(read_markers, markers);
+
+        // Simulate trimming 100 samples of latency from the start.
+        read_markers.offset_positions(-100);
+        write_pcm32_to_wav_with_markers(
+            samples[100..].to_vec(),
+            output_name,
+            16000,
+            &read_markers,
+        )?;
+
+        let written = read_wav_markers(output_name)?;
+        let cue = written.cue.unwrap();
+        assert_eq!(LittleEndian::read_u32(&cue[8..]), 900);
+        assert_eq!(LittleEndian::read_u32(&cue[24..]), 900);
+        let smpl = written.smpl.unwrap();
+        assert_eq!(LittleEndian::read_u32(&smpl[44..]), 1900);
+        assert_eq!(LittleEndian::read_u32(&smpl[48..]), 2900);
+        // Everything else is passed through unchanged.
+        assert_eq!(smpl[..44], markers.smpl.as_ref().unwrap()[..44]);
+
+        // The audio itself is still readable.
+        let (_, data) = wav::read(&mut File::open(&output_path)?)?;
+        assert_eq!(data.try_into_thirty_two_float().unwrap().len(), 3900);
+
+        std::fs::remove_file(input_path)?;
+        std::fs::remove_file(output_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_wav_writer() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let samples = (0..10_000)
+            .map(|n| (n as f32 * 0.01).sin() * 0.5)
+            .collect::<Vec<f32>>();
+
+        for bit_depth in [16, 24, 32] {
+            let path = dir.join(format!(
+                "dtln_writer_{}_{}.wav",
+                bit_depth,
+                std::process::id()
+            ));
+            let mut writer = WavWriter::new(path.to_str().unwrap(), 16000, bit_depth)?;
+            for chunk in samples.chunks(37) {
+                writer.write(chunk)?;
+            }
+            assert_eq!(writer.len(), samples.len());
+            writer.finalize()?;
+
+            let bytes = std::fs::read(&path)?;
+            let data_len = samples.len() * bit_depth as usize / 8;
+            let header_len = if bit_depth == 32 {
+                WAV_FLOAT_HEADER_SIZE
+            } else {
+                WAV_HEADER_SIZE
+            };
+            assert_eq!(bytes.len(), header_len + data_len);
+            assert_eq!(
+                LittleEndian::read_u32(&bytes[4..8]) as usize,
+                bytes.len() - 8
+            );
+            assert_eq!(
+                LittleEndian::read_u32(&bytes[header_len - 4..header_len]) as usize,
+                data_len
+            );
+
+            let (header, data) = wav::read(&mut Cursor::new(bytes))?;
+            assert_eq!(header.sampling_rate, 16000);
+            assert_eq!(header.bits_per_sample, bit_depth);
+            if bit_depth == 32 {
+                assert_eq!(data.try_into_thirty_two_float().unwrap(), samples);
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        // Dropping without finalize still leaves a valid file.
+        let path = dir.join(format!("dtln_writer_drop_{}.wav", std::process::id()));
+        {
+            let mut writer = WavWriter::new(path.to_str().unwrap(), 16000, 16)?;
+            writer.write(&samples[..101])?;
+        }
+        let bytes = std::fs::read(&path)?;
+        assert_eq!(LittleEndian::read_u32(&bytes[40..44]), 202);
+        assert_eq!(
+            wav::read(&mut Cursor::new(bytes))?
+                .1
+                .try_into_sixteen()
+                .unwrap()
+                .len(),
+            101
+        );
+        std::fs::remove_file(path)?;
+
+        assert!(WavWriter::new(dir.join("dtln_writer_8.wav").to_str().unwrap(), 16000, 8).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_supported_formats() -> anyhow::Result<()> {
+        use crate::dtln_processor::{supported_sample_rates, DtlnResamplingProcessor};
+
+        let rates = supported_sample_rates();
+        assert!(rates.contains(&DTLN_SAMPLE_RATE));
+        assert!(rates.windows(2).all(|pair| pair[0] < pair[1]));
+        for rate in rates {
+            assert!(DtlnResamplingProcessor::new(*rate).is_ok(), "{} Hz", rate);
+        }
+
+        // Every listed format reads back, the bundled clips are 16 bit.
+        let formats = supported_wav_formats();
+        let mut samples = vec![];
+        let info =
+            read_wav_bytes_to_pcm32(include_bytes!("../clips/airconditioning.wav"), &mut samples)?;
+        assert_eq!(info.bits_per_sample, SampleFormat::I16.bits_per_sample());
+        assert!(formats.contains(&SampleFormat::I16));
+
+        let dir = std::env::temp_dir();
+        for format in [SampleFormat::I16, SampleFormat::I24, SampleFormat::F32] {
+            let path = dir.join(format!(
+                "dtln_format_{}_{}.wav",
+                format.name(),
+                std::process::id()
+            ));
+            let mut writer =
+                WavWriter::new(path.to_str().unwrap(), 16000, format.bits_per_sample())?;
+            writer.write(&samples[..1000])?;
+            writer.finalize()?;
+            let read = read_wav_to_pcm32(path.to_str().unwrap(), &mut vec![]);
+            std::fs::remove_file(&path)?;
+            assert_eq!(read.is_ok(), formats.contains(&format), "{:?}", format);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_wav_round_trip() -> anyhow::Result<()> {
+        let mut samples = (0..5000)
+            .map(|_| (rand::random::<f32>() - 0.5) * 4.0)
+            .collect::<Vec<f32>>();
+        samples.extend([0.0, -0.0, 1e-40, f32::MIN_POSITIVE, f32::MAX, -1.0, 1.0]);
+        let path = std::env::temp_dir().join(format!("dtln_float_{}.wav", std::process::id()));
+        let name = path.to_str().unwrap();
+        write_pcm32_to_wav(samples.clone(), name, 16000)?;
+
+        // WAVE_FORMAT_IEEE_FLOAT with the extended fmt chunk, and a fact chunk with the
+        // sample count.
+        let bytes = std::fs::read(&path)?;
+        assert_eq!(LittleEndian::read_u32(&bytes[16..20]), 18);
+        assert_eq!(LittleEndian::read_u16(&bytes[20..22]), 3);
+        assert_eq!(LittleEndian::read_u16(&bytes[34..36]), 32);
+        assert_eq!(LittleEndian::read_u16(&bytes[36..38]), 0);
+        assert_eq!(&bytes[38..42], b"fact");
+        assert_eq!(
+            LittleEndian::read_u32(&bytes[46..50]) as usize,
+            samples.len()
+        );
+        assert_eq!(bytes, write_pcm32_to_wav_bytes(samples.clone(), 16000)?);
+
+        // Both readers recover every sample bit for bit, unclamped.
+        let bits = |samples: &[f32]| samples.iter().map(|x| x.to_bits()).collect::<Vec<u32>>();
+        let mut read = vec![];
+        let info = read_wav_to_pcm32(name, &mut read)?;
+        assert_eq!(info.bits_per_sample, 32);
+        assert_eq!(info.sample_count, samples.len());
+        assert_eq!(bits(&read), bits(&samples));
+        let (header, data) = wav::read(&mut Cursor::new(bytes))?;
+        assert_eq!(header.audio_format, wav::WAV_FORMAT_IEEE_FLOAT);
+        assert_eq!(
+            bits(&data.try_into_thirty_two_float().unwrap()),
+            bits(&samples)
+        );
+
+        // Other layouts are still rejected.
+        let header = Header::new(wav::WAV_FORMAT_IEEE_FLOAT, 2, 16000, 32);
+        let mut stereo = Cursor::new(vec![]);
+        wav::write(
+            header,
+            &wav::BitDepth::ThirtyTwoFloat(vec![0.0; 64]),
+            &mut stereo,
+        )?;
+        assert!(matches!(
+            read_wav_bytes_to_pcm32(&stereo.into_inner(), &mut read),
+            Err(DtlnError::UnsupportedFormat(_))
+        ));
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_block_global() -> anyhow::Result<()> {
+        let id = dtln_create_global();
+        let block_shift = dtln_block_shift_global(id).unwrap();
+        assert_eq!(block_shift, DTLN_BLOCK_SHIFT);
+
+        let input = (0..block_shift * 8)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+        let mut expected = vec![0.0; input.len()];
+        dtln_create().unwrap().denoise(&input, &mut expected);
+
+        let mut output = vec![0.0; block_shift];
+        for (block, expected) in input.chunks(block_shift).zip(expected.chunks(block_shift)) {
+            dtln_process_block_global(id, block, &mut output)?;
+            assert_eq!(output, expected);
+        }
+
+        // Unaligned blocks are rejected without touching the output.
+        output.fill(1.0);
+        for len in [0, 1, block_shift - 1, block_shift + 1, block_shift * 2] {
+            let block = vec![0.0; len];
+            assert!(dtln_process_block_global(id, &block, &mut output).is_err());
+        }
+        assert!(output.iter().all(|sample| *sample == 1.0));
+        assert!(dtln_process_block_global(id, &input[..block_shift], &mut [0.0; 1]).is_err());
+
+        dtln_destroy_global(id);
+        assert!(dtln_block_shift_global(id).is_none());
+        assert!(dtln_process_block_global(id, &input[..block_shift], &mut output).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_denoise_wav_bytes() -> anyhow::Result<()> {
+        for sample_rate in [16000, 48000] {
+            let input = (0..sample_rate as i32)
+                .map(|n| ((n * 7919) % 20000 - 10000) as i16)
+                .collect::<Vec<i16>>();
+            let header = Header::new(wav::WAV_FORMAT_PCM, 1, sample_rate, 16);
+            let mut bytes = Cursor::new(vec![]);
+            wav::write(header, &wav::BitDepth::Sixteen(input.clone()), &mut bytes)?;
+
+            let output = denoise_wav_bytes(&bytes.into_inner())?;
+            let (header, data) = wav::read(&mut Cursor::new(output))?;
+            assert_eq!(header.sampling_rate, sample_rate);
+            assert_eq!(header.channel_count, 1);
+            let data = data.try_into_thirty_two_float().unwrap();
+            assert!(data.len().abs_diff(input.len()) <= 3);
+            assert!(data.iter().all(|sample| sample.is_finite()));
+        }
+
+        assert!(matches!(
+            denoise_wav_bytes(b"not a wav file"),
+            Err(DtlnError::NotWav(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_wav_errors() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let mut samples = vec![];
+
+        let missing = dir.join("dtln_does_not_exist.wav");
+        assert!(matches!(
+            read_wav_to_pcm32(missing.to_str().unwrap(), &mut samples),
+            Err(DtlnError::NotFound(_))
+        ));
+        assert!(matches!(
+            validate_wav_path(missing.to_str().unwrap(), true),
+            Err(DtlnError::NotFound(_))
+        ));
+        // Other failures keep their reason: a directory opens but can't be read.
+        assert!(matches!(
+            read_wav_to_pcm32(dir.to_str().unwrap(), &mut samples),
+            Err(DtlnError::Unreadable(_, std::io::ErrorKind::IsADirectory))
+        ));
+        assert!(matches!(
+            validate_wav_path("clip.mp3", false),
+            Err(DtlnError::NotWav(_))
+        ));
+
+        let not_wav = dir.join(format!("dtln_not_wav_{}.wav", std::process::id()));
+        File::create(&not_wav)?.write_all(b"this is plain text, not audio")?;
+        assert!(matches!(
+            read_wav_to_pcm32(not_wav.to_str().unwrap(), &mut samples),
+            Err(DtlnError::NotWav(_))
+        ));
+
+        // A valid file with the end of its data chunk cut off.
+        let header = Header::new(wav::WAV_FORMAT_PCM, 1, 16000, 16);
+        let mut bytes = Cursor::new(vec![]);
+        wav::write(header, &wav::BitDepth::Sixteen(vec![0; 4000]), &mut bytes)?;
+        let bytes = bytes.into_inner();
+        let truncated = dir.join(format!("dtln_truncated_{}.wav", std::process::id()));
+        File::create(&truncated)?.write_all(&bytes[..bytes.len() / 2])?;
+        assert!(matches!(
+            read_wav_to_pcm32(truncated.to_str().unwrap(), &mut samples),
+            Err(DtlnError::Truncated(_))
+        ));
+
+        std::fs::remove_file(not_wav)?;
+        std::fs::remove_file(truncated)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_interleave() -> Result<()> {
+        assert_eq!(interleave(&[1, 2, 3, 4, 5, 6], 2)?, [1, 4, 2, 5, 3, 6]);
+        assert_eq!(deinterleave(&[1, 4, 2, 5, 3, 6], 2)?, [1, 2, 3, 4, 5, 6]);
+
+        for channels in [1, 2, 6] {
+            let floats = (0..channels * 500)
+                .map(|_| rand::random::<f32>())
+                .collect::<Vec<f32>>();
+            assert_eq!(
+                interleave(&deinterleave(&floats, channels)?, channels)?,
+                floats
+            );
+            assert_eq!(
+                deinterleave(&interleave(&floats, channels)?, channels)?,
+                floats
+            );
+
+            let ints = (0..channels * 500)
+                .map(|_| rand::random::<i16>())
+                .collect::<Vec<i16>>();
+            assert_eq!(interleave(&deinterleave(&ints, channels)?, channels)?, ints);
+            assert_eq!(deinterleave(&interleave(&ints, channels)?, channels)?, ints);
+        }
+
+        assert!(interleave(&[0.0f32; 7], 2).is_err());
+        assert!(deinterleave(&[0i16; 7], 6).is_err());
+        assert!(deinterleave(&[0i16; 6], 0).is_err());
+        Ok(())
     }
 }
@@ -1,14 +1,44 @@
 // Primary export functions for the NEON module.
-use dtln_processor::DtlnDeferredProcessor;
+use constants::DTLN_BLOCK_SHIFT;
+use dtln_error::DtlnError;
+use dtln_processor::{DtlnDeferredProcessor, DtlnOptions};
 use dtln_processor::{DtlnImmediateProcessor, DtlnProcessEngine};
+use dtln_stats::DtlnStats;
 
-use std::io::Result;
+pub use dtln_conformance::{run_conformance, ConformanceCheck, ConformanceResult};
+
+use std::ffi::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::ptr;
 use std::slice;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+#[macro_use]
+mod dtln_log;
 pub mod constants;
+pub mod dtln_conformance;
+#[cfg(feature = "dasp")]
+pub mod dtln_dasp;
+#[cfg(feature = "debug-export")]
+pub mod dtln_debug_export;
+#[cfg(any(feature = "flac", feature = "ogg"))]
+pub mod dtln_decode;
+pub mod dtln_dsp;
 pub mod dtln_engine;
+pub mod dtln_error;
+pub mod dtln_fft;
+pub mod dtln_model_info;
+pub mod dtln_model_switch;
+pub mod dtln_multichannel;
+#[cfg(feature = "ndarray")]
+pub mod dtln_ndarray;
+#[cfg(feature = "serde")]
+pub mod dtln_preset;
 pub mod dtln_processor;
+pub mod dtln_sample;
+pub mod dtln_scratch;
+pub mod dtln_simd;
+pub mod dtln_stats;
+pub mod dtln_tap;
 pub mod dtln_utilities;
 pub mod tflite;
 
@@ -16,21 +46,159 @@ use neon::prelude::*;
 
 use neon::types::buffer::TypedArray;
 
+/**
+* Create a denoiser.
+*
+* @param {object} [options] - { sampleRate, threads, mix, suppressionStrength, queueCapacity },
+* every field optional. See DtlnOptions for their meaning and defaults.
+
+* @returns {JsBox} - The engine to pass to the other functions.
+*/
 fn dtln_create_napi(mut cx: FunctionContext) -> JsResult<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>> {
-    let dtln_processor = DtlnDeferredProcessor::new();
-    let Ok(dtln_processor) = dtln_processor else {
-        return cx.throw_error("Failed to create DtlnDeferredProcessor");
-    };
+    let mut options = DtlnOptions::default();
+    if let Some(value) = cx.argument_opt(0) {
+        if !value.is_a::<JsUndefined, _>(&mut cx) {
+            let object = value.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            let number = |cx: &mut FunctionContext, key: &str| -> NeonResult<Option<f64>> {
+                let value = object.get_opt::<JsNumber, _, _>(cx, key)?;
+                let value = value.map(|value| value.value(cx));
+                match value {
+                    Some(value) if !value.is_finite() || value < 0.0 => {
+                        cx.throw_error(format!("Invalid {} {}", key, value))
+                    }
+                    _ => Ok(value),
+                }
+            };
+            // Counts, which would otherwise be truncated or saturated silently.
+            let integer = |cx: &mut FunctionContext, key: &str, max: f64| {
+                let value = number(cx, key)?;
+                match value {
+                    Some(value) if value.fract() != 0.0 || value > max => cx.throw_error(format!(
+                        "Invalid {} {}, expected an integer up to {}",
+                        key, value, max
+                    )),
+                    _ => Ok(value),
+                }
+            };
+
+            if let Some(sample_rate) = integer(&mut cx, "sampleRate", u32::MAX as f64)? {
+                options.sample_rate = sample_rate as u32;
+            }
+            if let Some(threads) = integer(&mut cx, "threads", u32::MAX as f64)? {
+                options.threads = threads as usize;
+            }
+            if let Some(mix) = number(&mut cx, "mix")? {
+                options.mix = mix as f32;
+            }
+            if let Some(strength) = number(&mut cx, "suppressionStrength")? {
+                options.suppression_strength = strength as f32;
+            }
+            if let Some(capacity) = integer(&mut cx, "queueCapacity", u32::MAX as f64)? {
+                options.queue_capacity = Some(capacity as usize);
+            }
+        }
+    }
+
+    match DtlnDeferredProcessor::with_options(&options) {
+        Ok(dtln_processor) => Ok(cx.boxed(Arc::new(Mutex::new(dtln_processor)))),
+        Err(e) => cx.throw_error(format!("Failed to create DtlnDeferredProcessor: {}", e)),
+    }
+}
+
+// Version of this crate, from Cargo.toml, e.g. "0.1.0".
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/**
+ * Identifies the bundled models: "dtln-quant-" followed by 16 hex digits of an FNV-1a
+ * hash of both model files, so it changes whenever their weights do. Models loaded with
+ * DtlnEngine::with_models aren't covered, see EngineInfo::model_description for those.
+ */
+pub fn model_version() -> &'static str {
+    static MODEL_VERSION: OnceLock<String> = OnceLock::new();
+    MODEL_VERSION.get_or_init(|| {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for byte in dtln_engine::MODEL_1.iter().chain(dtln_engine::MODEL_2) {
+            hash = (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+        format!("dtln-quant-{:016x}", hash)
+    })
+}
 
-    Ok(cx.boxed(Arc::new(Mutex::new(dtln_processor))))
+// NUL terminated version(), valid for the lifetime of the program.
+#[no_mangle]
+pub extern "C" fn dtln_rs_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+// NUL terminated model_version(), valid for the lifetime of the program.
+#[no_mangle]
+pub extern "C" fn dtln_rs_model_version() -> *const c_char {
+    static MODEL_VERSION: OnceLock<std::ffi::CString> = OnceLock::new();
+    MODEL_VERSION
+        .get_or_init(|| std::ffi::CString::new(model_version()).unwrap_or_default())
+        .as_ptr()
+}
+
+// Copies `list` to `buffer` if `capacity` holds all of it, returning its length.
+unsafe fn write_list<T: Copy>(list: &[T], buffer: *mut T, capacity: usize) -> usize {
+    if !buffer.is_null() && capacity >= list.len() {
+        let output = unsafe { slice::from_raw_parts_mut(buffer, capacity) };
+        output[..list.len()].copy_from_slice(list);
+    }
+    list.len()
+}
+
+/**
+ * Writes dtln_processor::supported_sample_rates to `buffer`, returning their number.
+ * Nothing is written if `capacity` can't hold them all, so callers can pass a null
+ * buffer to query the count.
+ *
+ * # Safety
+ *
+ * `buffer` must be null or valid for writes of `capacity` values.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_supported_sample_rates(
+    buffer: *mut u32,
+    capacity: usize,
+) -> usize {
+    unsafe { write_list(dtln_processor::supported_sample_rates(), buffer, capacity) }
+}
+
+/**
+ * Writes dtln_utilities::supported_wav_formats to `buffer` as SampleFormat values
+ * (0 for 16 bit, 1 for 24 bit, 2 for float), returning their number. Sized like
+ * dtln_rs_supported_sample_rates.
+ *
+ * # Safety
+ *
+ * `buffer` must be null or valid for writes of `capacity` values.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_supported_wav_formats(buffer: *mut u32, capacity: usize) -> usize {
+    let formats = dtln_utilities::supported_wav_formats()
+        .iter()
+        .map(|format| *format as u32)
+        .collect::<Vec<u32>>();
+    unsafe { write_list(&formats, buffer, capacity) }
 }
 
 fn dtln_stop_napi(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
-    dtln_processor.lock().unwrap().stop();
+    let Ok(mut dtln_processor) = dtln_processor.lock() else {
+        return cx.throw_error(DtlnError::Poisoned.to_string());
+    };
+    dtln_processor.stop();
     Ok(cx.undefined())
 }
 
+/**
+ * Creates a mono processor, or returns null if it can't be set up. The handle is live
+ * until passed to dtln_rs_processor_destroy, and must not be used from two threads at
+ * once.
+ */
 #[no_mangle]
 pub extern "C" fn dtln_rs_processor_create() -> *mut DtlnImmediateProcessor {
     match DtlnImmediateProcessor::new() {
@@ -39,8 +207,29 @@ pub extern "C" fn dtln_rs_processor_create() -> *mut DtlnImmediateProcessor {
     }
 }
 
+/**
+ * Creates a processor for interleaved audio with `channels` channels, to be used with
+ * dtln_rs_denoise_interleaved. Each channel keeps its own recurrent state.
+ */
 #[no_mangle]
-pub extern "C" fn dtln_rs_processor_destroy(handle: *mut DtlnImmediateProcessor) {
+pub extern "C" fn dtln_rs_processor_create_multichannel(
+    channels: usize,
+) -> *mut DtlnImmediateProcessor {
+    match DtlnImmediateProcessor::with_channels(channels) {
+        Ok(processor) => Box::into_raw(Box::new(processor)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/**
+ * Frees a processor handle. A null handle is ignored.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle, which is invalid afterwards.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_processor_destroy(handle: *mut DtlnImmediateProcessor) {
     if handle.is_null() {
         return;
     }
@@ -50,31 +239,563 @@ pub extern "C" fn dtln_rs_processor_destroy(handle: *mut DtlnImmediateProcessor)
     }
 }
 
+/**
+ * Denoises `len` samples into `output_ptr`, which holds `len` samples. Writes the
+ * samples the call completes, dtln_rs_output_size_for(len), at the start of the output
+ * and leaves the rest untouched: with calls of whole DTLN_BLOCK_SHIFT multiples that is
+ * all `len`, lagging the input by the processor latency. If a partial block held back
+ * from earlier calls would complete more than `len` samples, fails without consuming
+ * the input; use dtln_rs_denoise_sized or dtln_rs_denoise_fixed for such call sizes. A
+ * zero length call succeeds without touching the processor, and the buffers may then
+ * be null.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle. Unless `len` is 0, `input_ptr` and
+ * `output_ptr` must be null or valid for `len` samples, and must not overlap.
+ */
 #[no_mangle]
-pub extern "C" fn dtln_rs_denoise(
+pub unsafe extern "C" fn dtln_rs_denoise(
     handle: *mut DtlnImmediateProcessor,
     input_ptr: *const f32,
     len: usize,
     output_ptr: *mut f32,
 ) -> bool {
-    if handle.is_null() || input_ptr.is_null() || output_ptr.is_null() || len == 0 {
+    if handle.is_null() {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+    if input_ptr.is_null() || output_ptr.is_null() {
         return false;
     }
 
     let processor = unsafe { &mut *handle };
     let input = unsafe { slice::from_raw_parts(input_ptr, len) };
     let output = unsafe { slice::from_raw_parts_mut(output_ptr, len) };
+    if processor.output_len_for(len) > len {
+        return false;
+    }
 
-    match processor.denoise(input) {
+    // Never let a panic unwind into the C caller.
+    catch_unwind(AssertUnwindSafe(|| match processor.denoise(input) {
         Ok(result) => {
-            if result.samples.len() > output.len() {
-                return false;
-            }
             output[..result.samples.len()].copy_from_slice(&result.samples);
             true
         }
         Err(_) => false,
+    }))
+    .unwrap_or(false)
+}
+
+/**
+ * Denoises `len` samples into `output_ptr`, which holds `len` samples, always writing
+ * all `len` whatever the call size; see DtlnImmediateProcessor::denoise_fixed. The
+ * output lags the input by the processor latency plus DTLN_BLOCK_SHIFT - 1 samples,
+ * and output completed beyond `len` is carried to the next call. Don't mix it with
+ * dtln_rs_denoise or dtln_rs_denoise_sized on a handle within a stream. A zero length
+ * call succeeds without touching the processor, and the buffers may then be null.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle. Unless `len` is 0, `input_ptr` and
+ * `output_ptr` must be null or valid for `len` samples, and must not overlap.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_denoise_fixed(
+    handle: *mut DtlnImmediateProcessor,
+    input_ptr: *const f32,
+    len: usize,
+    output_ptr: *mut f32,
+) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+    if input_ptr.is_null() || output_ptr.is_null() {
+        return false;
+    }
+
+    let processor = unsafe { &mut *handle };
+    let input = unsafe { slice::from_raw_parts(input_ptr, len) };
+    let output = unsafe { slice::from_raw_parts_mut(output_ptr, len) };
+
+    // Never let a panic unwind into the C caller.
+    catch_unwind(AssertUnwindSafe(|| {
+        processor.denoise_fixed(input, output).is_ok()
+    }))
+    .unwrap_or(false)
+}
+
+/**
+ * Number of samples the next dtln_rs_denoise_sized call with `input_len` samples will
+ * write. This depends on the samples the processor currently holds back, so it must be
+ * queried before every call rather than cached. Returns 0 for a null handle.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_output_size_for(
+    handle: *mut DtlnImmediateProcessor,
+    input_len: usize,
+) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+    unsafe { &*handle }.output_len_for(input_len)
+}
+
+/**
+ * Denoises `len` samples into `output_ptr`, which holds `capacity` samples, and stores
+ * the number of samples written in `written_out`. If `capacity` is smaller than
+ * dtln_rs_output_size_for reports, fails without consuming the input so the call can be
+ * retried with a larger buffer. A zero length call succeeds without touching the
+ * processor, and the buffers may then be null. The output past the written samples is
+ * left untouched, unless dtln_rs_set_zero_fill is on.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle, and `written_out` null or writable.
+ * Unless `len` is 0, `input_ptr` must be null or valid for `len` samples and `output_ptr`
+ * null or valid for `capacity` samples, not overlapping the input.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_denoise_sized(
+    handle: *mut DtlnImmediateProcessor,
+    input_ptr: *const f32,
+    len: usize,
+    output_ptr: *mut f32,
+    capacity: usize,
+    written_out: *mut usize,
+) -> bool {
+    if handle.is_null() || written_out.is_null() {
+        return false;
+    }
+    unsafe { *written_out = 0 };
+    if len == 0 {
+        return true;
+    }
+    if input_ptr.is_null() || output_ptr.is_null() {
+        return false;
+    }
+
+    let processor = unsafe { &mut *handle };
+    if processor.output_len_for(len) > capacity {
+        return false;
+    }
+    let input = unsafe { slice::from_raw_parts(input_ptr, len) };
+    let output = unsafe { slice::from_raw_parts_mut(output_ptr, capacity) };
+
+    // Never let a panic unwind into the C caller.
+    catch_unwind(AssertUnwindSafe(|| {
+        match processor.denoise_into(input, output) {
+            Ok(written) => {
+                unsafe { *written_out = written };
+                true
+            }
+            Err(_) => false,
+        }
+    }))
+    .unwrap_or(false)
+}
+
+/**
+ * Sets whether the denoise calls zero the end of the output buffer past the samples
+ * they write, see DtlnImmediateProcessor::set_zero_fill. Off by default, for hosts that
+ * reuse buffers and don't track the written count.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_set_zero_fill(
+    handle: *mut DtlnImmediateProcessor,
+    enabled: bool,
+) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    unsafe { &mut *handle }.set_zero_fill(enabled);
+    true
+}
+
+// Result of dtln_rs_denoise_pts.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DtlnDenoiseResult {
+    // Samples written to the output buffer.
+    pub written: usize,
+    // Presentation timestamp of the first output sample, in samples at 16 kHz.
+    pub output_pts: i64,
+}
+
+/**
+ * dtln_rs_denoise_sized, with `input_pts` the presentation timestamp of the first input
+ * sample, in samples at 16 kHz. `result_out` receives the samples written and the
+ * timestamp of the first of them, which lags the input timestamps by the processor
+ * latency, see DtlnImmediateProcessor::output_pts.
+ *
+ * # Safety
+ *
+ * As for dtln_rs_denoise_sized, with `result_out` null or writable.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_denoise_pts(
+    handle: *mut DtlnImmediateProcessor,
+    input_ptr: *const f32,
+    len: usize,
+    output_ptr: *mut f32,
+    capacity: usize,
+    input_pts: i64,
+    result_out: *mut DtlnDenoiseResult,
+) -> bool {
+    if handle.is_null() || result_out.is_null() {
+        return false;
+    }
+
+    let output_pts = unsafe { &*handle }.output_pts(input_pts);
+    let mut written = 0;
+    let ok = unsafe {
+        dtln_rs_denoise_sized(handle, input_ptr, len, output_ptr, capacity, &mut written)
+    };
+    unsafe {
+        *result_out = DtlnDenoiseResult {
+            written,
+            output_pts,
+        }
+    };
+    ok
+}
+
+/**
+ * Whether a mono handle has returned output past the warm-up, see
+ * DtlnProcessEngine::is_warmed_up. False for a null handle.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_is_warmed_up(handle: *mut DtlnImmediateProcessor) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    unsafe { &*handle }.is_warmed_up()
+}
+
+/**
+ * Output samples still to come before the warm-up ends, 0 for a null handle.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_samples_until_warm(handle: *mut DtlnImmediateProcessor) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+    unsafe { &*handle }.samples_until_warm()
+}
+
+/**
+ * Copies the processor's cumulative counters into `stats_out`. If `reset` is set, the
+ * counters are cleared after reading.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle, and `stats_out` null or writable.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_get_stats(
+    handle: *mut DtlnImmediateProcessor,
+    stats_out: *mut DtlnStats,
+    reset: bool,
+) -> bool {
+    if handle.is_null() || stats_out.is_null() {
+        return false;
+    }
+
+    let processor = unsafe { &*handle };
+    let stats = if reset {
+        processor.take_stats()
+    } else {
+        processor.stats()
+    };
+    unsafe { *stats_out = stats };
+    true
+}
+
+/**
+ * Writes a NUL terminated description of the loaded models (see
+ * EngineInfo::model_description) to `buffer`. Returns the description's length without
+ * the terminator. Nothing is written if `capacity` can't hold the description and its
+ * terminator, so callers can pass a null buffer to query the size.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle, and `buffer` null or valid for writes
+ * of `capacity` bytes.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_model_description(
+    handle: *mut DtlnImmediateProcessor,
+    buffer: *mut c_char,
+    capacity: usize,
+) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+
+    let processor = unsafe { &*handle };
+    let description = processor.engine.info().model_description();
+    if !buffer.is_null() && capacity > description.len() {
+        let output = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, capacity) };
+        output[..description.len()].copy_from_slice(description.as_bytes());
+        output[description.len()] = 0;
+    }
+    description.len()
+}
+
+/**
+ * Ends the stream on a mono handle, writing the remaining samples to `output_ptr`.
+ * Returns the number of samples written, or 0 if `capacity` is too small to hold the
+ * tail, in which case the processor is left untouched. The tail is at most
+ * DTLN_BLOCK_SHIFT - 1 + DTLN_LATENCY samples, plus the crossover latency if the low
+ * band bypass is enabled.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle, and `output_ptr` null or valid for
+ * `capacity` samples.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_flush(
+    handle: *mut DtlnImmediateProcessor,
+    output_ptr: *mut f32,
+    capacity: usize,
+) -> usize {
+    if handle.is_null() || output_ptr.is_null() {
+        return 0;
+    }
+
+    let processor = unsafe { &mut *handle };
+    if capacity < processor.flush_len() {
+        return 0;
+    }
+    let output = unsafe { slice::from_raw_parts_mut(output_ptr, capacity) };
+
+    catch_unwind(AssertUnwindSafe(|| match processor.flush() {
+        Ok(tail) => {
+            output[..tail.len()].copy_from_slice(&tail);
+            tail.len()
+        }
+        Err(_) => 0,
+    }))
+    .unwrap_or(0)
+}
+
+// Samples requested from the read callback per iteration of dtln_rs_run.
+const RUN_BUFFER_SAMPLES: usize = DTLN_BLOCK_SHIFT * 32;
+
+// Fills `buffer` with up to `capacity` samples, returning the count written or 0 at EOF.
+pub type DtlnReadCallback =
+    extern "C" fn(user_data: *mut c_void, buffer: *mut f32, capacity: usize) -> usize;
+
+// Consumes `len` denoised samples. The buffer is only valid for the duration of the call.
+pub type DtlnWriteCallback = extern "C" fn(user_data: *mut c_void, buffer: *const f32, len: usize);
+
+/**
+ * Runs the block loop on a mono handle: pulls input from `read_cb` until it returns 0,
+ * passes the denoised output to `write_cb`, then flushes the tail to `write_cb`, so
+ * the total output matches the total input plus the processor latency. `user_data` is
+ * passed through to both callbacks. The callbacks may return any number of samples,
+ * framing is handled internally.
+ *
+ * Returns false if the arguments are invalid, a callback reported more samples than
+ * requested, or processing failed. Panics are caught and reported as failure, the
+ * callbacks themselves must not unwind.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle. The callbacks get `user_data` as is and
+ * buffers valid for the sample counts they are passed.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_run(
+    handle: *mut DtlnImmediateProcessor,
+    read_cb: Option<DtlnReadCallback>,
+    write_cb: Option<DtlnWriteCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    let (Some(read_cb), Some(write_cb)) = (read_cb, write_cb) else {
+        return false;
+    };
+    if handle.is_null() {
+        return false;
+    }
+
+    let processor = unsafe { &mut *handle };
+    catch_unwind(AssertUnwindSafe(|| {
+        let mut input = vec![0.0; RUN_BUFFER_SAMPLES];
+        loop {
+            let count = read_cb(user_data, input.as_mut_ptr(), input.len());
+            if count == 0 {
+                break;
+            }
+            if count > input.len() {
+                return false;
+            }
+            match processor.try_denoise(&input[..count]) {
+                Ok(result) if !result.samples.is_empty() => {
+                    write_cb(user_data, result.samples.as_ptr(), result.samples.len());
+                }
+                Ok(_) => {}
+                Err(_) => return false,
+            }
+        }
+
+        match processor.flush() {
+            Ok(tail) => {
+                if !tail.is_empty() {
+                    write_cb(user_data, tail.as_ptr(), tail.len());
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }))
+    .unwrap_or(false)
+}
+
+/**
+ * Denoises `frames` frames of interleaved audio. The handle must have been created with
+ * dtln_rs_processor_create_multichannel using the same channel count, input and output
+ * must both hold `frames * channels` samples. Zero frames succeeds as a no-op, like
+ * dtln_rs_denoise.
+ *
+ * `frames` must be a multiple of DTLN_BLOCK_SHIFT (128), so that the output is exactly
+ * as long as the input; other counts return false without consuming the input.
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle. Unless `frames` is 0, `input_ptr` and
+ * `output_ptr` must be null or valid for `frames * channels` samples, not overlapping.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_denoise_interleaved(
+    handle: *mut DtlnImmediateProcessor,
+    input_ptr: *const f32,
+    frames: usize,
+    channels: usize,
+    output_ptr: *mut f32,
+) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    if frames == 0 {
+        return true;
+    }
+    if input_ptr.is_null() || output_ptr.is_null() {
+        return false;
     }
+
+    let processor = unsafe { &mut *handle };
+    if channels != processor.channel_count() || !frames.is_multiple_of(DTLN_BLOCK_SHIFT) {
+        return false;
+    }
+
+    let Some(len) = frames.checked_mul(channels) else {
+        return false;
+    };
+    let input = unsafe { slice::from_raw_parts(input_ptr, len) };
+    let output = unsafe { slice::from_raw_parts_mut(output_ptr, len) };
+
+    catch_unwind(AssertUnwindSafe(|| {
+        match processor.denoise_interleaved(input, channels) {
+            Ok(result) => {
+                output.copy_from_slice(&result);
+                true
+            }
+            Err(_) => false,
+        }
+    }))
+    .unwrap_or(false)
+}
+
+/**
+ * Denoises `channels` planar buffers of `len_per_channel` samples each, for hosts that
+ * keep one buffer per channel. `input_ptrs` and `output_ptrs` point to `channels`
+ * buffer pointers, none of which may be null. The handle must have been created with
+ * dtln_rs_processor_create_multichannel using the same channel count, and the output
+ * matches dtln_rs_denoise_interleaved on the same audio. Like there, `len_per_channel`
+ * must be a multiple of DTLN_BLOCK_SHIFT (128).
+ *
+ * # Safety
+ *
+ * `handle` must be null or a live handle, and `input_ptrs` and `output_ptrs` null
+ * or valid for `channels` pointers. Unless `len_per_channel` is 0, each of those must
+ * be valid for `len_per_channel` samples, and no output may overlap an input.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn dtln_rs_denoise_planar(
+    handle: *mut DtlnImmediateProcessor,
+    channels: usize,
+    len_per_channel: usize,
+    input_ptrs: *const *const f32,
+    output_ptrs: *const *mut f32,
+) -> bool {
+    if handle.is_null() || input_ptrs.is_null() || output_ptrs.is_null() {
+        return false;
+    }
+    let processor = unsafe { &mut *handle };
+    if channels == 0 || channels != processor.channel_count() {
+        return false;
+    }
+    let input_ptrs = unsafe { slice::from_raw_parts(input_ptrs, channels) };
+    let output_ptrs = unsafe { slice::from_raw_parts(output_ptrs, channels) };
+    if input_ptrs.iter().any(|ptr| ptr.is_null()) || output_ptrs.iter().any(|ptr| ptr.is_null()) {
+        return false;
+    }
+    if len_per_channel == 0 {
+        return true;
+    }
+    if !len_per_channel.is_multiple_of(DTLN_BLOCK_SHIFT) {
+        return false;
+    }
+
+    let Some(len) = len_per_channel.checked_mul(channels) else {
+        return false;
+    };
+    let mut interleaved = vec![0.0; len];
+    for (channel, input_ptr) in input_ptrs.iter().enumerate() {
+        let input = unsafe { slice::from_raw_parts(*input_ptr, len_per_channel) };
+        for (frame, sample) in input.iter().enumerate() {
+            interleaved[frame * channels + channel] = *sample;
+        }
+    }
+
+    catch_unwind(AssertUnwindSafe(|| {
+        match processor.denoise_interleaved(&interleaved, channels) {
+            Ok(result) => {
+                for (channel, output_ptr) in output_ptrs.iter().enumerate() {
+                    let output = unsafe { slice::from_raw_parts_mut(*output_ptr, len_per_channel) };
+                    for (frame, sample) in output.iter_mut().enumerate() {
+                        *sample = result[frame * channels + channel];
+                    }
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }))
+    .unwrap_or(false)
 }
 
 /**
@@ -82,6 +803,8 @@ pub extern "C" fn dtln_rs_denoise(
 *
 * @param {Float32Array} samples - The samples to denoise.
 * @param {Float32Array} output - The denoised samples.
+*
+* An empty `samples` array is a no-op, leaving `output` untouched and returning false.
 
 * @returns {boolean} - True if the processing thread is backed up.
 */
@@ -90,39 +813,210 @@ fn dtln_denoise_napi(mut cx: FunctionContext) -> JsResult<JsBoolean> {
         return cx.throw_error("Invalid number of arguments, expected <engine: JsBox, samples: Float32Array, output: Float32Array>");
     }
 
-    let processor_starved;
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
+    let samples = cx.argument::<JsTypedArray<f32>>(1)?;
+    let mut output = cx.argument::<JsTypedArray<f32>>(2)?;
 
-    let result: Result<()> = {
-        let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
+    let result: std::result::Result<bool, String> = {
+        let lock = cx.lock();
+        let borrowed = (samples.try_borrow(&lock), output.try_borrow_mut(&lock));
+        match borrowed {
+            (Ok(samples_slice), Ok(mut output_slice)) => {
+                // RefMut has to be passed up the entire chain, and I'd rather not let
+                // it leak further into the dtln_denoise abstraction. Generically
+                // operating on an &mut [f32] is better, so copying here is our best option.
+                let denoise_result = match dtln_processor.lock() {
+                    Ok(mut processor) => processor
+                        .try_denoise(&samples_slice)
+                        .map_err(|e| e.to_string()),
+                    Err(_) => Err(DtlnError::Poisoned.to_string()),
+                };
 
-        let samples = cx.argument::<JsTypedArray<f32>>(1).unwrap();
-        let mut output = cx.argument::<JsTypedArray<f32>>(2).unwrap();
+                denoise_result.and_then(|denoise_result| {
+                    if denoise_result.samples.len() > output_slice.len() {
+                        return Err("Output buffer too small".to_string());
+                    }
+                    output_slice[..denoise_result.samples.len()]
+                        .copy_from_slice(&denoise_result.samples);
+                    Ok(denoise_result.processor_starved)
+                })
+            }
+            _ => Err("Unable to borrow sample buffers".to_string()),
+        }
+    };
+
+    match result {
+        Ok(processor_starved) => Ok(cx.boolean(processor_starved)),
+        Err(e) => cx.throw_error(format!("Error in dtln_denoise: {}", e)),
+    }
+}
+
+/**
+* End the stream, writing the remaining denoised samples: the result of the last
+* dtln_denoise call, then the engine tail. Size `output` for the last call's input
+* length plus DTLN_LATENCY (384) samples, more if starved calls left results behind.
+* If it's too small this throws before flushing, so the call can be retried with a
+* larger buffer. The processor is then ready for a new stream.
+*
+* @param {JsBox} engine - The engine returned by dtln_create.
+* @param {Float32Array} output - Receives the remaining samples.
+*
+* @returns {number} - The number of samples written.
+*/
+fn dtln_flush_napi(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
+    let mut output = cx.argument::<JsTypedArray<f32>>(1)?;
 
+    let result = {
         let lock = cx.lock();
-        let samples_slice = samples.try_borrow(&lock).unwrap();
-        let mut output_slice = output.try_borrow_mut(&lock).unwrap();
-
-        // RefMut has to be passed up the entire chain, and I'd rather not let
-        // it leak further into the dtln_denoise abstraction. Generically
-        // operating on an &mut [f32] is better, so copying here is our best option.
-        let denoise_result = dtln_processor
-            .lock()
-            .unwrap()
-            .denoise(&samples_slice)
-            .map_err(|e| panic!("Error in dtln_denoise: {}", e))
-            .unwrap();
-
-        processor_starved = denoise_result.processor_starved;
-
-        output_slice[..denoise_result.samples.len()].copy_from_slice(&denoise_result.samples);
-        Ok(())
+        let borrowed = (output.try_borrow_mut(&lock), dtln_processor.lock());
+        match borrowed {
+            (Ok(mut output_slice), Ok(mut processor)) => {
+                // The flush resets the processor, so a tail that doesn't fit would be lost.
+                let needed = processor.flush_len();
+                if needed > output_slice.len() {
+                    Err(format!(
+                        "Output buffer too small, {} samples needed",
+                        needed
+                    ))
+                } else {
+                    processor
+                        .flush()
+                        .map_err(|e| e.to_string())
+                        .and_then(|tail| {
+                            if tail.len() > output_slice.len() {
+                                return Err(format!(
+                                    "Output buffer too small, {} samples needed",
+                                    tail.len()
+                                ));
+                            }
+                            output_slice[..tail.len()].copy_from_slice(&tail);
+                            Ok(tail.len())
+                        })
+                }
+            }
+            (Err(_), _) => Err("Unable to borrow output buffer".to_string()),
+            (_, Err(_)) => Err(DtlnError::Poisoned.to_string()),
+        }
+    };
+
+    match result {
+        Ok(written) => Ok(cx.number(written as f64)),
+        Err(e) => cx.throw_error(format!("Error in dtln_flush: {}", e)),
+    }
+}
+
+/**
+* Get the processor's cumulative counters.
+*
+* @param {JsBox} engine - The engine to query.
+* @param {boolean} [reset] - Clear the counters after reading.
+
+* @returns {object} - { blocksProcessed, starvationEvents, nonFiniteSanitized, samplesProcessed }
+*/
+fn dtln_stats_napi(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
+    let reset = match cx.argument_opt(1) {
+        Some(value) => value
+            .downcast_or_throw::<JsBoolean, _>(&mut cx)?
+            .value(&mut cx),
+        None => false,
     };
 
-    if result.is_ok() {
-        Ok(cx.boolean(processor_starved))
+    let Ok(dtln_processor) = dtln_processor.lock() else {
+        return cx.throw_error(DtlnError::Poisoned.to_string());
+    };
+    let stats = if reset {
+        dtln_processor.take_stats()
     } else {
-        cx.throw_error("Error in dtln_denoise")
+        dtln_processor.stats()
+    };
+    drop(dtln_processor);
+
+    let result = cx.empty_object();
+    for (name, value) in [
+        ("blocksProcessed", stats.blocks_processed),
+        ("starvationEvents", stats.starvation_events),
+        ("nonFiniteSanitized", stats.non_finite_sanitized),
+        ("samplesProcessed", stats.samples_processed),
+        ("workerWakeups", stats.worker_wakeups),
+        ("invokeFailures", stats.invoke_failures),
+        ("droppedBlocks", stats.dropped_blocks),
+        ("droppedInputSamples", stats.dropped_input_samples),
+    ] {
+        let value = cx.number(value as f64);
+        result.set(&mut cx, name, value)?;
+    }
+    Ok(result)
+}
+
+/**
+ * Whether the processor has returned output past the warm-up, e.g. to unmute.
+ *
+ * @param {JsBox} engine - The engine returned by dtln_create.
+ * @returns {boolean}
+ */
+fn dtln_is_warmed_up_napi(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
+    let Ok(dtln_processor) = dtln_processor.lock() else {
+        return cx.throw_error(DtlnError::Poisoned.to_string());
+    };
+    let warmed_up = dtln_processor.is_warmed_up();
+    drop(dtln_processor);
+    Ok(cx.boolean(warmed_up))
+}
+
+/**
+ * Output samples still to come before the warm-up ends, 0 once it has.
+ *
+ * @param {JsBox} engine - The engine returned by dtln_create.
+ * @returns {number}
+ */
+fn dtln_samples_until_warm_napi(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let dtln_processor = cx.argument::<JsBox<Arc<Mutex<DtlnDeferredProcessor>>>>(0)?;
+    let Ok(dtln_processor) = dtln_processor.lock() else {
+        return cx.throw_error(DtlnError::Poisoned.to_string());
+    };
+    let samples = dtln_processor.samples_until_warm();
+    drop(dtln_processor);
+    Ok(cx.number(samples as f64))
+}
+
+/**
+ * The crate and bundled model versions, for logging which build produced the output.
+ *
+ * @returns {object} - { version, modelVersion }, see version() and model_version().
+ */
+fn dtln_version_napi(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let result = cx.empty_object();
+    let version = cx.string(version());
+    result.set(&mut cx, "version", version)?;
+    let model_version = cx.string(model_version());
+    result.set(&mut cx, "modelVersion", model_version)?;
+    Ok(result)
+}
+
+/**
+ * The input formats the crate handles, for building format pickers.
+ *
+ * @returns {object} - { sampleRates, wavFormats }: the supported sample rates in Hz,
+ * ascending, and the WAV sample formats as "i16", "i24" or "f32".
+ */
+fn dtln_supported_formats_napi(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let result = cx.empty_object();
+    let rates = cx.empty_array();
+    for (i, rate) in dtln_processor::supported_sample_rates().iter().enumerate() {
+        let rate = cx.number(*rate as f64);
+        rates.set(&mut cx, i as u32, rate)?;
     }
+    result.set(&mut cx, "sampleRates", rates)?;
+    let formats = cx.empty_array();
+    for (i, format) in dtln_utilities::supported_wav_formats().iter().enumerate() {
+        let format = cx.string(format.name());
+        formats.set(&mut cx, i as u32, format)?;
+    }
+    result.set(&mut cx, "wavFormats", formats)?;
+    Ok(result)
 }
 
 #[neon::main]
@@ -130,6 +1024,460 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("dtln_denoise", dtln_denoise_napi)?;
     cx.export_function("dtln_create", dtln_create_napi)?;
     cx.export_function("dtln_stop", dtln_stop_napi)?;
+    cx.export_function("dtln_flush", dtln_flush_napi)?;
+    cx.export_function("dtln_stats", dtln_stats_napi)?;
+    cx.export_function("dtln_is_warmed_up", dtln_is_warmed_up_napi)?;
+    cx.export_function("dtln_samples_until_warm", dtln_samples_until_warm_napi)?;
+    cx.export_function("dtln_version", dtln_version_napi)?;
+    cx.export_function("dtln_supported_formats", dtln_supported_formats_napi)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denoise_interleaved_ffi() -> anyhow::Result<()> {
+        let frames = 4096;
+        let left = (0..frames)
+            .map(|_| rand::random::<f32>() * 10.0)
+            .collect::<Vec<f32>>();
+        let right = (0..frames)
+            .map(|i| (i as f32 * 0.05).sin())
+            .collect::<Vec<f32>>();
+        let interleaved = left
+            .iter()
+            .zip(right.iter())
+            .flat_map(|(l, r)| [*l, *r])
+            .collect::<Vec<f32>>();
+
+        // Reference: each channel denoised by its own mono processor.
+        let expected_left = DtlnImmediateProcessor::new()?.denoise(&left)?.samples;
+        let expected_right = DtlnImmediateProcessor::new()?.denoise(&right)?.samples;
+
+        let handle = dtln_rs_processor_create_multichannel(2);
+        assert!(!handle.is_null());
+
+        let mut output = vec![0.0; interleaved.len()];
+        // Channel count mismatch is rejected.
+        assert!(!unsafe {
+            dtln_rs_denoise_interleaved(
+                handle,
+                interleaved.as_ptr(),
+                frames / 2,
+                1,
+                output.as_mut_ptr(),
+            )
+        });
+        // As is a frame count that isn't whole blocks, which would return fewer frames.
+        assert!(!unsafe {
+            dtln_rs_denoise_interleaved(
+                handle,
+                interleaved.as_ptr(),
+                frames - 1,
+                2,
+                output.as_mut_ptr(),
+            )
+        });
+        // As is a frame count whose sample count overflows.
+        assert!(!unsafe {
+            dtln_rs_denoise_interleaved(
+                handle,
+                interleaved.as_ptr(),
+                usize::MAX / 2 + 1,
+                2,
+                output.as_mut_ptr(),
+            )
+        });
+        assert!(unsafe {
+            dtln_rs_denoise_interleaved(
+                handle,
+                interleaved.as_ptr(),
+                frames,
+                2,
+                output.as_mut_ptr(),
+            )
+        });
+        unsafe { dtln_rs_processor_destroy(handle) };
+
+        for frame in 0..frames {
+            assert_eq!(output[frame * 2], expected_left[frame]);
+            assert_eq!(output[frame * 2 + 1], expected_right[frame]);
+        }
+        Ok(())
+    }
+
+    struct RunState {
+        input: Vec<f32>,
+        position: usize,
+        output: Vec<f32>,
+    }
+
+    extern "C" fn read_stub(user_data: *mut c_void, buffer: *mut f32, capacity: usize) -> usize {
+        let state = unsafe { &mut *(user_data as *mut RunState) };
+        // Odd sized reads, so the library has to do its own framing.
+        let count = (state.input.len() - state.position).min(capacity).min(1000);
+        let buffer = unsafe { slice::from_raw_parts_mut(buffer, count) };
+        buffer.copy_from_slice(&state.input[state.position..state.position + count]);
+        state.position += count;
+        count
+    }
+
+    extern "C" fn write_stub(user_data: *mut c_void, buffer: *const f32, len: usize) {
+        let state = unsafe { &mut *(user_data as *mut RunState) };
+        state
+            .output
+            .extend_from_slice(unsafe { slice::from_raw_parts(buffer, len) });
+    }
+
+    #[test]
+    fn test_run_ffi() -> anyhow::Result<()> {
+        let input = (0..20_000)
+            .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+            .collect::<Vec<f32>>();
+        let mut expected = DtlnImmediateProcessor::new()?.denoise(&input)?.samples;
+        let mut processor = DtlnImmediateProcessor::new()?;
+        processor.denoise(&input)?;
+        expected.extend(processor.flush()?);
+
+        let mut state = RunState {
+            input: input.clone(),
+            position: 0,
+            output: vec![],
+        };
+        let handle = dtln_rs_processor_create();
+        assert!(unsafe {
+            dtln_rs_run(
+                handle,
+                Some(read_stub),
+                Some(write_stub),
+                &mut state as *mut RunState as *mut c_void,
+            )
+        });
+        assert!(!unsafe { dtln_rs_run(handle, None, Some(write_stub), ptr::null_mut()) });
+        unsafe { dtln_rs_processor_destroy(handle) };
+
+        assert_eq!(state.position, input.len());
+        assert_eq!(state.output, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_description_ffi() {
+        let handle = dtln_rs_processor_create();
+        let len = unsafe { dtln_rs_model_description(handle, ptr::null_mut(), 0) };
+        assert!(len > 0);
+
+        let mut buffer = vec![1 as c_char; len + 1];
+        assert_eq!(
+            unsafe { dtln_rs_model_description(handle, buffer.as_mut_ptr(), buffer.len()) },
+            len
+        );
+        let description = unsafe { std::ffi::CStr::from_ptr(buffer.as_ptr()) };
+        assert_eq!(
+            description.to_str().unwrap(),
+            unsafe { &*handle }.engine.info().model_description()
+        );
+        unsafe { dtln_rs_processor_destroy(handle) };
+    }
+
+    #[test]
+    fn test_version() {
+        let parts = version().split('.').collect::<Vec<&str>>();
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part.parse::<u32>().is_ok()));
+        let ffi = unsafe { std::ffi::CStr::from_ptr(dtln_rs_version()) };
+        assert_eq!(ffi.to_str().unwrap(), version());
+
+        let hash = model_version().strip_prefix("dtln-quant-").unwrap();
+        assert_eq!(hash.len(), 16);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        let ffi = unsafe { std::ffi::CStr::from_ptr(dtln_rs_model_version()) };
+        assert_eq!(ffi.to_str().unwrap(), model_version());
+    }
+
+    #[test]
+    fn test_supported_formats_ffi() {
+        let count = unsafe { dtln_rs_supported_sample_rates(ptr::null_mut(), 0) };
+        let mut rates = vec![0u32; count];
+        assert_eq!(
+            unsafe { dtln_rs_supported_sample_rates(rates.as_mut_ptr(), count) },
+            count
+        );
+        assert_eq!(rates, dtln_processor::supported_sample_rates());
+        assert!(rates.contains(&16000));
+
+        let mut formats = [u32::MAX; 4];
+        let count = unsafe { dtln_rs_supported_wav_formats(formats.as_mut_ptr(), 1) };
+        assert_eq!(formats[0], u32::MAX);
+        assert_eq!(
+            unsafe { dtln_rs_supported_wav_formats(formats.as_mut_ptr(), 4) },
+            count
+        );
+        assert_eq!(formats[..count], [0, 2]);
+    }
+
+    #[test]
+    fn test_empty_input_ffi() {
+        let handle = dtln_rs_processor_create();
+        assert!(!handle.is_null());
+        assert!(unsafe { dtln_rs_denoise(handle, ptr::null(), 0, ptr::null_mut()) });
+        let mut stats = DtlnStats::default();
+        assert!(unsafe { dtln_rs_get_stats(handle, &mut stats, false) });
+        assert_eq!(stats, DtlnStats::default());
+        unsafe { dtln_rs_processor_destroy(handle) };
+
+        let handle = dtln_rs_processor_create_multichannel(2);
+        assert!(unsafe { dtln_rs_denoise_interleaved(handle, ptr::null(), 0, 2, ptr::null_mut()) });
+        unsafe { dtln_rs_processor_destroy(handle) };
+
+        // A null handle still fails.
+        assert!(!unsafe { dtln_rs_denoise(ptr::null_mut(), ptr::null(), 0, ptr::null_mut()) });
+    }
+
+    #[test]
+    fn test_denoise_pts_ffi() {
+        let handle = dtln_rs_processor_create();
+        let latency = unsafe { &*handle }.latency() as i64;
+        let input = vec![0.25; 300];
+        let mut output = vec![0.0; 1024];
+        let mut result = DtlnDenoiseResult::default();
+        let mut emitted = 0;
+        for block in 0..6 {
+            let input_pts = 1000 + block * input.len() as i64;
+            assert!(unsafe {
+                dtln_rs_denoise_pts(
+                    handle,
+                    input.as_ptr(),
+                    input.len(),
+                    output.as_mut_ptr(),
+                    output.len(),
+                    input_pts,
+                    &mut result,
+                )
+            });
+            assert_eq!(result.output_pts, 1000 + emitted - latency);
+            emitted += result.written as i64;
+        }
+        assert!(!unsafe {
+            dtln_rs_denoise_pts(
+                handle,
+                input.as_ptr(),
+                input.len(),
+                output.as_mut_ptr(),
+                output.len(),
+                0,
+                ptr::null_mut(),
+            )
+        });
+        unsafe { dtln_rs_processor_destroy(handle) };
+    }
+
+    #[test]
+    fn test_denoise_planar_ffi() {
+        let len = DTLN_BLOCK_SHIFT * 40;
+        let speech = (0..len)
+            .map(|n| 0.3 * (2.0 * std::f32::consts::PI * 300.0 * n as f32 / 16000.0).sin())
+            .collect::<Vec<f32>>();
+        let noise = (0..len)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+        let mut left = vec![0.0; len];
+        let mut right = vec![0.0; len];
+
+        let handle = dtln_rs_processor_create_multichannel(2);
+        let inputs = [speech.as_ptr(), noise.as_ptr()];
+        let outputs = [left.as_mut_ptr(), right.as_mut_ptr()];
+        assert!(unsafe {
+            dtln_rs_denoise_planar(handle, 2, len, inputs.as_ptr(), outputs.as_ptr())
+        });
+        // Wrong channel counts and null buffers are rejected.
+        assert!(!unsafe {
+            dtln_rs_denoise_planar(handle, 1, len, inputs.as_ptr(), outputs.as_ptr())
+        });
+        let null_outputs = [left.as_mut_ptr(), ptr::null_mut()];
+        assert!(!unsafe {
+            dtln_rs_denoise_planar(handle, 2, len, inputs.as_ptr(), null_outputs.as_ptr())
+        });
+        // As is a length that isn't whole blocks.
+        assert!(!unsafe {
+            dtln_rs_denoise_planar(handle, 2, len - 1, inputs.as_ptr(), outputs.as_ptr())
+        });
+        // As is a length whose interleaved sample count overflows.
+        let huge = usize::MAX / 2 + 1;
+        assert!(!unsafe {
+            dtln_rs_denoise_planar(handle, 2, huge, inputs.as_ptr(), outputs.as_ptr())
+        });
+        unsafe { dtln_rs_processor_destroy(handle) };
+
+        // Each channel is denoised on its own, as a mono processor would.
+        for (input, output) in [(&speech, &left), (&noise, &right)] {
+            let mut processor = DtlnImmediateProcessor::new().unwrap();
+            assert_eq!(&processor.denoise(input).unwrap().samples, output);
+        }
+    }
+
+    #[test]
+    fn test_warm_up_ffi() {
+        let handle = dtln_rs_processor_create();
+        let latency = unsafe { &*handle }.latency();
+        assert_eq!(unsafe { dtln_rs_samples_until_warm(handle) }, latency);
+        let input = vec![0.25; latency + DTLN_BLOCK_SHIFT];
+        let mut output = vec![0.0; input.len()];
+        assert!(!unsafe { dtln_rs_is_warmed_up(handle) });
+        assert!(unsafe {
+            dtln_rs_denoise(handle, input.as_ptr(), input.len(), output.as_mut_ptr())
+        });
+        assert!(unsafe { dtln_rs_is_warmed_up(handle) });
+        assert_eq!(unsafe { dtln_rs_samples_until_warm(handle) }, 0);
+        assert!(!unsafe { dtln_rs_is_warmed_up(ptr::null_mut()) });
+        unsafe { dtln_rs_processor_destroy(handle) };
+    }
+
+    #[test]
+    fn test_zero_fill_ffi() {
+        let handle = dtln_rs_processor_create();
+        assert!(unsafe { dtln_rs_set_zero_fill(handle, true) });
+        let input = vec![0.25; 300];
+        let mut output = vec![0.75; 1024];
+        let mut written = 0;
+        assert!(unsafe {
+            dtln_rs_denoise_sized(
+                handle,
+                input.as_ptr(),
+                input.len(),
+                output.as_mut_ptr(),
+                output.len(),
+                &mut written,
+            )
+        });
+        assert_eq!(written, 256);
+        assert!(output[written..].iter().all(|sample| *sample == 0.0));
+        assert!(!unsafe { dtln_rs_set_zero_fill(ptr::null_mut(), true) });
+        unsafe { dtln_rs_processor_destroy(handle) };
+    }
+
+    #[test]
+    fn test_output_size_ffi() {
+        let handle = dtln_rs_processor_create();
+        assert!(!handle.is_null());
+        let mut emitted = 0;
+        for len in [1, 127, 128, 129, 300, 0, 511, 1024, 77, 5000, 3, 128] {
+            let input = vec![0.25; len];
+            let size = unsafe { dtln_rs_output_size_for(handle, len) };
+            let mut written = usize::MAX;
+            if size > 0 {
+                // One sample short is rejected, and the input is kept for the retry.
+                let mut short = vec![0.0; size - 1];
+                assert!(!unsafe {
+                    dtln_rs_denoise_sized(
+                        handle,
+                        input.as_ptr(),
+                        len,
+                        short.as_mut_ptr(),
+                        short.len(),
+                        &mut written,
+                    )
+                });
+                assert_eq!(unsafe { dtln_rs_output_size_for(handle, len) }, size);
+            }
+            let mut output = vec![0.0; size.max(1)];
+            assert!(unsafe {
+                dtln_rs_denoise_sized(
+                    handle,
+                    input.as_ptr(),
+                    len,
+                    output.as_mut_ptr(),
+                    size,
+                    &mut written,
+                )
+            });
+            assert_eq!(written, size);
+            emitted += written;
+        }
+        let total = [1, 127, 128, 129, 300, 0, 511, 1024, 77, 5000, 3, 128]
+            .iter()
+            .sum::<usize>();
+        assert_eq!(emitted, total / DTLN_BLOCK_SHIFT * DTLN_BLOCK_SHIFT);
+
+        assert_eq!(unsafe { dtln_rs_output_size_for(ptr::null_mut(), 100) }, 0);
+        unsafe { dtln_rs_processor_destroy(handle) };
+    }
+
+    #[test]
+    fn test_denoise_ffi_alignment() -> anyhow::Result<()> {
+        let input = (0..DTLN_BLOCK_SHIFT * 40)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+        let expected = DtlnImmediateProcessor::new()?.denoise(&input)?.samples;
+
+        // Whole block calls come out as long as the input, lagging by the latency only.
+        let handle = dtln_rs_processor_create();
+        let mut output = vec![];
+        for chunk in input.chunks(DTLN_BLOCK_SHIFT * 4) {
+            let mut denoised = vec![f32::NAN; chunk.len()];
+            assert!(unsafe {
+                dtln_rs_denoise(handle, chunk.as_ptr(), chunk.len(), denoised.as_mut_ptr())
+            });
+            output.extend(denoised);
+        }
+        assert_eq!(output, expected);
+
+        // A partial block completes fewer samples than given, the rest is untouched.
+        let mut denoised = vec![f32::NAN; 100];
+        assert!(unsafe { dtln_rs_denoise(handle, input.as_ptr(), 100, denoised.as_mut_ptr()) });
+        assert!(denoised.iter().all(|sample| sample.is_nan()));
+        // Completing more than fits is rejected without consuming the input.
+        let mut denoised = vec![0.0; 50];
+        assert!(!unsafe { dtln_rs_denoise(handle, input.as_ptr(), 50, denoised.as_mut_ptr()) });
+        assert_eq!(
+            unsafe { dtln_rs_output_size_for(handle, 28) },
+            DTLN_BLOCK_SHIFT
+        );
+        unsafe { dtln_rs_processor_destroy(handle) };
+        Ok(())
+    }
+
+    #[test]
+    fn test_denoise_length_preserving_ffi() -> anyhow::Result<()> {
+        let input = (0..DTLN_BLOCK_SHIFT * 40)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect::<Vec<f32>>();
+        let expected = DtlnImmediateProcessor::new()?.denoise(&input)?.samples;
+
+        // Call sizes that leave a partial block pending still fill the whole output.
+        let handle = dtln_rs_processor_create();
+        let mut output = vec![];
+        let mut start = 0;
+        for len in [100, 100, 1, 127, 300, 77, 128, 511].iter().cycle() {
+            let end = (start + len).min(input.len());
+            let mut chunk = vec![f32::NAN; end - start];
+            assert!(unsafe {
+                dtln_rs_denoise_fixed(
+                    handle,
+                    input[start..end].as_ptr(),
+                    chunk.len(),
+                    chunk.as_mut_ptr(),
+                )
+            });
+            output.extend(chunk);
+            start = end;
+            if start == input.len() {
+                break;
+            }
+        }
+        let warm_up = DTLN_BLOCK_SHIFT - 1;
+        assert!(output[..warm_up].iter().all(|sample| *sample == 0.0));
+        assert_eq!(output[warm_up..], expected[..input.len() - warm_up]);
+
+        // The carried output comes out of the flush ahead of the tail.
+        let mut tail = vec![0.0; 4096];
+        let written = unsafe { dtln_rs_flush(handle, tail.as_mut_ptr(), tail.len()) };
+        assert_eq!(written, warm_up + constants::DTLN_LATENCY);
+        assert_eq!(tail[..warm_up], expected[input.len() - warm_up..]);
+        unsafe { dtln_rs_processor_destroy(handle) };
+        Ok(())
+    }
+}
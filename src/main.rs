@@ -5,7 +5,9 @@ use anyhow::Result;
 use dtln_rs::dtln_processor::{DtlnDeferredProcessor, DtlnProcessEngine};
 
 #[cfg(target_os = "macos")]
-use dtln_rs::dtln_utilities::{read_wav_to_pcm32, write_pcm32_to_wav};
+use dtln_rs::dtln_utilities::{
+    read_wav_markers, read_wav_to_pcm32, validate_wav_path, write_pcm32_to_wav_with_markers,
+};
 
 #[cfg(target_os = "macos")]
 const BLOCK_SIZE: usize = 1024;
@@ -24,16 +26,17 @@ fn main() -> Result<()> {
     }
     // Get input name as first argument
     let input_name = std::env::args().nth(1).unwrap();
-
-    check_is_wav(&input_name, true);
+    exit_on_error(validate_wav_path(&input_name, true));
 
     // Get output name as second argument
     let output_name = std::env::args().nth(2).unwrap();
-    check_is_wav(&output_name, false);
+    exit_on_error(validate_wav_path(&output_name, false));
 
     let mut samples = vec![];
     let mut output = vec![];
-    let _sample_rate = read_wav_to_pcm32(&input_name, &mut samples);
+    let _wav_info = exit_on_error(read_wav_to_pcm32(&input_name, &mut samples));
+    // Cue markers and loop points are carried over to the output unchanged.
+    let markers = read_wav_markers(&input_name)?;
     let mut processor = DtlnDeferredProcessor::new()?;
 
     // Simulate blocked input for every 16834 samples
@@ -57,28 +60,20 @@ fn main() -> Result<()> {
     processor.stop();
 
     // Write to wav
-    write_pcm32_to_wav(output, &output_name, EXPECTED_SAMPLE_RATE)?;
+    write_pcm32_to_wav_with_markers(output, &output_name, EXPECTED_SAMPLE_RATE, &markers)?;
     Ok(())
 }
 
+// Print a friendly message and exit with a nonzero status instead of panicking.
 #[cfg(target_os = "macos")]
-fn check_is_wav(name: &str, check_exists: bool) {
-    let path = std::path::Path::new(name);
-
-    if check_exists {
-        // Check that input_path exists and is a file
-        let file = std::fs::File::open(path).unwrap();
-        let file_metadata = file.metadata().unwrap();
-        if !file_metadata.is_file() {
-            panic!("File {} is not a file", name);
+fn exit_on_error<T>(result: std::result::Result<T, dtln_rs::dtln_error::DtlnError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
         }
     }
-
-    // Check that it is a wav file
-    let input_file_extension = path.extension().unwrap();
-    if input_file_extension != "wav" {
-        panic!("File {} is not a wav file", name);
-    }
 }
 
 // Set up an empty main function for targets we don't provide a CLI for.
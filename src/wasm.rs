@@ -1,8 +1,17 @@
 // Define webassembly interface to the library
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+
 use dtln_rs::dtln_utilities::{
-    dtln_create_global, dtln_denoise_global, dtln_destroy_global, dtln_get_audio_buffer_raw_ptr,
+    denoise_wav_bytes, dtln_block_shift_global, dtln_create_global, dtln_denoise_global,
+    dtln_destroy_global, dtln_get_audio_buffer_raw_ptr, dtln_process_block_global,
 };
 
+thread_local! {
+    // Message of the last failed call, read by dtln_last_error_wasm.
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
 // WASM Interface/exports.
 #[no_mangle]
 extern "C" fn dtln_create_wasm() -> u32 {
@@ -23,3 +32,108 @@ extern "C" fn dtln_denoise_wasm(id: u32) {
 extern "C" fn dtln_destroy_wasm(id: u32) {
     dtln_destroy_global(id);
 }
+
+// Samples dtln_process_block_wasm requires, 0 for an unknown engine.
+#[no_mangle]
+extern "C" fn dtln_block_shift_wasm(id: u32) -> usize {
+    dtln_block_shift_global(id).unwrap_or(0)
+}
+
+/**
+ * Denoises exactly one block of `len` samples from `input` into `output`, see
+ * dtln_process_block_global. Returns false if `len` isn't the block shift, with the
+ * reason available from dtln_last_error_wasm.
+ */
+#[no_mangle]
+extern "C" fn dtln_process_block_wasm(
+    id: u32,
+    input: *const f32,
+    output: *mut f32,
+    len: usize,
+) -> bool {
+    if input.is_null() || output.is_null() {
+        set_last_error("Null sample buffer");
+        return false;
+    }
+    if !input.is_aligned() || !output.is_aligned() {
+        set_last_error("Sample buffer not aligned to 4 bytes");
+        return false;
+    }
+    let input = unsafe { std::slice::from_raw_parts(input, len) };
+    let output = unsafe { std::slice::from_raw_parts_mut(output, len) };
+    match dtln_process_block_global(id, input, output) {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(&e.to_string());
+            false
+        }
+    }
+}
+
+fn set_last_error(message: &str) {
+    LAST_ERROR.with(|error| {
+        *error.borrow_mut() = CString::new(message).unwrap_or_default();
+    });
+}
+
+// Allocates a byte buffer for passing data in, freed with dtln_free_bytes_wasm.
+#[no_mangle]
+extern "C" fn dtln_alloc_bytes_wasm(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+#[no_mangle]
+extern "C" fn dtln_free_bytes_wasm(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        unsafe { drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len))) };
+    }
+}
+
+// Allocates a buffer of `len` samples, aligned for HEAPF32, freed with
+// dtln_free_samples_wasm.
+#[no_mangle]
+extern "C" fn dtln_alloc_samples_wasm(len: usize) -> *mut f32 {
+    Box::into_raw(vec![0f32; len].into_boxed_slice()) as *mut f32
+}
+
+#[no_mangle]
+extern "C" fn dtln_free_samples_wasm(ptr: *mut f32, len: usize) {
+    if !ptr.is_null() {
+        unsafe { drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len))) };
+    }
+}
+
+/**
+ * Denoises a WAV file held in `input`, see denoise_wav_bytes. Returns the denoised WAV,
+ * with its length written to `output_len`, to be freed with dtln_free_bytes_wasm. On
+ * failure returns null and the reason is available from dtln_last_error_wasm.
+ */
+#[no_mangle]
+extern "C" fn dtln_denoise_wav_bytes_wasm(
+    input: *const u8,
+    len: usize,
+    output_len: *mut usize,
+) -> *mut u8 {
+    let input = if input.is_null() {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(input, len) }
+    };
+    match denoise_wav_bytes(input) {
+        Ok(output) => {
+            let output = output.into_boxed_slice();
+            // Allocated with dtln_alloc_bytes_wasm, so not necessarily aligned.
+            unsafe { output_len.write_unaligned(output.len()) };
+            Box::into_raw(output) as *mut u8
+        }
+        Err(e) => {
+            set_last_error(&e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn dtln_last_error_wasm() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ptr())
+}